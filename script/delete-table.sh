@@ -1,6 +1,6 @@
 #!/bin/bash
 
-TABLE_NAME="orders"
+TABLE_NAME="${ORDERS_TABLE:-orders}"
 
 # Delete the table
 aws dynamodb delete-table --table-name ${TABLE_NAME}
@@ -0,0 +1,208 @@
+use super::{percentile, sorted, SizeBucket};
+use crate::order::{Order, Outcome, Venue};
+use crate::services::aws_dynamodb::{DynamoDbClient, OrderPages, OrderQuery};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_DAYS: u64 = 90;
+// further back than any order could have settled
+const MAX_DAYS: u64 = 10 * 365;
+// below this many orders in the trade's size bucket, the whole pair is used instead
+const MIN_BUCKET_SAMPLES: usize = 30;
+// sample sizes from which the percentiles settle down
+const MEDIUM_CONFIDENCE_SAMPLES: usize = 30;
+const HIGH_CONFIDENCE_SAMPLES: usize = 100;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct EstimateParams {
+    pub sell_token: String,
+    pub buy_token: String,
+    // in whole sell tokens, like min_sell_amount on /orders
+    pub sell_amount: f64,
+    // how far back history is used
+    pub days: Option<u64>,
+}
+
+// which history the estimate is drawn from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    // orders of the pair in the trade's size bucket
+    PairAndSize,
+    // every order of the pair, as too few were of a similar size or the size is unknown
+    Pair,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Confidence {
+    None,
+    Low,
+    Medium,
+    High,
+}
+
+impl Confidence {
+    fn of(sample_size: usize) -> Self {
+        match sample_size {
+            0 => Confidence::None,
+            n if n < MEDIUM_CONFIDENCE_SAMPLES => Confidence::Low,
+            n if n < HIGH_CONFIDENCE_SAMPLES => Confidence::Medium,
+            _ => Confidence::High,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Range {
+    p10: Option<f64>,
+    median: Option<f64>,
+    p90: Option<f64>,
+}
+
+impl Range {
+    fn of(sorted: &[f64]) -> Self {
+        Self {
+            p10: percentile(sorted, 10.0),
+            median: percentile(sorted, 50.0),
+            p90: percentile(sorted, 90.0),
+        }
+    }
+
+    fn scaled(&self, factor: f64) -> Self {
+        Self {
+            p10: self.p10.map(|v| v * factor),
+            median: self.median.map(|v| v * factor),
+            p90: self.p90.map(|v| v * factor),
+        }
+    }
+}
+
+// How CoW's execution compared with a venue's quote on the similar orders it quoted
+#[derive(Debug, Serialize)]
+pub struct Advantage {
+    sample_size: usize,
+    win_rate: Option<f64>,
+    // executed minus quoted buy amount, relative to the quote
+    advantage: Range,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Estimate {
+    scope: Scope,
+    // None when no order of the pair has a USD valuation to size the trade with
+    size_bucket: Option<SizeBucket>,
+    sell_usd: Option<f64>,
+    sample_size: usize,
+    confidence: Confidence,
+    // net surplus over the minimum buy amount
+    surplus_percentage: Range,
+    // surplus_percentage applied to the trade's USD value, so roughly in USD of the buy token
+    surplus_usd: Range,
+    zerox: Advantage,
+    univ3_swap: Advantage,
+}
+
+// What similar orders of the pair got, as a guess at what this trade will get
+pub async fn estimate_surplus(
+    client: &DynamoDbClient,
+    params: &EstimateParams,
+) -> eyre::Result<Estimate> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let days = params.days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS);
+    let query = OrderQuery {
+        sell_token: Some(params.sell_token.to_lowercase()),
+        buy_token: Some(params.buy_token.to_lowercase()),
+        from_timestamp: Some(now.saturating_sub(days * 24 * 60 * 60)),
+        ..Default::default()
+    };
+
+    // newest first, so the first valued order has the latest sell token price
+    let mut pages = OrderPages::new(client.clone(), query);
+    let mut orders = Vec::new();
+    let mut sell_token_usd_price = None;
+    while let Some(page) = pages.next_page().await? {
+        for order in page {
+            if sell_token_usd_price.is_none() && order.has_usd_valuation() {
+                sell_token_usd_price = Some(*order.sell_token_usd_price());
+            }
+            orders.push(Sample::new(&order));
+        }
+    }
+
+    let sell_usd = sell_token_usd_price.map(|price| params.sell_amount * price);
+    let size_bucket = sell_usd.map(SizeBucket::from_usd);
+    let in_bucket = orders
+        .iter()
+        .filter(|sample| sample.size_bucket.is_some() && sample.size_bucket == size_bucket)
+        .collect::<Vec<_>>();
+    let (scope, samples) = if in_bucket.len() >= MIN_BUCKET_SAMPLES {
+        (Scope::PairAndSize, in_bucket)
+    } else {
+        (Scope::Pair, orders.iter().collect())
+    };
+
+    let surplus_percentage = Range::of(&sorted(samples.iter().map(|s| s.surplus_percentage)));
+    let surplus_usd = match sell_usd {
+        Some(sell_usd) => surplus_percentage.scaled(sell_usd),
+        None => Range::of(&[]),
+    };
+
+    Ok(Estimate {
+        scope,
+        size_bucket,
+        sell_usd,
+        sample_size: samples.len(),
+        confidence: Confidence::of(samples.len()),
+        surplus_percentage,
+        surplus_usd,
+        zerox: advantage(&samples, Venue::Zerox),
+        univ3_swap: advantage(&samples, Venue::Univ3Swap),
+    })
+}
+
+fn advantage(samples: &[&Sample], venue: Venue) -> Advantage {
+    let index = Venue::ALL.iter().position(|v| *v == venue).unwrap();
+    let quoted = samples
+        .iter()
+        .filter_map(|sample| sample.venues[index])
+        .collect::<Vec<_>>();
+    let won = quoted
+        .iter()
+        .filter(|(outcome, _)| *outcome == Outcome::Won)
+        .count();
+
+    Advantage {
+        sample_size: quoted.len(),
+        win_rate: (!quoted.is_empty()).then(|| won as f64 / quoted.len() as f64),
+        advantage: Range::of(&sorted(quoted.iter().map(|(_, advantage)| *advantage))),
+    }
+}
+
+// the part of an order an estimate needs
+struct Sample {
+    surplus_percentage: f64,
+    size_bucket: Option<SizeBucket>,
+    // per venue in Venue::ALL order, the outcome and the advantage over the quote
+    venues: [Option<(Outcome, f64)>; 3],
+}
+
+impl Sample {
+    fn new(order: &Order) -> Self {
+        Self {
+            surplus_percentage: *order.surplus_percentage(),
+            size_bucket: SizeBucket::of(order),
+            venues: Venue::ALL.map(|venue| {
+                venue.outcome(order).map(|outcome| {
+                    (
+                        outcome,
+                        venue.executed_minus_quote(order) / venue.quote_buy(order),
+                    )
+                })
+            }),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+pub mod estimate;
+pub mod stats;
+pub mod timeseries;
+pub mod wallet;
+
+use crate::order::Order;
+use crate::rpc::block_timestamp;
+use crate::services::aws_dynamodb::{DynamoDbClient, OrderQuery};
+use chrono::DateTime;
+use ethers::providers::Middleware;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// how long a computed report is served again, and what "now" is rounded down to in default
+// time ranges so that requests within it ask the same thing
+pub const CACHE_SECS: u64 = 60;
+// reports kept at once, past it new ones are computed but not cached until older ones expire
+const MAX_CACHED_REPORTS: usize = 256;
+
+// the time range a report covers when it gives no from_timestamp, and the most it may cover
+const DEFAULT_DAYS: u64 = 7;
+const MAX_DAYS: u64 = 90;
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+pub fn cache_now() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    now - now % CACHE_SECS
+}
+
+// Bounds the order filters in time, to the DEFAULT_DAYS up to to_timestamp (or now) when they
+// have no from_timestamp. Returns the seconds covered, Err when they span more than MAX_DAYS.
+pub fn bound_in_time(query: &mut OrderQuery) -> eyre::Result<u64> {
+    let to = query.to_timestamp.unwrap_or_else(cache_now);
+    let from = *query
+        .from_timestamp
+        .get_or_insert(to.saturating_sub(DEFAULT_DAYS * DAY_SECS));
+    let span = to.saturating_sub(from);
+    if span > MAX_DAYS * DAY_SECS {
+        return Err(eyre::eyre!("Reports cover at most {} days", MAX_DAYS));
+    }
+    Ok(span)
+}
+
+// Reports by the query they were computed for, so repeated requests and dashboard views don't
+// page through every order in range again
+pub struct ReportCache<T> {
+    reports: Mutex<HashMap<String, (Instant, T)>>,
+}
+
+impl<T> Default for ReportCache<T> {
+    fn default() -> Self {
+        Self {
+            reports: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone> ReportCache<T> {
+    pub async fn get_or_compute<Fut>(&self, key: String, compute: Fut) -> eyre::Result<T>
+    where
+        Fut: Future<Output = eyre::Result<T>>,
+    {
+        let ttl = Duration::from_secs(CACHE_SECS);
+        if let Some((at, report)) = self.reports.lock().unwrap().get(&key) {
+            if at.elapsed() < ttl {
+                return Ok(report.clone());
+            }
+        }
+
+        let report = compute.await?;
+        let mut reports = self.reports.lock().unwrap();
+        reports.retain(|_, (at, _)| at.elapsed() < ttl);
+        if reports.len() < MAX_CACHED_REPORTS {
+            reports.insert(key, (Instant::now(), report.clone()));
+        }
+        Ok(report)
+    }
+}
+
+// nearest-rank percentile, `sorted` has to be in ascending order
+pub fn percentile(sorted: &[f64], p: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+pub fn sorted(values: impl IntoIterator<Item = f64>) -> Vec<f64> {
+    let mut values = values
+        .into_iter()
+        .filter(|v| v.is_finite())
+        .collect::<Vec<_>>();
+    values.sort_by(f64::total_cmp);
+    values
+}
+
+// the UTC day an order settled on, as YYYY-MM-DD, None when its timestamp is unknown
+pub fn day(order: &Order) -> Option<String> {
+    match *order.timestamp() {
+        0 => None,
+        timestamp => DateTime::from_timestamp(timestamp as i64, 0)
+            .map(|time| time.format("%Y-%m-%d").to_string()),
+    }
+}
+
+// Unix seconds an order settled at. Orders stored without it, as their block couldn't be read
+// at the time, have it read from their block now. None when it still can't be.
+pub async fn settled_at<M: Middleware>(provider: &M, order: &Order) -> Option<u64> {
+    match *order.timestamp() {
+        0 => block_timestamp(provider, *order.block_number()).await.ok(),
+        timestamp => Some(timestamp),
+    }
+}
+
+// Stores a timestamp read by `settled_at`, so its block is only read once. Failures are only
+// logged, the block is read again next time.
+pub async fn store_settled_at(client: &DynamoDbClient, order: &Order, timestamp: u64) {
+    if *order.timestamp() != 0 {
+        return;
+    }
+    if let Err(e) = client.set_timestamp(order.uid(), timestamp).await {
+        eprintln!(
+            "Failed to store the timestamp of order {}: {}",
+            order.uid(),
+            e
+        );
+    }
+}
+
+pub fn pair(order: &Order) -> String {
+    format!("{}/{}", order.sell_token(), order.buy_token())
+}
+
+// trade size by the USD value of the sell amount
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum SizeBucket {
+    #[serde(rename = "<1k")]
+    Under1k,
+    #[serde(rename = "1k-10k")]
+    Under10k,
+    #[serde(rename = "10k-100k")]
+    Under100k,
+    #[serde(rename = "100k-1m")]
+    Under1m,
+    #[serde(rename = ">=1m")]
+    Over1m,
+}
+
+impl SizeBucket {
+    pub const ALL: [SizeBucket; 5] = [
+        SizeBucket::Under1k,
+        SizeBucket::Under10k,
+        SizeBucket::Under100k,
+        SizeBucket::Under1m,
+        SizeBucket::Over1m,
+    ];
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            SizeBucket::Under1k => "<1k",
+            SizeBucket::Under10k => "1k-10k",
+            SizeBucket::Under100k => "10k-100k",
+            SizeBucket::Under1m => "100k-1m",
+            SizeBucket::Over1m => ">=1m",
+        }
+    }
+
+    pub fn from_usd(usd: f64) -> Self {
+        match usd {
+            usd if usd < 1_000.0 => SizeBucket::Under1k,
+            usd if usd < 10_000.0 => SizeBucket::Under10k,
+            usd if usd < 100_000.0 => SizeBucket::Under100k,
+            usd if usd < 1_000_000.0 => SizeBucket::Under1m,
+            _ => SizeBucket::Over1m,
+        }
+    }
+
+    // None for orders without USD prices
+    pub fn of(order: &Order) -> Option<Self> {
+        order
+            .has_usd_valuation()
+            .then(|| SizeBucket::from_usd(*order.sell_usd()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{JsonRpcError, MockError, MockResponse, Provider};
+    use ethers::types::{Block, H256};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn order(block_number: u64, timestamp: u64) -> Order {
+        let mut order = serde_json::to_value(Order::default()).unwrap();
+        order["block_number"] = block_number.into();
+        order["timestamp"] = timestamp.into();
+        serde_json::from_value(order).unwrap()
+    }
+
+    #[tokio::test]
+    async fn settled_at_is_the_stored_timestamp() {
+        let (provider, mock) = Provider::mocked();
+        assert_eq!(
+            settled_at(&provider, &order(20_000_000, 1_717_000_000)).await,
+            Some(1_717_000_000)
+        );
+        assert!(matches!(
+            mock.assert_request("eth_getBlockByNumber", ()),
+            Err(MockError::EmptyRequests)
+        ));
+    }
+
+    #[tokio::test]
+    async fn settled_at_reads_unknown_timestamps_from_the_block() {
+        let (provider, mock) = Provider::mocked();
+        let block = Block::<H256> {
+            timestamp: 1_717_000_123.into(),
+            ..Default::default()
+        };
+        mock.push::<Block<H256>, _>(block).unwrap();
+
+        assert_eq!(
+            settled_at(&provider, &order(20_000_000, 0)).await,
+            Some(1_717_000_123)
+        );
+        mock.assert_request("eth_getBlockByNumber", ("0x1312d00", false))
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn settled_at_is_unknown_when_the_block_is() {
+        let (provider, mock) = Provider::mocked();
+        mock.push::<Option<Block<H256>>, _>(None).unwrap();
+        assert_eq!(settled_at(&provider, &order(20_000_000, 0)).await, None);
+
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: -32000,
+            message: "header not found".into(),
+            data: None,
+        }));
+        assert_eq!(settled_at(&provider, &order(20_000_000, 0)).await, None);
+    }
+
+    #[test]
+    fn reports_are_bounded_in_time() {
+        let mut query = OrderQuery::default();
+        assert_eq!(bound_in_time(&mut query).unwrap(), DEFAULT_DAYS * DAY_SECS);
+        assert_eq!(
+            cache_now() - query.from_timestamp.unwrap(),
+            DEFAULT_DAYS * DAY_SECS
+        );
+
+        let mut query = OrderQuery {
+            to_timestamp: Some(100 * DAY_SECS),
+            ..Default::default()
+        };
+        bound_in_time(&mut query).unwrap();
+        assert_eq!(query.from_timestamp, Some(93 * DAY_SECS));
+
+        let mut query = OrderQuery {
+            from_timestamp: Some(0),
+            to_timestamp: Some(91 * DAY_SECS),
+            ..Default::default()
+        };
+        assert!(bound_in_time(&mut query).is_err());
+    }
+
+    #[test]
+    fn percentile_is_nearest_rank() {
+        let values = sorted([5.0, 1.0, 4.0, 2.0, 3.0]);
+        assert_eq!(percentile(&values, 0.0), Some(1.0));
+        assert_eq!(percentile(&values, 20.0), Some(1.0));
+        assert_eq!(percentile(&values, 21.0), Some(2.0));
+        assert_eq!(percentile(&values, 50.0), Some(3.0));
+        assert_eq!(percentile(&values, 100.0), Some(5.0));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+
+    #[test]
+    fn sorted_drops_non_finite_values() {
+        let values = sorted([f64::NAN, 2.0, f64::INFINITY, -1.0, f64::NEG_INFINITY]);
+        assert_eq!(values, [-1.0, 2.0]);
+    }
+
+    #[test]
+    fn size_buckets_split_at_powers_of_ten() {
+        assert_eq!(SizeBucket::from_usd(999.99), SizeBucket::Under1k);
+        assert_eq!(SizeBucket::from_usd(1_000.0), SizeBucket::Under10k);
+        assert_eq!(SizeBucket::from_usd(99_999.0), SizeBucket::Under100k);
+        assert_eq!(SizeBucket::from_usd(1_000_000.0), SizeBucket::Over1m);
+    }
+
+    #[tokio::test]
+    async fn report_cache_serves_a_query_once() {
+        let cache = ReportCache::default();
+        let computed = AtomicUsize::new(0);
+        let compute = |value: u32| {
+            let computed = &computed;
+            async move {
+                computed.fetch_add(1, Ordering::Relaxed);
+                eyre::Ok(value)
+            }
+        };
+
+        assert_eq!(
+            cache.get_or_compute("a".into(), compute(1)).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            cache.get_or_compute("a".into(), compute(2)).await.unwrap(),
+            1
+        );
+        assert_eq!(
+            cache.get_or_compute("b".into(), compute(3)).await.unwrap(),
+            3
+        );
+        assert_eq!(computed.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn report_cache_keeps_no_errors() {
+        let cache = ReportCache::default();
+        assert!(cache
+            .get_or_compute("a".into(), async { Err(eyre::eyre!("down")) })
+            .await
+            .is_err());
+        assert_eq!(
+            cache
+                .get_or_compute("a".into(), async { eyre::Ok(1) })
+                .await
+                .unwrap(),
+            1
+        );
+    }
+}
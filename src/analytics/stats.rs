@@ -0,0 +1,347 @@
+use super::{bound_in_time, day, pair, percentile, sorted, ReportCache, SizeBucket};
+use crate::order::{Order, Outcome, Venue};
+use crate::services::aws_dynamodb::{DynamoDbClient, OrderPages, OrderQuery};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::LazyLock;
+
+const DEFAULT_BINS: usize = 20;
+const MAX_BINS: usize = 200;
+
+static REPORTS: LazyLock<ReportCache<StatsReport>> = LazyLock::new(Default::default);
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Day,
+    Pair,
+    Size,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StatsParams {
+    pub group_by: Option<GroupBy>,
+    // histogram buckets
+    pub bins: Option<usize>,
+}
+
+impl StatsParams {
+    // bounds the order filters in time, see `bound_in_time`
+    pub fn query(&self, mut query: OrderQuery) -> eyre::Result<OrderQuery> {
+        bound_in_time(&mut query)?;
+        Ok(query)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Getters)]
+#[getset(get = "pub")]
+pub struct VenueStats {
+    // orders the venue managed to quote, the rest don't count either way
+    quoted: usize,
+    won: usize,
+    lost: usize,
+    tied: usize,
+    win_rate: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Getters)]
+#[getset(get = "pub")]
+pub struct Distribution {
+    min: Option<f64>,
+    p10: Option<f64>,
+    p25: Option<f64>,
+    median: Option<f64>,
+    p75: Option<f64>,
+    p90: Option<f64>,
+    max: Option<f64>,
+    mean: Option<f64>,
+}
+
+impl Distribution {
+    pub fn of(sorted: &[f64]) -> Self {
+        Self {
+            min: sorted.first().copied(),
+            p10: percentile(sorted, 10.0),
+            p25: percentile(sorted, 25.0),
+            median: percentile(sorted, 50.0),
+            p75: percentile(sorted, 75.0),
+            p90: percentile(sorted, 90.0),
+            max: sorted.last().copied(),
+            mean: (!sorted.is_empty()).then(|| sorted.iter().sum::<f64>() / sorted.len() as f64),
+        }
+    }
+}
+
+// `lower` is inclusive and `upper` exclusive, the outer buckets are open ended
+#[derive(Debug, Clone, Serialize, Getters)]
+#[getset(get = "pub")]
+pub struct HistogramBucket {
+    lower: Option<f64>,
+    upper: Option<f64>,
+    count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Getters)]
+#[getset(get = "pub")]
+pub struct Stats {
+    order_count: usize,
+    venues: BTreeMap<&'static str, VenueStats>,
+    surplus_percentage: Distribution,
+    surplus_percentage_histogram: Vec<HistogramBucket>,
+    total_net_surplus_usd: f64,
+    // orders without USD prices aren't part of total_net_surplus_usd
+    valued_order_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Getters)]
+#[getset(get = "pub")]
+pub struct Group {
+    key: String,
+    stats: Stats,
+}
+
+#[derive(Debug, Clone, Serialize, Getters)]
+#[getset(get = "pub")]
+pub struct StatsReport {
+    overall: Stats,
+    // empty unless grouped
+    groups: Vec<Group>,
+}
+
+// the part of an order the statistics need, so a long range isn't held as full orders
+struct Sample {
+    surplus_percentage: f64,
+    net_surplus_usd: Option<f64>,
+    outcomes: [Option<Outcome>; 3],
+}
+
+impl Sample {
+    fn new(order: &Order) -> Self {
+        Self {
+            surplus_percentage: *order.surplus_percentage(),
+            net_surplus_usd: order
+                .has_usd_valuation()
+                .then_some(*order.net_surplus_usd()),
+            outcomes: Venue::ALL.map(|venue| venue.outcome(order)),
+        }
+    }
+}
+
+// Statistics of orders fed in one at a time, for callers that page through orders themselves
+#[derive(Default)]
+pub struct StatsAccumulator {
+    samples: Vec<Sample>,
+}
+
+impl StatsAccumulator {
+    pub fn add(&mut self, order: &Order) {
+        self.samples.push(Sample::new(order));
+    }
+
+    pub fn finish(&self) -> Stats {
+        let edges = bucket_edges(
+            &sorted(self.samples.iter().map(|s| s.surplus_percentage)),
+            DEFAULT_BINS,
+        );
+        stats(self.samples.iter(), &edges)
+    }
+}
+
+// served from REPORTS when the same query was computed within CACHE_SECS
+pub async fn compute_stats(
+    client: &DynamoDbClient,
+    query: OrderQuery,
+    params: &StatsParams,
+) -> eyre::Result<StatsReport> {
+    let key = format!("{:?} {:?}", query, params);
+    REPORTS
+        .get_or_compute(key, compute_stats_uncached(client, query, params))
+        .await
+}
+
+async fn compute_stats_uncached(
+    client: &DynamoDbClient,
+    query: OrderQuery,
+    params: &StatsParams,
+) -> eyre::Result<StatsReport> {
+    let mut pages = OrderPages::new(client.clone(), query);
+    let mut samples = Vec::new();
+    let mut groups: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+
+    while let Some(orders) = pages.next_page().await? {
+        for order in orders {
+            if let Some(group_by) = params.group_by {
+                groups
+                    .entry(group_key(group_by, &order))
+                    .or_default()
+                    .push(samples.len());
+            }
+            samples.push(Sample::new(&order));
+        }
+    }
+
+    // every group shares the overall bucket edges, so their histograms line up
+    let edges = bucket_edges(
+        &sorted(samples.iter().map(|s| s.surplus_percentage)),
+        params.bins.unwrap_or(DEFAULT_BINS).clamp(1, MAX_BINS),
+    );
+
+    let mut groups = groups
+        .into_iter()
+        .map(|(key, indices)| Group {
+            stats: stats(indices.iter().map(|&i| &samples[i]), &edges),
+            key,
+        })
+        .collect::<Vec<_>>();
+    if params.group_by == Some(GroupBy::Size) {
+        groups.sort_by_key(|group| {
+            SizeBucket::ALL
+                .iter()
+                .position(|bucket| bucket.as_str() == group.key)
+                .unwrap_or(SizeBucket::ALL.len())
+        });
+    }
+
+    Ok(StatsReport {
+        overall: stats(samples.iter(), &edges),
+        groups,
+    })
+}
+
+fn group_key(group_by: GroupBy, order: &Order) -> String {
+    let key = match group_by {
+        GroupBy::Day => day(order),
+        GroupBy::Pair => Some(pair(order)),
+        GroupBy::Size => SizeBucket::of(order).map(|bucket| bucket.as_str().to_string()),
+    };
+    key.unwrap_or_else(|| "unknown".to_string())
+}
+
+fn stats<'a>(samples: impl Iterator<Item = &'a Sample> + Clone, edges: &[f64]) -> Stats {
+    let mut venues = Venue::ALL
+        .iter()
+        .map(|venue| (venue.as_str(), VenueStats::default()))
+        .collect::<BTreeMap<_, _>>();
+    let mut total_net_surplus_usd = 0.0;
+    let mut valued_order_count = 0;
+
+    for sample in samples.clone() {
+        for (venue, outcome) in Venue::ALL.iter().zip(sample.outcomes) {
+            let Some(outcome) = outcome else {
+                continue;
+            };
+            let venue_stats = venues.get_mut(venue.as_str()).unwrap();
+            venue_stats.quoted += 1;
+            match outcome {
+                Outcome::Won => venue_stats.won += 1,
+                Outcome::Lost => venue_stats.lost += 1,
+                Outcome::Tied => venue_stats.tied += 1,
+            }
+        }
+        if let Some(usd) = sample.net_surplus_usd {
+            total_net_surplus_usd += usd;
+            valued_order_count += 1;
+        }
+    }
+    for venue_stats in venues.values_mut() {
+        venue_stats.win_rate =
+            (venue_stats.quoted > 0).then(|| venue_stats.won as f64 / venue_stats.quoted as f64);
+    }
+
+    let values = sorted(samples.map(|s| s.surplus_percentage));
+    Stats {
+        order_count: values.len(),
+        venues,
+        surplus_percentage_histogram: histogram(&values, edges),
+        surplus_percentage: Distribution::of(&values),
+        total_net_surplus_usd,
+        valued_order_count,
+    }
+}
+
+// Evenly spaced between the 1st and 99th percentile, so a few extreme orders don't squash
+// every other one into a single bucket
+fn bucket_edges(sorted: &[f64], bins: usize) -> Vec<f64> {
+    let (Some(low), Some(high)) = (percentile(sorted, 1.0), percentile(sorted, 99.0)) else {
+        return Vec::new();
+    };
+    if low == high {
+        return vec![low];
+    }
+
+    let width = (high - low) / bins as f64;
+    (0..=bins).map(|i| low + width * i as f64).collect()
+}
+
+pub fn histogram(sorted: &[f64], edges: &[f64]) -> Vec<HistogramBucket> {
+    if edges.is_empty() {
+        return Vec::new();
+    }
+
+    // open bucket below the first edge, one per pair of edges, open bucket from the last edge
+    let mut counts = vec![0; edges.len() + 1];
+    for value in sorted {
+        counts[edges.partition_point(|edge| edge <= value)] += 1;
+    }
+
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| HistogramBucket {
+            lower: i.checked_sub(1).map(|i| edges[i]),
+            upper: edges.get(i).copied(),
+            count,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_edges_span_the_1st_to_99th_percentile() {
+        let values = sorted((0..=100).map(f64::from));
+        assert_eq!(bucket_edges(&values, 4), [1.0, 25.5, 50.0, 74.5, 99.0]);
+        assert_eq!(bucket_edges(&[3.0, 3.0], 4), [3.0]);
+        assert!(bucket_edges(&[], 4).is_empty());
+    }
+
+    #[test]
+    fn histogram_has_open_buckets_on_either_side() {
+        let buckets = histogram(&[-5.0, 0.0, 0.5, 1.0, 1.5, 2.0, 9.0], &[0.0, 1.0, 2.0]);
+        let buckets = buckets
+            .iter()
+            .map(|b| (b.lower, b.upper, b.count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            buckets,
+            [
+                (None, Some(0.0), 1),
+                (Some(0.0), Some(1.0), 2),
+                (Some(1.0), Some(2.0), 2),
+                (Some(2.0), None, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn histogram_counts_every_value() {
+        let values = sorted((0..1000).map(|i| (i as f64 * 0.37).sin()));
+        let edges = bucket_edges(&values, 20);
+        let total: usize = histogram(&values, &edges).iter().map(|b| b.count).sum();
+        assert_eq!(total, values.len());
+    }
+
+    #[test]
+    fn distribution_of_nothing_is_empty() {
+        let distribution = Distribution::of(&[]);
+        assert_eq!(distribution.median, None);
+        assert_eq!(distribution.mean, None);
+
+        let distribution = Distribution::of(&[1.0, 2.0, 6.0]);
+        assert_eq!(distribution.median, Some(2.0));
+        assert_eq!(distribution.mean, Some(3.0));
+    }
+}
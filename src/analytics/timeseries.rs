@@ -0,0 +1,254 @@
+use super::{bound_in_time, settled_at, store_settled_at};
+use crate::config::Config;
+use crate::order::{Outcome, Venue};
+use crate::rpc::Rpc;
+use crate::services::aws_dynamodb::{DynamoDbClient, OrderPages, OrderQuery};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// Empty buckets are filled in, so a range gives a point per interval whether or not it has
+// orders. A month of hours.
+const MAX_POINTS: u64 = 31 * 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    // net surplus of the orders with a USD valuation
+    SurplusUsd,
+    // share of the orders 0x quoted where CoW's execution beat the quote
+    WinRateVsZerox,
+    // USD value sold, of the orders with a USD valuation
+    Volume,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Interval {
+    #[serde(rename = "1h")]
+    Hour,
+    #[serde(rename = "1d")]
+    Day,
+}
+
+impl Interval {
+    fn secs(&self) -> u64 {
+        match self {
+            Interval::Hour => 60 * 60,
+            Interval::Day => 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimeseriesParams {
+    pub metric: Metric,
+    pub interval: Interval,
+    // "<sell_token>/<buy_token>", as /stats groups pairs
+    pub pair: Option<String>,
+}
+
+impl TimeseriesParams {
+    // Narrows the order filters to `pair` and bounds them in time like /stats. Err when the pair
+    // isn't two tokens, or the range holds more than MAX_POINTS intervals.
+    pub fn query(&self, mut query: OrderQuery) -> eyre::Result<OrderQuery> {
+        let span = bound_in_time(&mut query)?;
+        if span / self.interval.secs() > MAX_POINTS {
+            return Err(eyre::eyre!(
+                "A timeseries has at most {} points, use a wider interval",
+                MAX_POINTS
+            ));
+        }
+
+        if let Some(pair) = &self.pair {
+            let (sell_token, buy_token) = pair
+                .split_once('/')
+                .filter(|(sell, buy)| !sell.is_empty() && !buy.is_empty())
+                .ok_or_else(|| eyre::eyre!("pair must be <sell_token>/<buy_token>"))?;
+            query.sell_token = Some(sell_token.to_lowercase());
+            query.buy_token = Some(buy_token.to_lowercase());
+        }
+        Ok(query)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Point {
+    // unix seconds the bucket starts at, in UTC
+    start: u64,
+    // None when no order in the bucket counts towards the metric
+    value: Option<f64>,
+    order_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Timeseries {
+    metric: Metric,
+    interval: Interval,
+    points: Vec<Point>,
+}
+
+#[derive(Default)]
+struct Bucket {
+    order_count: usize,
+    // orders the metric could be computed for
+    counted: usize,
+    sum: f64,
+}
+
+impl Bucket {
+    fn value(&self, metric: Metric) -> Option<f64> {
+        if self.counted == 0 {
+            return None;
+        }
+        Some(match metric {
+            Metric::SurplusUsd | Metric::Volume => self.sum,
+            Metric::WinRateVsZerox => self.sum / self.counted as f64,
+        })
+    }
+}
+
+// Buckets the matching orders by when they settled. Orders stored without a timestamp only
+// match a range starting at 0, and each costs a read of its block from `rpc.quorum` endpoint
+// hosts, so it is stored once read.
+pub async fn compute_timeseries(
+    config: &Config,
+    client: &DynamoDbClient,
+    query: OrderQuery,
+    params: &TimeseriesParams,
+) -> eyre::Result<Timeseries> {
+    let provider = Rpc::new(config).quorum_provider();
+    let mut pages = OrderPages::new(client.clone(), query);
+    let interval = params.interval.secs();
+    let mut buckets: BTreeMap<u64, Bucket> = BTreeMap::new();
+
+    while let Some(orders) = pages.next_page().await? {
+        for order in orders {
+            // stored without a timestamp, and its block can't be read either
+            let Some(settled_at) = settled_at(&provider, &order).await else {
+                continue;
+            };
+            store_settled_at(client, &order, settled_at).await;
+
+            let bucket = buckets.entry(settled_at / interval * interval).or_default();
+            bucket.order_count += 1;
+
+            let value = match params.metric {
+                Metric::SurplusUsd => order
+                    .has_usd_valuation()
+                    .then_some(*order.net_surplus_usd()),
+                Metric::Volume => order.has_usd_valuation().then_some(*order.sell_usd()),
+                Metric::WinRateVsZerox => Venue::Zerox
+                    .outcome(&order)
+                    .map(|outcome| (outcome == Outcome::Won) as u8 as f64),
+            };
+            if let Some(value) = value {
+                bucket.counted += 1;
+                bucket.sum += value;
+            }
+        }
+    }
+
+    Ok(Timeseries {
+        metric: params.metric,
+        interval: params.interval,
+        points: points(buckets, interval, params.metric),
+    })
+}
+
+// Buckets between the first and last order are filled in, so a chart's gaps show as empty
+// points rather than missing ones
+fn points(mut buckets: BTreeMap<u64, Bucket>, interval: u64, metric: Metric) -> Vec<Point> {
+    let mut points = Vec::new();
+    if let (Some(&first), Some(&last)) = (buckets.keys().next(), buckets.keys().next_back()) {
+        for start in (first..=last).step_by(interval as usize) {
+            let bucket = buckets.remove(&start).unwrap_or_default();
+            points.push(Point {
+                start,
+                value: bucket.value(metric),
+                order_count: bucket.order_count,
+            });
+        }
+    }
+    points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bucket(order_count: usize, counted: usize, sum: f64) -> Bucket {
+        Bucket {
+            order_count,
+            counted,
+            sum,
+        }
+    }
+
+    fn params(interval: Interval) -> TimeseriesParams {
+        TimeseriesParams {
+            metric: Metric::Volume,
+            interval,
+            pair: None,
+        }
+    }
+
+    #[test]
+    fn bucket_value_sums_usd_and_averages_the_win_rate() {
+        let bucket = bucket(5, 4, 3.0);
+        assert_eq!(bucket.value(Metric::SurplusUsd), Some(3.0));
+        assert_eq!(bucket.value(Metric::Volume), Some(3.0));
+        assert_eq!(bucket.value(Metric::WinRateVsZerox), Some(0.75));
+    }
+
+    #[test]
+    fn bucket_without_counted_orders_has_no_value() {
+        let bucket = bucket(3, 0, 0.0);
+        for metric in [Metric::SurplusUsd, Metric::Volume, Metric::WinRateVsZerox] {
+            assert_eq!(bucket.value(metric), None);
+        }
+    }
+
+    #[test]
+    fn gaps_between_the_first_and_last_bucket_are_filled() {
+        let hour = Interval::Hour.secs();
+        let buckets = BTreeMap::from([(hour, bucket(2, 2, 10.0)), (4 * hour, bucket(1, 0, 0.0))]);
+
+        let points = points(buckets, hour, Metric::Volume);
+        let points = points
+            .iter()
+            .map(|point| (point.start, point.value, point.order_count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            points,
+            [
+                (hour, Some(10.0), 2),
+                (2 * hour, None, 0),
+                (3 * hour, None, 0),
+                (4 * hour, None, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn no_buckets_have_no_points() {
+        assert!(points(BTreeMap::new(), 60, Metric::Volume).is_empty());
+    }
+
+    #[test]
+    fn ranges_are_bounded_by_their_points() {
+        let day = Interval::Day.secs();
+        let range = |days| OrderQuery {
+            from_timestamp: Some(0),
+            to_timestamp: Some(days * day),
+            ..Default::default()
+        };
+
+        assert!(params(Interval::Hour).query(range(31)).is_ok());
+        assert!(params(Interval::Hour).query(range(32)).is_err());
+        assert!(params(Interval::Day).query(range(90)).is_ok());
+        // the same maximum as /stats
+        assert!(params(Interval::Day).query(range(91)).is_err());
+
+        let query = params(Interval::Day).query(OrderQuery::default()).unwrap();
+        assert!(query.from_timestamp.is_some());
+    }
+}
@@ -0,0 +1,117 @@
+use super::stats::{Stats, StatsAccumulator};
+use crate::order::Order;
+use crate::services::aws_dynamodb::{
+    fetch_orders_from_database, DynamoDbClient, OrderPages, OrderQuery, Page, PageRequest,
+};
+use crate::tokens::{OrderWithTokens, TokenInfo, TokenRegistry};
+use futures::future;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// What a wallet sold and bought of one token, in whole tokens. Surplus is always in the buy
+// token, so only tokens the wallet bought have any.
+#[derive(Debug, Default, Serialize)]
+pub struct TokenTotals {
+    token: String,
+    token_info: Option<TokenInfo>,
+    sold: f64,
+    bought: f64,
+    net_surplus: f64,
+    // of the orders with a USD valuation
+    net_surplus_usd: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WalletReport {
+    owner: String,
+    // over every analysed trade of the wallet. Only on the first page, the next ones would scan
+    // the whole history again for the same numbers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<Stats>,
+    // by USD surplus, highest first, only on the first page as well
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tokens: Option<Vec<TokenTotals>>,
+    // newest first
+    trades: Page<OrderWithTokens>,
+}
+
+pub async fn compute_wallet_report(
+    client: &DynamoDbClient,
+    tokens: &TokenRegistry,
+    owner: &str,
+    page: &PageRequest,
+) -> eyre::Result<WalletReport> {
+    let owner = owner.to_lowercase();
+    // OwnerIndex holds the wallet's orders in settlement order
+    let query = OrderQuery {
+        owner: Some(owner.clone()),
+        ..Default::default()
+    };
+
+    let (summary, totals) = match page.cursor {
+        Some(_) => (None, None),
+        None => {
+            let (summary, totals) = summarize(client, tokens, &query).await?;
+            (Some(summary), Some(totals))
+        }
+    };
+
+    let trades = tokens
+        .page_with_tokens(fetch_orders_from_database(client, &query, page).await?)
+        .await;
+
+    Ok(WalletReport {
+        owner,
+        summary,
+        tokens: totals,
+        trades,
+    })
+}
+
+async fn summarize(
+    client: &DynamoDbClient,
+    tokens: &TokenRegistry,
+    query: &OrderQuery,
+) -> eyre::Result<(Stats, Vec<TokenTotals>)> {
+    let mut pages = OrderPages::new(client.clone(), query.clone());
+    let mut summary = StatsAccumulator::default();
+    let mut totals: BTreeMap<String, TokenTotals> = BTreeMap::new();
+    while let Some(orders) = pages.next_page().await? {
+        for order in orders {
+            summary.add(&order);
+            add_to_totals(&mut totals, &order);
+        }
+    }
+
+    let mut totals = future::join_all(totals.into_values().map(|mut totals| async move {
+        totals.token_info = tokens.resolve(&totals.token).await.ok();
+        totals
+    }))
+    .await;
+    totals.sort_by(|a, b| b.net_surplus_usd.total_cmp(&a.net_surplus_usd));
+
+    Ok((summary.finish(), totals))
+}
+
+fn add_to_totals(totals: &mut BTreeMap<String, TokenTotals>, order: &Order) {
+    fn entry<'a>(
+        totals: &'a mut BTreeMap<String, TokenTotals>,
+        token: &str,
+    ) -> &'a mut TokenTotals {
+        totals
+            .entry(token.to_string())
+            .or_insert_with(|| TokenTotals {
+                token: token.to_string(),
+                ..Default::default()
+            })
+    }
+
+    entry(totals, order.sell_token()).sold += order.executed_sell();
+
+    let bought = entry(totals, order.buy_token());
+    bought.bought += order.executed_buy();
+    bought.net_surplus += order.net_surplus();
+    if order.has_usd_valuation() {
+        bought.net_surplus_usd += order.net_surplus_usd();
+    }
+}
@@ -0,0 +1,133 @@
+use crate::config::Config;
+use crate::order::Order;
+use crate::services::{
+    aws_dynamodb::{DynamoDbClient, OrderQuery, PageRequest},
+    aws_s3::ArchiveBucket,
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// orders per archive file, one DynamoDB page
+const ARCHIVE_FILE_SIZE: i32 = 1000;
+
+// Moves orders past their retention into gzipped JSON Lines files under ARCHIVE_PREFIX, deleting
+// each order from the table once the file holding it is stored. Nothing else deletes them, so a
+// missed run only leaves them in the table for longer.
+pub async fn archive(config: &Config) -> eyre::Result<()> {
+    let retention = config
+        .order_retention_days()
+        .map(|days| days * 24 * 60 * 60)
+        .ok_or_else(|| eyre::eyre!("ORDER_RETENTION_DAYS must be set to archive orders"))?;
+    let bucket = ArchiveBucket::new(config).await?;
+    let client = DynamoDbClient::new(config).await?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut page = PageRequest {
+        limit: Some(ARCHIVE_FILE_SIZE),
+        cursor: None,
+    };
+
+    let (mut archived, mut kept) = (0, 0);
+    for file in 0.. {
+        let result = client
+            .expiring_orders(now, Some(now.saturating_sub(retention)), &page)
+            .await?;
+        let orders = result
+            .items
+            .iter()
+            .map(Order::from_dynamodb_item)
+            .collect::<eyre::Result<Vec<_>>>()?;
+
+        if !orders.is_empty() {
+            let key = bucket
+                .put(&format!("{}-{:05}.jsonl.gz", now, file), encode(&orders)?)
+                .await?;
+            println!(
+                "Archived {} orders to s3://{}/{}",
+                orders.len(),
+                bucket.bucket(),
+                key
+            );
+
+            for order in &orders {
+                // an order reanalysed in the meantime stays, and is archived again once it expires
+                if client.delete_order(order).await? {
+                    archived += 1;
+                } else {
+                    kept += 1;
+                }
+            }
+        }
+
+        match result.next_cursor {
+            Some(cursor) => page.cursor = Some(cursor),
+            None => break,
+        }
+    }
+
+    println!(
+        "Archive finished: {} archived and deleted, {} rewritten since and kept",
+        archived, kept
+    );
+    Ok(())
+}
+
+fn encode(orders: &[Order]) -> eyre::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    for order in orders {
+        serde_json::to_writer(&mut encoder, order)?;
+        encoder.write_all(b"\n")?;
+    }
+
+    Ok(encoder.finish()?)
+}
+
+fn decode(body: &[u8]) -> eyre::Result<Vec<Order>> {
+    BufReader::new(GzDecoder::new(body))
+        .lines()
+        .filter(|line| line.as_ref().map_or(true, |line| !line.is_empty()))
+        .map(|line| Ok(serde_json::from_str(&line?)?))
+        .collect()
+}
+
+// Walks the orders matching a query across every archive file, one file at a time
+pub struct ArchivePages {
+    bucket: ArchiveBucket,
+    keys: VecDeque<String>,
+    query: OrderQuery,
+}
+
+impl ArchivePages {
+    pub async fn new(config: &Config, query: OrderQuery) -> eyre::Result<Self> {
+        let bucket = ArchiveBucket::new(config).await?;
+        let keys = bucket
+            .keys()
+            .await?
+            .into_iter()
+            .filter(|key| key.ends_with(".jsonl.gz"))
+            .collect();
+
+        Ok(Self {
+            bucket,
+            keys,
+            query,
+        })
+    }
+
+    pub async fn next_page(&mut self) -> eyre::Result<Option<Vec<Order>>> {
+        let Some(key) = self.keys.pop_front() else {
+            return Ok(None);
+        };
+
+        let body = self.bucket.get(&key).await?;
+        let orders = decode(&body)
+            .map_err(|e| eyre::eyre!("Failed to read archive {}: {}", key, e))?
+            .into_iter()
+            .filter(|order| self.query.matches(order))
+            .collect();
+
+        Ok(Some(orders))
+    }
+}
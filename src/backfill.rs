@@ -0,0 +1,122 @@
+use crate::config::Config;
+use crate::constant;
+use crate::rpc::{Rpc, RpcProvider};
+use crate::services::{aws_dynamodb_raw_events::RawEventStore, http_client::ApiClient};
+use crate::tokens::TokenRegistry;
+use crate::{analyze_order, Settlement, TradeEvent};
+use ethers::{
+    contract::EthEvent,
+    middleware::Middleware,
+    types::{Address, Filter},
+};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// blocks per eth_getLogs call, well within what RPC providers answer in one go
+const LOG_CHUNK: u64 = 500;
+
+// Analyses every order settled from `from_block` to `to_block`, both inclusive, writing each as
+// a line of JSON. Only orders settled in the last few minutes get 0x and CowSwap quotes and USD
+// prices, older ones are compared with UniV3 at their settlement block alone. Nothing is written to DynamoDB, stored orders and recorded events are left as
+// they are.
+pub async fn backfill(
+    config: &Config,
+    from_block: u64,
+    to_block: u64,
+    output: Option<PathBuf>,
+) -> eyre::Result<()> {
+    if from_block > to_block {
+        return Err(eyre::eyre!(
+            "--from {} is after --to {}",
+            from_block,
+            to_block
+        ));
+    }
+
+    let rpc = Rpc::new(config);
+    let provider = Arc::new(rpc.provider());
+    let block_provider = rpc.quorum_provider();
+    let tokens = TokenRegistry::chain_only(config)?;
+    let raw_events = RawEventStore::dry_run(config).await?;
+    let api_client = ApiClient::new(config);
+    let settlement_contract = constant::GPv2SETTLEMENT.parse::<Address>()?;
+
+    let mut out: Box<dyn Write> = match &output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(std::io::stdout())),
+    };
+
+    let mut seen = HashSet::new();
+    let mut timestamps = HashMap::new();
+    let (mut analysed, mut skipped, mut failed) = (0, 0, 0);
+    for chunk_start in (from_block..=to_block).step_by(LOG_CHUNK as usize) {
+        let chunk_end = (chunk_start + LOG_CHUNK - 1).min(to_block);
+        let filter = Filter::new()
+            .address(settlement_contract)
+            .from_block(chunk_start)
+            .to_block(chunk_end);
+        let trades = TradeEvent::new::<_, RpcProvider>(filter, Arc::clone(&provider))
+            .query_with_meta()
+            .await?;
+        eprintln!(
+            "Blocks {} to {}: {} trades",
+            chunk_start,
+            chunk_end,
+            trades.len()
+        );
+
+        for (trade, meta) in trades {
+            let uid = trade.order_uid.to_string();
+            // partial fills settle more than once, and are skipped anyway
+            if !seen.insert(uid.clone()) {
+                continue;
+            }
+
+            let block_number = meta.block_number.as_u64();
+            let timestamp = match timestamps.get(&block_number) {
+                Some(timestamp) => *timestamp,
+                None => {
+                    let timestamp = match block_provider.get_block(block_number).await {
+                        Ok(Some(block)) => block.timestamp.as_u64(),
+                        _ => 0,
+                    };
+                    timestamps.insert(block_number, timestamp);
+                    timestamp
+                }
+            };
+
+            match analyze_order(
+                config,
+                &tokens,
+                &raw_events,
+                &api_client,
+                &uid,
+                Settlement::past(block_number, timestamp),
+            )
+            .await
+            {
+                Ok(Some(order)) => {
+                    serde_json::to_writer(&mut out, &order)?;
+                    writeln!(out)?;
+                    analysed += 1;
+                }
+                Ok(None) => skipped += 1,
+                Err(e) => {
+                    eprintln!("Failed to analyze order {}: {}", uid, e);
+                    failed += 1;
+                }
+            }
+        }
+        out.flush()?;
+    }
+
+    // stdout may be the output itself
+    eprintln!(
+        "Backfill of blocks {} to {} done: {} analysed, {} skipped, {} failed",
+        from_block, to_block, analysed, skipped, failed
+    );
+    Ok(())
+}
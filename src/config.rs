@@ -0,0 +1,904 @@
+use crate::rpc::Endpoint;
+use clap::Args;
+use dotenv::dotenv;
+use getset::{CopyGetters, Getters};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+// read when it exists and no other file is given
+const DEFAULT_CONFIG_FILE: &str = "cow-quote.toml";
+
+// Everything the service can be tuned with. Built from defaults, then the TOML file, then
+// environment variables, then command line flags, each overriding the one before.
+#[derive(Getters, CopyGetters, Clone, Debug)]
+#[getset(get = "pub")]
+pub struct Config {
+    // tried in order, failing over to the next when one doesn't answer
+    rpc_endpoints: Vec<Endpoint>,
+    // how many endpoints must agree on a critical read, like token decimals
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    rpc_quorum: usize,
+    // how long an endpoint that failed is passed over for
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    rpc_failover_cooldown: Duration,
+    // forked by the Uniswap simulation, so it has to serve state at past blocks
+    archive_rpc_endpoint: Endpoint,
+    zerox_api_key: String,
+    zerox_api_url: String,
+    // CowSwap's mainnet API, the orderbook, trades, quote and native price endpoints hang off it
+    cow_api_url: String,
+    // for each request to the APIs above, retries included
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    http_timeout: Duration,
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    http_max_retries: u32,
+    // backoff between retries doubles from the first up to the second
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    http_retry_base_delay: Duration,
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    http_retry_max_delay: Duration,
+    // requests per second, by host, hosts not listed aren't limited
+    http_rate_limits: HashMap<String, f64>,
+
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    bind_address: SocketAddr,
+    // keys accepted by the mutating routes, which are closed when there are none
+    api_keys: Vec<String>,
+    // requests per minute and client on the read routes
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    rate_limit_per_minute: u32,
+    // take the client from X-Forwarded-For's rightmost entry, only behind a proxy that appends it
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    trust_forwarded_for: bool,
+    // "*" allows every origin, and no origins allow none
+    cors_allowed_origins: Vec<String>,
+
+    orders_table: String,
+    migrations_table: String,
+    raw_events_table: String,
+    tokens_table: String,
+    // points the DynamoDB client at e.g. DynamoDB Local instead of AWS
+    dynamodb_endpoint_url: Option<String>,
+    // orders expire this many days after settlement, and are kept forever when unset
+    order_retention_days: Option<u64>,
+
+    archive_bucket: Option<String>,
+    archive_prefix: String,
+    // points the S3 client at e.g. MinIO instead of AWS
+    s3_endpoint_url: Option<String>,
+
+    // how long POST /start listens for when it isn't told, until stopped when unset
+    run_duration: Option<Duration>,
+    // how many blocks back /latest-data goes
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    latest_blocks: u64,
+    // waits between reconnects double from the first up to the second
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    reconnect_delay: Duration,
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    max_reconnect_delay: Duration,
+
+    // Uniswap V3 pools tried for the simulated swap, by fee in hundredths of a basis point
+    univ3_fee_tiers: Vec<u32>,
+    // for sending, and then confirming, each simulated swap on the fork
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    univ3_tx_timeout: Duration,
+
+    // a readiness check that takes longer than this to answer counts as down
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    health_check_timeout: Duration,
+    // a running listener that saw no trade for this long is reported as stale on /readyz
+    #[getset(skip)]
+    #[get_copy = "pub"]
+    max_trade_age: Duration,
+}
+
+impl Config {
+    // Reads every layer and checks the result, reporting every problem at once rather than
+    // stopping at the first
+    pub fn load(args: &ConfigArgs) -> eyre::Result<Self> {
+        dotenv().ok();
+
+        let mut errors = Vec::new();
+        let mut layers = match config_file(args) {
+            Some(path) => ConfigFile::read(&path)?,
+            None => ConfigFile::default(),
+        };
+        layers.apply_env(&mut errors);
+        layers.apply_args(args);
+
+        let config = layers.validate(&mut errors);
+        if !errors.is_empty() {
+            return Err(eyre::eyre!(
+                "Invalid configuration:\n  - {}",
+                errors.join("\n  - ")
+            ));
+        }
+        Ok(config)
+    }
+}
+
+// Flags override the config file and environment for a single run
+#[derive(Args, Debug, Default)]
+pub struct ConfigArgs {
+    /// TOML config file, defaults to CONFIG_FILE, then to cow-quote.toml when it exists
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// RPC endpoint, an http(s):// or ws(s):// URL or an IPC socket path, repeat for failover
+    #[arg(long = "rpc-url", global = true)]
+    pub rpc_urls: Vec<String>,
+    /// Address the API server listens on
+    #[arg(long, global = true)]
+    pub bind_address: Option<String>,
+    #[arg(long, global = true)]
+    pub orders_table: Option<String>,
+    #[arg(long, global = true)]
+    pub dynamodb_endpoint_url: Option<String>,
+    #[arg(long, global = true)]
+    pub rate_limit_per_minute: Option<u32>,
+    /// How long POST /start and watch listen for when they aren't told
+    #[arg(long, global = true)]
+    pub run_duration_secs: Option<u64>,
+}
+
+fn config_file(args: &ConfigArgs) -> Option<PathBuf> {
+    args.config
+        .clone()
+        .or_else(|| optional_env("CONFIG_FILE").map(PathBuf::from))
+        .or_else(|| {
+            let default = PathBuf::from(DEFAULT_CONFIG_FILE);
+            default.exists().then_some(default)
+        })
+}
+
+// One optional value per setting, so each layer only sets what it has
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    rpc: RpcSection,
+    apis: ApisSection,
+    http: HttpSection,
+    server: ServerSection,
+    dynamodb: DynamoDbSection,
+    archive: ArchiveSection,
+    ingestion: IngestionSection,
+    univ3: Univ3Section,
+    health: HealthSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RpcSection {
+    endpoints: Option<Vec<String>>,
+    // shorthand for its https:// and wss:// endpoints, used when no endpoints are listed
+    alchemy_rpc_url: Option<String>,
+    archive_url: Option<String>,
+    quorum: Option<usize>,
+    failover_cooldown_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ApisSection {
+    zerox_api_key: Option<String>,
+    zerox_api_url: Option<String>,
+    cow_api_url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct HttpSection {
+    timeout_secs: Option<u64>,
+    max_retries: Option<u32>,
+    retry_base_delay_ms: Option<u64>,
+    retry_max_delay_secs: Option<u64>,
+    rate_limits: Option<HashMap<String, f64>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerSection {
+    bind_address: Option<String>,
+    api_keys: Option<Vec<String>>,
+    rate_limit_per_minute: Option<u32>,
+    trust_forwarded_for: Option<bool>,
+    cors_allowed_origins: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DynamoDbSection {
+    endpoint_url: Option<String>,
+    orders_table: Option<String>,
+    migrations_table: Option<String>,
+    raw_events_table: Option<String>,
+    tokens_table: Option<String>,
+    order_retention_days: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ArchiveSection {
+    bucket: Option<String>,
+    prefix: Option<String>,
+    s3_endpoint_url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct IngestionSection {
+    run_duration_secs: Option<u64>,
+    latest_blocks: Option<u64>,
+    reconnect_delay_secs: Option<u64>,
+    max_reconnect_delay_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Univ3Section {
+    fee_tiers: Option<Vec<u32>>,
+    tx_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct HealthSection {
+    check_timeout_secs: Option<u64>,
+    max_trade_age_secs: Option<u64>,
+}
+
+impl ConfigFile {
+    fn read(path: &Path) -> eyre::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| eyre::eyre!("Failed to read config file {}: {}", path.display(), e))?;
+        toml::from_str(&text)
+            .map_err(|e| eyre::eyre!("Invalid config file {}: {}", path.display(), e))
+    }
+
+    fn apply_env(&mut self, errors: &mut Vec<String>) {
+        set(&mut self.rpc.endpoints, env_list("RPC_ENDPOINTS"));
+        set(
+            &mut self.rpc.alchemy_rpc_url,
+            env_value("ALCHEMY_RPC_URL", errors),
+        );
+        set(
+            &mut self.rpc.archive_url,
+            env_value("ARCHIVE_RPC_URL", errors),
+        );
+        set(&mut self.rpc.quorum, env_value("RPC_QUORUM", errors));
+
+        set(
+            &mut self.apis.zerox_api_key,
+            env_value("ZEROX_API_KEY", errors),
+        );
+        set(
+            &mut self.apis.zerox_api_url,
+            env_value("ZEROX_API_URL", errors),
+        );
+        set(&mut self.apis.cow_api_url, env_value("COW_API_URL", errors));
+
+        set(
+            &mut self.http.max_retries,
+            env_value("HTTP_MAX_RETRIES", errors),
+        );
+        set(
+            &mut self.http.rate_limits,
+            env_list("HTTP_RATE_LIMITS").map(|limits| {
+                limits
+                    .iter()
+                    .filter_map(|limit| match limit.split_once('=') {
+                        Some((host, rate)) => parse("HTTP_RATE_LIMITS", rate, errors)
+                            .map(|rate| (host.trim().to_string(), rate)),
+                        None => {
+                            errors.push(format!(
+                                "HTTP_RATE_LIMITS has {:?}, which isn't host=requests_per_second",
+                                limit
+                            ));
+                            None
+                        }
+                    })
+                    .collect()
+            }),
+        );
+
+        set(
+            &mut self.server.bind_address,
+            env_value("BIND_ADDRESS", errors),
+        );
+        set(&mut self.server.api_keys, env_list("API_KEYS"));
+        set(
+            &mut self.server.rate_limit_per_minute,
+            env_value("RATE_LIMIT_PER_MINUTE", errors),
+        );
+        set(
+            &mut self.server.trust_forwarded_for,
+            optional_env("TRUST_FORWARDED_FOR").map(|value| value == "true" || value == "1"),
+        );
+        set(
+            &mut self.server.cors_allowed_origins,
+            env_list("CORS_ALLOWED_ORIGINS"),
+        );
+
+        set(
+            &mut self.dynamodb.endpoint_url,
+            env_value("DYNAMODB_ENDPOINT_URL", errors),
+        );
+        set(
+            &mut self.dynamodb.orders_table,
+            env_value("ORDERS_TABLE", errors),
+        );
+        set(
+            &mut self.dynamodb.migrations_table,
+            env_value("MIGRATIONS_TABLE", errors),
+        );
+        set(
+            &mut self.dynamodb.raw_events_table,
+            env_value("RAW_EVENTS_TABLE", errors),
+        );
+        set(
+            &mut self.dynamodb.tokens_table,
+            env_value("TOKENS_TABLE", errors),
+        );
+        set(
+            &mut self.dynamodb.order_retention_days,
+            env_value("ORDER_RETENTION_DAYS", errors),
+        );
+
+        set(
+            &mut self.archive.bucket,
+            env_value("ARCHIVE_BUCKET", errors),
+        );
+        set(
+            &mut self.archive.prefix,
+            env_value("ARCHIVE_PREFIX", errors),
+        );
+        set(
+            &mut self.archive.s3_endpoint_url,
+            env_value("S3_ENDPOINT_URL", errors),
+        );
+
+        set(
+            &mut self.ingestion.run_duration_secs,
+            env_value("RUN_DURATION_SECS", errors),
+        );
+        set(
+            &mut self.ingestion.latest_blocks,
+            env_value("LATEST_BLOCKS", errors),
+        );
+
+        set(
+            &mut self.univ3.fee_tiers,
+            env_list("UNIV3_FEE_TIERS").map(|tiers| {
+                tiers
+                    .iter()
+                    .filter_map(|tier| parse("UNIV3_FEE_TIERS", tier, errors))
+                    .collect()
+            }),
+        );
+    }
+
+    fn apply_args(&mut self, args: &ConfigArgs) {
+        if !args.rpc_urls.is_empty() {
+            self.rpc.endpoints = Some(args.rpc_urls.clone());
+        }
+        set(&mut self.server.bind_address, args.bind_address.clone());
+        set(&mut self.dynamodb.orders_table, args.orders_table.clone());
+        set(
+            &mut self.dynamodb.endpoint_url,
+            args.dynamodb_endpoint_url.clone(),
+        );
+        set(
+            &mut self.server.rate_limit_per_minute,
+            args.rate_limit_per_minute,
+        );
+        set(
+            &mut self.ingestion.run_duration_secs,
+            args.run_duration_secs,
+        );
+    }
+
+    // fills in the defaults, and records what is missing or out of range
+    fn validate(self, errors: &mut Vec<String>) -> Config {
+        let zerox_api_key = self
+            .apis
+            .zerox_api_key
+            .filter(|key| !key.is_empty())
+            .unwrap_or_else(|| {
+                errors.push(
+                    "apis.zerox_api_key is required, set it in the config file or ZEROX_API_KEY"
+                        .into(),
+                );
+                String::new()
+            });
+
+        let endpoints = match (self.rpc.endpoints, self.rpc.alchemy_rpc_url) {
+            (Some(endpoints), _) if !endpoints.is_empty() => endpoints,
+            (_, Some(alchemy)) if !alchemy.is_empty() => {
+                if alchemy.contains("://") {
+                    errors.push(
+                        "rpc.alchemy_rpc_url is the endpoint without https:// or wss://".into(),
+                    );
+                }
+                vec![format!("https://{}", alchemy), format!("wss://{}", alchemy)]
+            }
+            _ => {
+                errors.push(
+                    "rpc.endpoints is required, set it in the config file or RPC_ENDPOINTS, \
+                     or set ALCHEMY_RPC_URL"
+                        .into(),
+                );
+                Vec::new()
+            }
+        };
+        let rpc_endpoints: Vec<Endpoint> = endpoints
+            .iter()
+            .filter_map(|endpoint| endpoint_from(endpoint, "rpc.endpoints", errors))
+            .collect();
+
+        // endpoints on the same host, like ALCHEMY_RPC_URL's two, would only agree with themselves
+        let rpc_hosts = rpc_endpoints
+            .iter()
+            .map(Endpoint::host)
+            .collect::<HashSet<_>>()
+            .len();
+        let rpc_quorum = self.rpc.quorum.unwrap_or(1);
+        if rpc_quorum == 0 || rpc_quorum > rpc_hosts.max(1) {
+            errors.push(format!(
+                "rpc.quorum must be between 1 and the {} distinct endpoint hosts",
+                rpc_hosts
+            ));
+        }
+
+        // the first HTTP endpoint, as the fork makes many small requests
+        let archive_rpc_endpoint = match self.rpc.archive_url {
+            Some(archive) => endpoint_from(&archive, "rpc.archive_url", errors),
+            None => rpc_endpoints
+                .iter()
+                .find(|endpoint| !endpoint.is_pubsub())
+                .or(rpc_endpoints.first())
+                .cloned(),
+        }
+        .unwrap_or(Endpoint::Http(String::new()));
+
+        let zerox_api_url = url(
+            self.apis.zerox_api_url,
+            "https://api.0x.org",
+            "apis.zerox_api_url",
+            errors,
+        );
+        let cow_api_url = url(
+            self.apis.cow_api_url,
+            "https://api.cow.fi/mainnet",
+            "apis.cow_api_url",
+            errors,
+        );
+        let dynamodb_endpoint_url = self
+            .dynamodb
+            .endpoint_url
+            .map(|endpoint| url(Some(endpoint), "", "dynamodb.endpoint_url", errors));
+        let s3_endpoint_url = self
+            .archive
+            .s3_endpoint_url
+            .map(|endpoint| url(Some(endpoint), "", "archive.s3_endpoint_url", errors));
+
+        let bind_address = self
+            .server
+            .bind_address
+            .as_deref()
+            .unwrap_or("0.0.0.0:3000")
+            .parse()
+            .unwrap_or_else(|e| {
+                errors.push(format!(
+                    "server.bind_address is not a socket address: {}",
+                    e
+                ));
+                SocketAddr::from(([0, 0, 0, 0], 3000))
+            });
+
+        let rate_limit_per_minute = self.server.rate_limit_per_minute.unwrap_or(120);
+        if rate_limit_per_minute == 0 {
+            errors.push("server.rate_limit_per_minute must be above 0".into());
+        }
+        let latest_blocks = self.ingestion.latest_blocks.unwrap_or(15);
+        if latest_blocks == 0 {
+            errors.push("ingestion.latest_blocks must be above 0".into());
+        }
+
+        let reconnect_delay = secs(self.ingestion.reconnect_delay_secs, 1);
+        let max_reconnect_delay = secs(self.ingestion.max_reconnect_delay_secs, 60);
+        if reconnect_delay.is_zero() || reconnect_delay > max_reconnect_delay {
+            errors.push(
+                "ingestion.reconnect_delay_secs must be above 0 and at most max_reconnect_delay_secs"
+                    .into(),
+            );
+        }
+
+        let univ3_fee_tiers = self.univ3.fee_tiers.unwrap_or(vec![100, 500, 3000, 10000]);
+        if univ3_fee_tiers.is_empty() {
+            errors.push("univ3.fee_tiers needs at least one fee tier".into());
+        }
+        // fees are uint24 on chain
+        if let Some(tier) = univ3_fee_tiers.iter().find(|tier| **tier >= 1 << 24) {
+            errors.push(format!(
+                "univ3.fee_tiers has {}, which is no fee tier",
+                tier
+            ));
+        }
+
+        let http_retry_base_delay =
+            Duration::from_millis(self.http.retry_base_delay_ms.unwrap_or(250));
+        let http_retry_max_delay = secs(self.http.retry_max_delay_secs, 10);
+        if http_retry_base_delay.is_zero() || http_retry_base_delay > http_retry_max_delay {
+            errors.push(
+                "http.retry_base_delay_ms must be above 0 and at most retry_max_delay_secs".into(),
+            );
+        }
+        let http_rate_limits = self.http.rate_limits.unwrap_or_default();
+        for (host, rate) in &http_rate_limits {
+            if !rate.is_finite() || *rate <= 0.0 {
+                errors.push(format!(
+                    "http.rate_limits has {} for {}, it must be above 0",
+                    rate, host
+                ));
+            }
+        }
+
+        let durations = [
+            ("http.timeout_secs", self.http.timeout_secs, 10),
+            (
+                "rpc.failover_cooldown_secs",
+                self.rpc.failover_cooldown_secs,
+                30,
+            ),
+            ("univ3.tx_timeout_secs", self.univ3.tx_timeout_secs, 8),
+            (
+                "health.check_timeout_secs",
+                self.health.check_timeout_secs,
+                5,
+            ),
+            (
+                "health.max_trade_age_secs",
+                self.health.max_trade_age_secs,
+                10 * 60,
+            ),
+        ]
+        .map(|(name, value, default)| {
+            if value == Some(0) {
+                errors.push(format!("{} must be above 0", name));
+            }
+            secs(value, default)
+        });
+
+        Config {
+            rpc_endpoints,
+            rpc_quorum,
+            rpc_failover_cooldown: durations[1],
+            archive_rpc_endpoint,
+            zerox_api_key,
+            zerox_api_url,
+            cow_api_url,
+            http_timeout: durations[0],
+            http_max_retries: self.http.max_retries.unwrap_or(3),
+            http_retry_base_delay,
+            http_retry_max_delay,
+            http_rate_limits,
+            bind_address,
+            api_keys: self.server.api_keys.unwrap_or_default(),
+            rate_limit_per_minute,
+            trust_forwarded_for: self.server.trust_forwarded_for.unwrap_or(false),
+            cors_allowed_origins: self.server.cors_allowed_origins.unwrap_or_default(),
+            orders_table: self.dynamodb.orders_table.unwrap_or("orders".into()),
+            migrations_table: self
+                .dynamodb
+                .migrations_table
+                .unwrap_or("orders_migrations".into()),
+            raw_events_table: self
+                .dynamodb
+                .raw_events_table
+                .unwrap_or("raw_events".into()),
+            tokens_table: self.dynamodb.tokens_table.unwrap_or("tokens".into()),
+            dynamodb_endpoint_url,
+            order_retention_days: self.dynamodb.order_retention_days,
+            archive_bucket: self.archive.bucket,
+            archive_prefix: self.archive.prefix.unwrap_or("orders/".into()),
+            s3_endpoint_url,
+            run_duration: self.ingestion.run_duration_secs.map(Duration::from_secs),
+            latest_blocks,
+            reconnect_delay,
+            max_reconnect_delay,
+            univ3_fee_tiers,
+            univ3_tx_timeout: durations[2],
+            health_check_timeout: durations[3],
+            max_trade_age: durations[4],
+        }
+    }
+}
+
+fn set<T>(slot: &mut Option<T>, value: Option<T>) {
+    if value.is_some() {
+        *slot = value;
+    }
+}
+
+fn secs(value: Option<u64>, default: u64) -> Duration {
+    Duration::from_secs(value.unwrap_or(default))
+}
+
+// with the trailing slash dropped, so paths can be appended
+fn url(value: Option<String>, default: &str, name: &str, errors: &mut Vec<String>) -> String {
+    let url = value.unwrap_or(default.to_string());
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        errors.push(format!(
+            "{} must be an http:// or https:// URL, not {:?}",
+            name, url
+        ));
+    }
+    url.trim_end_matches('/').to_string()
+}
+
+fn endpoint_from(endpoint: &str, name: &str, errors: &mut Vec<String>) -> Option<Endpoint> {
+    match endpoint.parse() {
+        Ok(endpoint) => Some(endpoint),
+        Err(e) => {
+            errors.push(format!(
+                "{} has {:?}, which is invalid: {}",
+                name, endpoint, e
+            ));
+            None
+        }
+    }
+}
+
+fn parse<T: FromStr>(key: &str, value: &str, errors: &mut Vec<String>) -> Option<T>
+where
+    T::Err: Display,
+{
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(e) => {
+            errors.push(format!("{} has {:?}, which is invalid: {}", key, value, e));
+            None
+        }
+    }
+}
+
+fn env_value<T: FromStr>(key: &str, errors: &mut Vec<String>) -> Option<T>
+where
+    T::Err: Display,
+{
+    optional_env(key).and_then(|value| parse(key, &value, errors))
+}
+
+// empty values, as left by .env.example, count as unset
+fn optional_env(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+// comma separated, with blanks dropped
+fn env_list(key: &str) -> Option<Vec<String>> {
+    optional_env(key).map(|value| {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL: &str = r#"
+        [rpc]
+        endpoints = ["https://eth.example.com", "wss://eth.example.com"]
+
+        [apis]
+        zerox_api_key = "key"
+    "#;
+
+    fn file(text: &str) -> ConfigFile {
+        toml::from_str(text).unwrap()
+    }
+
+    fn validate(layers: ConfigFile) -> (Config, Vec<String>) {
+        let mut errors = Vec::new();
+        let config = layers.validate(&mut errors);
+        (config, errors)
+    }
+
+    #[test]
+    fn defaults_fill_in_what_no_layer_sets() {
+        let (config, errors) = validate(file(MINIMAL));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(config.orders_table(), "orders");
+        assert_eq!(config.rate_limit_per_minute(), 120);
+        assert_eq!(config.rpc_quorum(), 1);
+        assert_eq!(config.rpc_endpoints().len(), 2);
+        // the first HTTP endpoint, when no archive is set
+        assert_eq!(
+            config.archive_rpc_endpoint().url(),
+            "https://eth.example.com"
+        );
+    }
+
+    #[test]
+    fn flags_override_the_file() {
+        let mut layers = file(&format!(
+            "{}\n[server]\nrate_limit_per_minute = 60\n[dynamodb]\norders_table = \"file\"",
+            MINIMAL
+        ));
+        layers.apply_args(&ConfigArgs {
+            rpc_urls: vec!["http://localhost:8545".into()],
+            orders_table: Some("flag".into()),
+            ..Default::default()
+        });
+
+        let (config, errors) = validate(layers);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(config.orders_table(), "flag");
+        // left alone by the flags
+        assert_eq!(config.rate_limit_per_minute(), 60);
+        // listed endpoints are replaced, not added to
+        assert_eq!(
+            config.rpc_endpoints(),
+            &vec![Endpoint::Http("http://localhost:8545".into())]
+        );
+    }
+
+    // the only test reading the environment, so nothing else sees these variables
+    #[test]
+    fn env_overrides_the_file_and_flags_override_env() {
+        env::set_var("ORDERS_TABLE", "env");
+        env::set_var("RATE_LIMIT_PER_MINUTE", "30");
+        env::set_var("LATEST_BLOCKS", "ten");
+        let mut layers = file(&format!(
+            "{}
+[server]
+rate_limit_per_minute = 60
+[dynamodb]
+orders_table = \"file\"",
+            MINIMAL
+        ));
+        let mut errors = Vec::new();
+        layers.apply_env(&mut errors);
+        env::remove_var("ORDERS_TABLE");
+        env::remove_var("RATE_LIMIT_PER_MINUTE");
+        env::remove_var("LATEST_BLOCKS");
+
+        layers.apply_args(&ConfigArgs {
+            rate_limit_per_minute: Some(90),
+            ..Default::default()
+        });
+        let config = layers.validate(&mut errors);
+        assert_eq!(config.orders_table(), "env");
+        assert_eq!(config.rate_limit_per_minute(), 90);
+        // a bad value is reported along with whatever validation finds
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].starts_with("LATEST_BLOCKS has \"ten\""));
+    }
+
+    #[test]
+    fn unset_flags_leave_the_file_alone() {
+        let mut layers = file(&format!("{}\n[dynamodb]\norders_table = \"file\"", MINIMAL));
+        layers.apply_args(&ConfigArgs::default());
+
+        let (config, _) = validate(layers);
+        assert_eq!(config.orders_table(), "file");
+        assert_eq!(config.rpc_endpoints().len(), 2);
+    }
+
+    #[test]
+    fn endpoints_take_precedence_over_alchemy() {
+        let mut layers = file(MINIMAL);
+        layers.rpc.alchemy_rpc_url = Some("eth-mainnet.g.alchemy.com/v2/key".into());
+
+        let (config, errors) = validate(layers);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(config.rpc_endpoints()[0].url(), "https://eth.example.com");
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        let (_, errors) = validate(file(
+            r#"
+            [rpc]
+            endpoints = ["ftp://eth.example.com"]
+            quorum = 2
+
+            [apis]
+            cow_api_url = "api.cow.fi"
+
+            [server]
+            rate_limit_per_minute = 0
+
+            [univ3]
+            fee_tiers = []
+            "#,
+        ));
+
+        for expected in [
+            "apis.zerox_api_key is required",
+            "rpc.endpoints has \"ftp://eth.example.com\"",
+            "rpc.quorum must be between 1 and the 0 distinct endpoint hosts",
+            "apis.cow_api_url must be an http:// or https:// URL",
+            "server.rate_limit_per_minute must be above 0",
+            "univ3.fee_tiers needs at least one fee tier",
+        ] {
+            assert!(
+                errors.iter().any(|e| e.starts_with(expected)),
+                "no {:?} in {:?}",
+                expected,
+                errors
+            );
+        }
+    }
+
+    #[test]
+    fn quorum_counts_hosts_not_urls() {
+        let mut layers = file("[apis]\nzerox_api_key = \"key\"\n[rpc]\nquorum = 2");
+        layers.rpc.alchemy_rpc_url = Some("eth-mainnet.g.alchemy.com/v2/key".into());
+        let (_, errors) = validate(layers);
+        assert_eq!(
+            errors,
+            ["rpc.quorum must be between 1 and the 1 distinct endpoint hosts"]
+        );
+
+        let (config, errors) = validate(file(
+            r#"
+            [rpc]
+            endpoints = ["https://a.example.com", "wss://a.example.com", "https://b.example.com"]
+            quorum = 2
+
+            [apis]
+            zerox_api_key = "key"
+            "#,
+        ));
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(config.rpc_quorum(), 2);
+    }
+
+    #[test]
+    fn missing_endpoints_are_reported() {
+        let (_, errors) = validate(file("[apis]\nzerox_api_key = \"key\""));
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(errors[0].starts_with("rpc.endpoints is required"));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<ConfigFile>("[rpc]\nendpoint = \"https://x\"").is_err());
+    }
+
+    #[test]
+    fn invalid_values_are_collected_with_their_key() {
+        let mut errors = Vec::new();
+        assert_eq!(parse::<u32>("LATEST_BLOCKS", "ten", &mut errors), None);
+        assert_eq!(parse::<u32>("RPC_QUORUM", "-1", &mut errors), None);
+        assert_eq!(parse::<u32>("RPC_QUORUM", "2", &mut errors), Some(2));
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("LATEST_BLOCKS has \"ten\""));
+        assert!(errors[1].starts_with("RPC_QUORUM has \"-1\""));
+    }
+}
@@ -1,4 +1,17 @@
+#[allow(non_upper_case_globals)]
 pub const GPv2SETTLEMENT: &str = "0x9008D19f58AAbD9eD0D60971565AA8510560ab41";
 pub const WETH: &str = "0xeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee";
+pub const WRAPPED_ETH: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+// the reference for USD prices
+pub const USDC: &str = "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48";
+pub const USDC_DECIMALS: u8 = 6;
 pub const UNISWAP_V3_ROUTER: &str = "0x68b3465833fb72A70ecDF485E0e4C7bD8665Fc45";
 pub const UNISWAP_V3_FACTORY: &str = "0x1F98431c8aD98523631AE4a59f267346ea31F984";
+
+// Quotes and native prices are only to be had for the market as it is now, so they are only
+// taken for orders settled this recently. Older orders are compared with the UniV3 simulation at
+// their settlement block alone.
+pub const LIVE_WINDOW_SECS: u64 = 5 * 60;
+
+// bump whenever the way an Order is derived changes, so stored analyses can be replaced
+pub const ANALYSIS_VERSION: u32 = 4;
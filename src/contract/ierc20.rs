@@ -3,8 +3,9 @@ use ethers::prelude::abigen;
 pub use IERC20;
 
 use ethers::{
-    providers::{Provider, Ws},
-    types::Address,
+    abi::AbiDecode,
+    providers::{JsonRpcError, Middleware, MiddlewareError},
+    types::{transaction::eip2718::TypedTransaction, Address},
 };
 use std::sync::Arc;
 
@@ -12,6 +13,8 @@ abigen!(
     IERC20,
     r#"[
     function decimals() public view returns (uint8)
+    function symbol() public view returns (string)
+    function name() public view returns (string)
     function approve(address spender, uint256 amount) public returns (bool)
     function balanceOf(address owner) public view returns (uint256)
     function allowance(address owner, address spender) public view returns (uint256)
@@ -20,8 +23,8 @@ abigen!(
     ]"#
 );
 
-pub async fn get_token_decimals(
-    provider: Arc<Provider<Ws>>,
+pub async fn get_token_decimals<M: Middleware + 'static>(
+    provider: Arc<M>,
     token: Address,
     is_weth: bool,
 ) -> eyre::Result<u8> {
@@ -32,3 +35,83 @@ pub async fn get_token_decimals(
         Ok(erc20.decimals().call().await? as u8)
     }
 }
+
+// None when the token doesn't implement it
+pub async fn get_token_symbol<M: Middleware + 'static>(
+    provider: Arc<M>,
+    token: Address,
+) -> eyre::Result<Option<String>> {
+    let erc20 = IERC20::new(token, Arc::clone(&provider));
+    call_text(provider, &erc20.symbol().tx).await
+}
+
+// None when the token doesn't implement it
+pub async fn get_token_name<M: Middleware + 'static>(
+    provider: Arc<M>,
+    token: Address,
+) -> eyre::Result<Option<String>> {
+    let erc20 = IERC20::new(token, Arc::clone(&provider));
+    call_text(provider, &erc20.name().tx).await
+}
+
+// ERC20 made symbol() and name() optional and left their type open, so some older tokens like
+// MKR return a bytes32 instead of a string. Called raw to decode either. Only the node failing
+// to answer is an error, a revert or an output that is neither means the token has no such text.
+async fn call_text<M: Middleware + 'static>(
+    provider: Arc<M>,
+    tx: &TypedTransaction,
+) -> eyre::Result<Option<String>> {
+    match provider.call(tx, None).await {
+        Ok(output) => Ok(decode_text(&output)),
+        Err(e) if e.as_error_response().is_some_and(JsonRpcError::is_revert) => Ok(None),
+        Err(e) => Err(eyre::eyre!("{}", e)),
+    }
+}
+
+fn decode_text(output: &[u8]) -> Option<String> {
+    if let Ok(text) = String::decode(output) {
+        return Some(text);
+    }
+    if output.len() == 32 {
+        let end = output.iter().position(|b| *b == 0).unwrap_or(output.len());
+        return String::from_utf8(output[..end].to_vec()).ok();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::AbiEncode;
+
+    fn bytes32(text: &str) -> Vec<u8> {
+        let mut output = text.as_bytes().to_vec();
+        output.resize(32, 0);
+        output
+    }
+
+    #[test]
+    fn decodes_abi_strings() {
+        assert_eq!(
+            decode_text(&"USD Coin".to_string().encode()),
+            Some("USD Coin".to_string())
+        );
+        assert_eq!(decode_text(&String::new().encode()), Some(String::new()));
+    }
+
+    #[test]
+    fn decodes_bytes32_up_to_the_first_zero() {
+        assert_eq!(decode_text(&bytes32("MKR")), Some("MKR".to_string()));
+        assert_eq!(decode_text(&bytes32("Maker")), Some("Maker".to_string()));
+        let full = "A".repeat(32);
+        assert_eq!(decode_text(full.as_bytes()), Some(full.clone()));
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        assert_eq!(decode_text(&[]), None);
+        assert_eq!(decode_text(&[0xff; 32]), None);
+        assert_eq!(decode_text(&[b'A'; 31]), None);
+        assert_eq!(decode_text(&[b'A'; 64]), None);
+    }
+}
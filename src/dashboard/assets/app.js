@@ -0,0 +1,99 @@
+// Keeps the overview's order table live from /orders/stream. The rows mirror order_row in
+// src/dashboard/mod.rs, so a change to one needs the other.
+(function () {
+  "use strict";
+
+  // the overview renders the same number of rows
+  const MAX_ROWS = 50;
+  const VENUES = [
+    ["zerox_quote_buy", "compared_executed_with_zerox_quote"],
+    ["cows_own_quote_buy", "compared_executed_with_cows_own_quote"],
+    ["univ3_swap_buy", "compared_executed_with_univ3_swap"],
+  ];
+
+  const orders = document.getElementById("orders");
+  const live = document.getElementById("live");
+  if (!orders || !window.EventSource) {
+    return;
+  }
+
+  function cell(row, text, className) {
+    const td = document.createElement("td");
+    td.textContent = text;
+    if (className) {
+      td.className = className;
+    }
+    row.appendChild(td);
+    return td;
+  }
+
+  function tokenLabel(info, address) {
+    if (info && info.symbol) {
+      return info.symbol;
+    }
+    return address.length > 10 ? address.slice(0, 6) + "…" + address.slice(-4) : address;
+  }
+
+  function amount(value) {
+    return value.toFixed(Math.abs(value) >= 1000 ? 2 : 6);
+  }
+
+  function time(timestamp) {
+    if (!timestamp) {
+      return "-";
+    }
+    return new Date(timestamp * 1000).toISOString().slice(0, 19).replace("T", " ");
+  }
+
+  function hasUsdValuation(order) {
+    return order.sell_token_usd_price > 0 && order.buy_token_usd_price > 0;
+  }
+
+  function renderRow(order) {
+    const row = document.createElement("tr");
+    row.className = "new";
+
+    const link = document.createElement("a");
+    link.href = "/dashboard/orders/" + encodeURIComponent(order.uid);
+    link.textContent = time(order.timestamp);
+    cell(row, "").appendChild(link);
+
+    cell(
+      row,
+      tokenLabel(order.sell_token_info, order.sell_token) +
+        " → " +
+        tokenLabel(order.buy_token_info, order.buy_token)
+    );
+    cell(row, amount(order.executed_sell));
+    cell(row, amount(order.executed_buy));
+    cell(row, (order.surplus_percentage * 100).toFixed(3) + "%");
+    cell(row, hasUsdValuation(order) ? "$" + order.net_surplus_usd.toFixed(2) : "-");
+
+    for (const [quote, difference] of VENUES) {
+      if (!order[quote]) {
+        cell(row, "-", "muted");
+      } else if (order[difference] > 0) {
+        cell(row, "won", "won");
+      } else if (order[difference] < 0) {
+        cell(row, "lost", "lost");
+      } else {
+        cell(row, "tied", "tied");
+      }
+    }
+    return row;
+  }
+
+  const source = new EventSource("/orders/stream");
+  source.onopen = function () {
+    live.textContent = "live";
+  };
+  source.onerror = function () {
+    live.textContent = "reconnecting";
+  };
+  source.addEventListener("order", function (event) {
+    orders.insertBefore(renderRow(JSON.parse(event.data)), orders.firstChild);
+    while (orders.rows.length > MAX_ROWS) {
+      orders.deleteRow(-1);
+    }
+  });
+})();
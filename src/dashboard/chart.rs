@@ -0,0 +1,173 @@
+// Inline SVG charts, sized by viewBox so they scale with the page
+use super::escape;
+use std::fmt::Write;
+
+const WIDTH: f64 = 640.0;
+const LABEL_WIDTH: f64 = 120.0;
+const ROW_HEIGHT: f64 = 28.0;
+const BAR_HEIGHT: f64 = 18.0;
+
+// One stacked bar per row, of won, tied and lost as shares of the row's total
+pub fn outcome_bars(rows: &[(&str, usize, usize, usize)]) -> String {
+    let height = ROW_HEIGHT * rows.len() as f64;
+    let plot_width = WIDTH - LABEL_WIDTH;
+    let mut svg = open(height);
+
+    for (i, (label, won, tied, lost)) in rows.iter().enumerate() {
+        let y = ROW_HEIGHT * i as f64;
+        text(&mut svg, 0.0, y + BAR_HEIGHT * 0.75, "label", label);
+
+        let total = (won + tied + lost) as f64;
+        if total == 0.0 {
+            text(
+                &mut svg,
+                LABEL_WIDTH,
+                y + BAR_HEIGHT * 0.75,
+                "muted",
+                "no quotes",
+            );
+            continue;
+        }
+
+        let mut x = LABEL_WIDTH;
+        for (count, class) in [(won, "won"), (tied, "tied"), (lost, "lost")] {
+            let width = *count as f64 / total * plot_width;
+            if width == 0.0 {
+                continue;
+            }
+            let _ = write!(
+                svg,
+                r#"<rect class="{}" x="{:.1}" y="{:.1}" width="{:.1}" height="{}"><title>{} {}</title></rect>"#,
+                class, x, y, width, BAR_HEIGHT, count, class
+            );
+            if width > 40.0 {
+                text(
+                    &mut svg,
+                    x + 4.0,
+                    y + BAR_HEIGHT * 0.75,
+                    "bar-label",
+                    &format!("{:.0}%", *count as f64 / total * 100.0),
+                );
+            }
+            x += width;
+        }
+    }
+
+    close(svg)
+}
+
+// One column per bucket, with the outer buckets' open ends shown as such
+pub fn histogram(buckets: &[(Option<f64>, Option<f64>, usize)]) -> String {
+    let height = 180.0;
+    let plot_height = height - 24.0;
+    let max = buckets.iter().map(|b| b.2).max().unwrap_or(0).max(1) as f64;
+    let column = WIDTH / buckets.len().max(1) as f64;
+    let mut svg = open(height);
+
+    for (i, (lower, upper, count)) in buckets.iter().enumerate() {
+        let bar = *count as f64 / max * plot_height;
+        let range = match (lower, upper) {
+            (None, Some(upper)) => format!("< {:.3}%", upper * 100.0),
+            (Some(lower), None) => format!(">= {:.3}%", lower * 100.0),
+            (Some(lower), Some(upper)) => {
+                format!("{:.3}% to {:.3}%", lower * 100.0, upper * 100.0)
+            }
+            (None, None) => "all".to_string(),
+        };
+        let _ = write!(
+            svg,
+            r#"<rect class="bucket" x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}"><title>{}: {} orders</title></rect>"#,
+            column * i as f64 + 1.0,
+            plot_height - bar,
+            (column - 2.0).max(1.0),
+            bar,
+            escape(&range),
+            count
+        );
+    }
+    if let (Some(first), Some(last)) = (buckets.first(), buckets.last()) {
+        let edge = |edge: Option<f64>| edge.map_or(String::new(), |e| format!("{:.3}%", e * 100.0));
+        text(&mut svg, 0.0, height - 6.0, "muted", &edge(first.1));
+        let _ = write!(
+            svg,
+            r#"<text class="muted" x="{}" y="{}" text-anchor="end">{}</text>"#,
+            WIDTH,
+            height - 6.0,
+            escape(&edge(last.0))
+        );
+    }
+
+    close(svg)
+}
+
+// Horizontal bars growing left or right of a zero line, for values in percent
+pub fn diverging_bars(rows: &[(&str, f64)]) -> String {
+    let height = ROW_HEIGHT * rows.len() as f64;
+    let half = (WIDTH - LABEL_WIDTH) / 2.0;
+    let zero = LABEL_WIDTH + half;
+    let max = rows
+        .iter()
+        .map(|(_, value)| value.abs())
+        .fold(0.0, f64::max);
+    let scale = if max > 0.0 { (half - 60.0) / max } else { 0.0 };
+    let mut svg = open(height);
+
+    for (i, (label, value)) in rows.iter().enumerate() {
+        let y = ROW_HEIGHT * i as f64;
+        let width = value.abs() * scale;
+        let (x, class) = if *value >= 0.0 {
+            (zero, "won")
+        } else {
+            (zero - width, "lost")
+        };
+        text(&mut svg, 0.0, y + BAR_HEIGHT * 0.75, "label", label);
+        let _ = write!(
+            svg,
+            r#"<rect class="{}" x="{:.1}" y="{:.1}" width="{:.1}" height="{}"></rect>"#,
+            class, x, y, width, BAR_HEIGHT
+        );
+        let (label_x, anchor) = if *value >= 0.0 {
+            (zero + width + 4.0, "start")
+        } else {
+            (zero - width - 4.0, "end")
+        };
+        let _ = write!(
+            svg,
+            r#"<text class="label" x="{:.1}" y="{:.1}" text-anchor="{}">{:+.4}%</text>"#,
+            label_x,
+            y + BAR_HEIGHT * 0.75,
+            anchor,
+            value
+        );
+    }
+    let _ = write!(
+        svg,
+        r#"<line class="axis" x1="{zero:.1}" y1="0" x2="{zero:.1}" y2="{height:.1}"></line>"#
+    );
+
+    close(svg)
+}
+
+fn open(height: f64) -> String {
+    format!(
+        r#"<svg class="chart" viewBox="0 0 {} {}" role="img" xmlns="http://www.w3.org/2000/svg">"#,
+        WIDTH,
+        height.max(1.0)
+    )
+}
+
+fn close(mut svg: String) -> String {
+    svg.push_str("</svg>");
+    svg
+}
+
+fn text(svg: &mut String, x: f64, y: f64, class: &str, content: &str) {
+    let _ = write!(
+        svg,
+        r#"<text class="{}" x="{:.1}" y="{:.1}">{}</text>"#,
+        class,
+        x,
+        y,
+        escape(content)
+    );
+}
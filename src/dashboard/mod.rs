@@ -0,0 +1,392 @@
+pub mod chart;
+
+use crate::analytics::cache_now;
+use crate::analytics::stats::{compute_stats, GroupBy, Stats, StatsParams};
+use crate::order::{Order, Outcome, Venue};
+use crate::services::aws_dynamodb::{
+    fetch_orders_from_database, DynamoDbClient, OrderQuery, PageRequest,
+};
+use crate::tokens::{OrderWithTokens, TokenInfo, TokenRegistry};
+use chrono::DateTime;
+use futures::future;
+use serde::Deserialize;
+use std::fmt::Write;
+
+// compiled into the binary, so the dashboard ships with the API and nothing else
+pub const STYLE: &str = include_str!("assets/style.css");
+pub const SCRIPT: &str = include_str!("assets/app.js");
+
+const DEFAULT_DAYS: u64 = 7;
+const MAX_DAYS: u64 = 90;
+// app.js drops rows past the same number
+const LIVE_ROWS: i32 = 50;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DashboardParams {
+    // how far back the charts and breakdowns look
+    pub days: Option<u64>,
+}
+
+impl DashboardParams {
+    fn days(&self) -> u64 {
+        self.days.unwrap_or(DEFAULT_DAYS).clamp(1, MAX_DAYS)
+    }
+
+    // rounded like the stats cache, so page views share its reports
+    fn query(&self) -> OrderQuery {
+        OrderQuery {
+            from_timestamp: Some(cache_now().saturating_sub(self.days() * 24 * 60 * 60)),
+            ..Default::default()
+        }
+    }
+}
+
+// Live order table, CoW against each venue, and the surplus distribution
+pub async fn overview_page(
+    client: &DynamoDbClient,
+    tokens: &TokenRegistry,
+    params: &DashboardParams,
+) -> eyre::Result<String> {
+    let report = compute_stats(client, params.query(), &StatsParams::default()).await?;
+    let latest = fetch_orders_from_database(
+        client,
+        &OrderQuery::default(),
+        &PageRequest {
+            limit: Some(LIVE_ROWS),
+            cursor: None,
+        },
+    )
+    .await?;
+    let latest = tokens.page_with_tokens(latest).await.items;
+    let overall = report.overall();
+
+    let mut body = String::new();
+    let _ = write!(
+        body,
+        r#"<section class="cards">{}{}{}</section>"#,
+        card("Orders", &overall.order_count().to_string()),
+        card(
+            "Median surplus",
+            &percent(*overall.surplus_percentage().median())
+        ),
+        card("Net surplus", &usd(*overall.total_net_surplus_usd())),
+    );
+    let _ = write!(
+        body,
+        r#"<section><h2>CoW against each venue, last {} days</h2><p class="muted">Share of quoted orders where CoW's execution beat, tied or fell short of the venue's quote</p>{}<p class="legend"><span class="won">won</span> <span class="tied">tied</span> <span class="lost">lost</span></p></section>"#,
+        params.days(),
+        chart::outcome_bars(&venue_rows(overall)),
+    );
+    let buckets = overall
+        .surplus_percentage_histogram()
+        .iter()
+        .map(|bucket| (*bucket.lower(), *bucket.upper(), *bucket.count()))
+        .collect::<Vec<_>>();
+    let _ = write!(
+        body,
+        r#"<section><h2>Surplus over the minimum buy amount</h2>{}</section>"#,
+        chart::histogram(&buckets),
+    );
+    let _ = write!(
+        body,
+        r#"<section><h2>Latest orders <span id="live" class="muted">connecting</span></h2><table><thead>{}</thead><tbody id="orders">{}</tbody></table></section>"#,
+        ORDER_HEADER,
+        latest.iter().map(order_row).collect::<String>(),
+    );
+
+    Ok(layout("Overview", &days_nav("/dashboard", params), &body))
+}
+
+// CoW against each venue and the surplus distribution per traded pair
+pub async fn pairs_page(
+    client: &DynamoDbClient,
+    tokens: &TokenRegistry,
+    params: &DashboardParams,
+) -> eyre::Result<String> {
+    let report = compute_stats(
+        client,
+        params.query(),
+        &StatsParams {
+            group_by: Some(GroupBy::Pair),
+            bins: None,
+        },
+    )
+    .await?;
+    let mut groups = report.groups().iter().collect::<Vec<_>>();
+    groups.sort_by_key(|group| std::cmp::Reverse(*group.stats().order_count()));
+
+    let labels = future::join_all(groups.iter().map(|group| async move {
+        let (sell, buy) = group.key().split_once('/').unwrap_or((group.key(), ""));
+        let (sell_token, buy_token) = tokio::join!(tokens.resolve(sell), tokens.resolve(buy));
+        format!(
+            "{} → {}",
+            token_label(sell_token.ok().as_ref(), sell),
+            token_label(buy_token.ok().as_ref(), buy)
+        )
+    }))
+    .await;
+
+    let mut rows = String::new();
+    for (group, label) in groups.iter().zip(labels) {
+        let stats = group.stats();
+        let distribution = stats.surplus_percentage();
+        let _ = write!(
+            rows,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>{}<td>{}</td></tr>",
+            escape(&label),
+            stats.order_count(),
+            percent(*distribution.p10()),
+            percent(*distribution.median()),
+            percent(*distribution.p90()),
+            Venue::ALL
+                .iter()
+                .map(|venue| format!(
+                    "<td>{}</td>",
+                    win_rate(*stats.venues()[venue.as_str()].win_rate())
+                ))
+                .collect::<String>(),
+            usd(*stats.total_net_surplus_usd()),
+        );
+    }
+
+    let body = format!(
+        r#"<section><h2>Pairs, last {} days</h2><p class="muted">Surplus percentiles over the minimum buy amount, and how often CoW beat each venue's quote</p><table><thead><tr><th>Pair</th><th>Orders</th><th>p10</th><th>Median</th><th>p90</th>{}<th>Net surplus</th></tr></thead><tbody>{}</tbody></table></section>"#,
+        params.days(),
+        Venue::ALL
+            .iter()
+            .map(|venue| format!("<th>Won vs {}</th>", venue_label(*venue)))
+            .collect::<String>(),
+        rows,
+    );
+
+    Ok(layout(
+        "Pairs",
+        &days_nav("/dashboard/pairs", params),
+        &body,
+    ))
+}
+
+// None when the order isn't stored
+pub async fn order_page(
+    client: &DynamoDbClient,
+    tokens: &TokenRegistry,
+    uid: &str,
+) -> eyre::Result<Option<String>> {
+    let Some(order) = client.get_order(&uid.to_lowercase()).await? else {
+        return Ok(None);
+    };
+    let order = tokens.with_tokens(order).await;
+    let sell = token_label(order.sell_token_info().as_ref(), order.order().sell_token());
+    let buy = token_label(order.buy_token_info().as_ref(), order.order().buy_token());
+    let o = order.order();
+
+    let mut facts = String::new();
+    for (name, value) in [
+        ("Owner", o.owner().to_string()),
+        ("Settled", time(*o.timestamp())),
+        ("Block", o.block_number().to_string()),
+        ("Sold", format!("{} {}", amount(*o.executed_sell()), sell)),
+        ("Minimum buy", format!("{} {}", amount(*o.min_buy()), buy)),
+        ("Bought", format!("{} {}", amount(*o.executed_buy()), buy)),
+        (
+            "Surplus",
+            format!(
+                "{} {} ({})",
+                amount(*o.net_surplus()),
+                buy,
+                percent(Some(*o.surplus_percentage()))
+            ),
+        ),
+        ("Sell value", usd_of(o, *o.sell_usd())),
+        ("Surplus value", usd_of(o, *o.net_surplus_usd())),
+        ("Analysis version", o.analysis_version().to_string()),
+    ] {
+        let _ = write!(facts, "<dt>{}</dt><dd>{}</dd>", name, escape(&value));
+    }
+
+    let mut quotes = String::new();
+    let mut bars = Vec::new();
+    for venue in Venue::ALL {
+        let quote = venue.quote_buy(o);
+        let (quoted, difference) = if quote == 0.0 {
+            ("no quote".to_string(), "".to_string())
+        } else {
+            bars.push((
+                venue_label(venue),
+                venue.executed_minus_quote(o) / quote * 100.0,
+            ));
+            (
+                format!("{} {}", amount(quote), buy),
+                format!("{} {}", amount(venue.executed_minus_quote(o)), buy),
+            )
+        };
+        let _ = write!(
+            quotes,
+            "<tr><td>{}</td><td>{}</td><td>{}</td>{}</tr>",
+            venue_label(venue),
+            escape(&quoted),
+            escape(&difference),
+            outcome_cell(venue.outcome(o)),
+        );
+    }
+
+    let body = format!(
+        r#"<section><h2 class="uid">{}</h2><dl>{}</dl></section><section><h2>Executed against each venue's quote</h2><p class="muted">How much more CoW's execution bought than the venue quoted, in percent of the quote</p>{}<table><thead><tr><th>Venue</th><th>Quoted buy</th><th>Executed minus quote</th><th>CoW</th></tr></thead><tbody>{}</tbody></table></section>"#,
+        escape(o.uid()),
+        facts,
+        if bars.is_empty() {
+            r#"<p class="muted">No venue quoted this order</p>"#.to_string()
+        } else {
+            chart::diverging_bars(&bars)
+        },
+        quotes,
+    );
+
+    Ok(Some(layout("Order", "", &body)))
+}
+
+// kept in step with renderRow in app.js
+const ORDER_HEADER: &str = "<tr><th>Settled</th><th>Pair</th><th>Sold</th><th>Bought</th><th>Surplus</th><th>Surplus value</th><th>vs 0x</th><th>vs CoW quote</th><th>vs Uniswap V3</th></tr>";
+
+fn order_row(order: &OrderWithTokens) -> String {
+    let o = order.order();
+    let sell = token_label(order.sell_token_info().as_ref(), o.sell_token());
+    let buy = token_label(order.buy_token_info().as_ref(), o.buy_token());
+
+    format!(
+        r#"<tr><td><a href="/dashboard/orders/{}">{}</a></td><td>{} → {}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>{}</tr>"#,
+        escape(o.uid()),
+        time(*o.timestamp()),
+        escape(&sell),
+        escape(&buy),
+        amount(*o.executed_sell()),
+        amount(*o.executed_buy()),
+        percent(Some(*o.surplus_percentage())),
+        usd_of(o, *o.net_surplus_usd()),
+        Venue::ALL
+            .iter()
+            .map(|venue| outcome_cell(venue.outcome(o)))
+            .collect::<String>(),
+    )
+}
+
+fn venue_rows(stats: &Stats) -> Vec<(&'static str, usize, usize, usize)> {
+    Venue::ALL
+        .iter()
+        .map(|venue| {
+            let venue_stats = &stats.venues()[venue.as_str()];
+            (
+                venue_label(*venue),
+                *venue_stats.won(),
+                *venue_stats.tied(),
+                *venue_stats.lost(),
+            )
+        })
+        .collect()
+}
+
+fn venue_label(venue: Venue) -> &'static str {
+    match venue {
+        Venue::Zerox => "0x",
+        Venue::CowsOwnQuote => "CoW quote",
+        Venue::Univ3Swap => "Uniswap V3",
+    }
+}
+
+fn outcome_cell(outcome: Option<Outcome>) -> String {
+    match outcome {
+        Some(Outcome::Won) => r#"<td class="won">won</td>"#.to_string(),
+        Some(Outcome::Lost) => r#"<td class="lost">lost</td>"#.to_string(),
+        Some(Outcome::Tied) => r#"<td class="tied">tied</td>"#.to_string(),
+        None => r#"<td class="muted">-</td>"#.to_string(),
+    }
+}
+
+// the symbol, or a shortened address for tokens that have none
+fn token_label(token: Option<&TokenInfo>, address: &str) -> String {
+    match token {
+        Some(token) if !token.symbol().is_empty() => token.symbol().to_string(),
+        _ if address.len() > 10 => format!("{}…{}", &address[..6], &address[address.len() - 4..]),
+        _ => address.to_string(),
+    }
+}
+
+fn card(title: &str, value: &str) -> String {
+    format!(
+        r#"<div class="card"><div class="muted">{}</div><div class="value">{}</div></div>"#,
+        title,
+        escape(value)
+    )
+}
+
+fn days_nav(path: &str, params: &DashboardParams) -> String {
+    [1, 7, 30, 90]
+        .iter()
+        .map(|days| {
+            let class = if *days == params.days() {
+                r#" class="active""#
+            } else {
+                ""
+            };
+            format!(r#"<a{} href="{}?days={}">{}d</a>"#, class, path, days, days)
+        })
+        .collect()
+}
+
+fn layout(title: &str, controls: &str, body: &str) -> String {
+    format!(
+        r#"<!doctype html><html lang="en"><head><meta charset="utf-8"><meta name="viewport" content="width=device-width, initial-scale=1"><title>{} · CoW quote comparison</title><link rel="stylesheet" href="/dashboard/assets/style.css"></head><body><header><h1>CoW quote comparison</h1><nav><a href="/dashboard">Overview</a><a href="/dashboard/pairs">Pairs</a></nav><div class="controls">{}</div></header><main>{}</main><script src="/dashboard/assets/app.js"></script></body></html>"#,
+        title, controls, body
+    )
+}
+
+fn percent(ratio: Option<f64>) -> String {
+    ratio.map_or("-".to_string(), |ratio| format!("{:.3}%", ratio * 100.0))
+}
+
+fn win_rate(rate: Option<f64>) -> String {
+    rate.map_or("-".to_string(), |rate| format!("{:.0}%", rate * 100.0))
+}
+
+fn usd(value: f64) -> String {
+    format!("${:.2}", value)
+}
+
+// orders CowSwap had no prices for have no USD value
+fn usd_of(order: &Order, value: f64) -> String {
+    if order.has_usd_valuation() {
+        usd(value)
+    } else {
+        "-".to_string()
+    }
+}
+
+fn amount(value: f64) -> String {
+    let decimals = if value.abs() >= 1000.0 { 2 } else { 6 };
+    format!("{:.*}", decimals, value)
+}
+
+fn time(timestamp: u64) -> String {
+    match timestamp {
+        0 => "-".to_string(),
+        timestamp => DateTime::from_timestamp(timestamp as i64, 0)
+            .map_or("-".to_string(), |time| {
+                time.format("%Y-%m-%d %H:%M:%S").to_string()
+            }),
+    }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,771 @@
+use crate::archive::ArchivePages;
+use crate::config::Config;
+use crate::order::Order;
+use crate::services::aws_dynamodb::{DynamoDbClient, OrderPages, OrderQuery};
+use parquet::{
+    basic::Compression,
+    data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type},
+    file::{metadata::KeyValue, properties::WriterProperties, writer::SerializedFileWriter},
+    schema::parser::parse_message_type,
+};
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+
+// parquet buffers this many orders before writing them out as one row group
+const ROW_GROUP_SIZE: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv",
+            ExportFormat::Jsonl => "application/x-ndjson",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Jsonl => "jsonl",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+}
+
+// the orders table, or the files the archiver moved expired orders to
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportSource {
+    #[default]
+    Table,
+    Archive,
+}
+
+impl FromStr for ExportSource {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        match s {
+            "table" => Ok(ExportSource::Table),
+            "archive" => Ok(ExportSource::Archive),
+            other => Err(eyre::eyre!(
+                "Unknown export source {}, expected table or archive",
+                other
+            )),
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> eyre::Result<Self> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            "parquet" => Ok(ExportFormat::Parquet),
+            other => Err(eyre::eyre!(
+                "Unknown export format {}, expected csv, jsonl or parquet",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum ColumnValue {
+    Text(fn(&Order) -> String),
+    UInt(fn(&Order) -> u64),
+    Float(fn(&Order) -> f64),
+}
+
+#[derive(Clone, Copy)]
+pub struct Column {
+    pub name: &'static str,
+    pub unit: &'static str,
+    pub value: ColumnValue,
+}
+
+// The export schema. Columns are only ever appended, so files from older exports line up.
+// `_raw` columns are integers in the token's smallest unit, `_tokens` columns the same amount
+// divided by 10^decimals, and `_ratio` columns are a difference divided by the minimum buy amount
+// (the executed buy amount when there is none).
+pub const COLUMNS: &[Column] = &[
+    Column {
+        name: "uid",
+        unit: "",
+        value: ColumnValue::Text(|o| o.uid().clone()),
+    },
+    Column {
+        name: "owner",
+        unit: "address",
+        value: ColumnValue::Text(|o| o.owner().clone()),
+    },
+    Column {
+        name: "block_number",
+        unit: "block",
+        value: ColumnValue::UInt(|o| *o.block_number()),
+    },
+    Column {
+        name: "timestamp",
+        unit: "unix seconds, 0 when unknown",
+        value: ColumnValue::UInt(|o| *o.timestamp()),
+    },
+    Column {
+        name: "sell_token",
+        unit: "address",
+        value: ColumnValue::Text(|o| o.sell_token().clone()),
+    },
+    Column {
+        name: "sell_decimals",
+        unit: "decimals",
+        value: ColumnValue::UInt(|o| *o.sell_decimals() as u64),
+    },
+    Column {
+        name: "sell_amount_raw",
+        unit: "sell token base units",
+        value: ColumnValue::Text(|o| o.sell_raw().clone()),
+    },
+    Column {
+        name: "sell_amount_tokens",
+        unit: "sell token",
+        value: ColumnValue::Float(|o| *o.sell()),
+    },
+    Column {
+        name: "executed_sell_amount_raw",
+        unit: "sell token base units",
+        value: ColumnValue::Text(|o| o.executed_sell_raw().clone()),
+    },
+    Column {
+        name: "executed_sell_amount_tokens",
+        unit: "sell token",
+        value: ColumnValue::Float(|o| *o.executed_sell()),
+    },
+    Column {
+        name: "buy_token",
+        unit: "address",
+        value: ColumnValue::Text(|o| o.buy_token().clone()),
+    },
+    Column {
+        name: "buy_decimals",
+        unit: "decimals",
+        value: ColumnValue::UInt(|o| *o.buy_decimals() as u64),
+    },
+    Column {
+        name: "min_buy_amount_raw",
+        unit: "buy token base units",
+        value: ColumnValue::Text(|o| o.min_buy_raw().clone()),
+    },
+    Column {
+        name: "min_buy_amount_tokens",
+        unit: "buy token",
+        value: ColumnValue::Float(|o| *o.min_buy()),
+    },
+    Column {
+        name: "executed_buy_amount_raw",
+        unit: "buy token base units",
+        value: ColumnValue::Text(|o| o.executed_buy_raw().clone()),
+    },
+    Column {
+        name: "executed_buy_amount_tokens",
+        unit: "buy token",
+        value: ColumnValue::Float(|o| *o.executed_buy()),
+    },
+    Column {
+        name: "net_surplus_tokens",
+        unit: "buy token",
+        value: ColumnValue::Float(|o| *o.net_surplus()),
+    },
+    Column {
+        name: "surplus_ratio",
+        unit: "ratio",
+        value: ColumnValue::Float(|o| *o.surplus_percentage()),
+    },
+    Column {
+        name: "zerox_quote_buy_amount_raw",
+        unit: "buy token base units",
+        value: ColumnValue::Text(|o| o.zerox_quote_buy_raw().clone()),
+    },
+    Column {
+        name: "zerox_quote_buy_amount_tokens",
+        unit: "buy token",
+        value: ColumnValue::Float(|o| *o.zerox_quote_buy()),
+    },
+    Column {
+        name: "executed_minus_zerox_tokens",
+        unit: "buy token",
+        value: ColumnValue::Float(|o| *o.compared_executed_with_zerox_quote()),
+    },
+    Column {
+        name: "executed_vs_zerox_ratio",
+        unit: "ratio",
+        value: ColumnValue::Float(|o| *o.compared_with_zerox_percentage()),
+    },
+    Column {
+        name: "cows_own_quote_buy_amount_raw",
+        unit: "buy token base units",
+        value: ColumnValue::Text(|o| o.cows_own_quote_buy_raw().clone()),
+    },
+    Column {
+        name: "cows_own_quote_buy_amount_tokens",
+        unit: "buy token",
+        value: ColumnValue::Float(|o| *o.cows_own_quote_buy()),
+    },
+    Column {
+        name: "executed_minus_cows_own_quote_tokens",
+        unit: "buy token",
+        value: ColumnValue::Float(|o| *o.compared_executed_with_cows_own_quote()),
+    },
+    Column {
+        name: "executed_vs_cows_own_quote_ratio",
+        unit: "ratio",
+        value: ColumnValue::Float(|o| *o.compared_with_cows_own_quote_percentage()),
+    },
+    Column {
+        name: "univ3_swap_buy_amount_raw",
+        unit: "buy token base units",
+        value: ColumnValue::Text(|o| o.univ3_swap_buy_raw().clone()),
+    },
+    Column {
+        name: "univ3_swap_buy_amount_tokens",
+        unit: "buy token",
+        value: ColumnValue::Float(|o| *o.univ3_swap_buy()),
+    },
+    Column {
+        name: "executed_minus_univ3_swap_tokens",
+        unit: "buy token",
+        value: ColumnValue::Float(|o| *o.compared_executed_with_univ3_swap()),
+    },
+    Column {
+        name: "executed_vs_univ3_swap_ratio",
+        unit: "ratio",
+        value: ColumnValue::Float(|o| *o.compared_with_univ3_swap_percentage()),
+    },
+    Column {
+        name: "analysis_version",
+        unit: "",
+        value: ColumnValue::UInt(|o| *o.analysis_version() as u64),
+    },
+    Column {
+        name: "sell_token_usd_price",
+        unit: "USD per sell token, 0 when unknown",
+        value: ColumnValue::Float(|o| *o.sell_token_usd_price()),
+    },
+    Column {
+        name: "buy_token_usd_price",
+        unit: "USD per buy token, 0 when unknown",
+        value: ColumnValue::Float(|o| *o.buy_token_usd_price()),
+    },
+    Column {
+        name: "sell_amount_usd",
+        unit: "USD",
+        value: ColumnValue::Float(|o| *o.sell_usd()),
+    },
+    Column {
+        name: "net_surplus_usd",
+        unit: "USD",
+        value: ColumnValue::Float(|o| *o.net_surplus_usd()),
+    },
+];
+
+// one JSON object per order, with its keys in column order
+struct ExportRow<'a>(&'a Order);
+
+impl Serialize for ExportRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(COLUMNS.len()))?;
+        for column in COLUMNS {
+            match column.value {
+                ColumnValue::Text(value) => map.serialize_entry(column.name, &value(self.0))?,
+                ColumnValue::UInt(value) => map.serialize_entry(column.name, &value(self.0))?,
+                ColumnValue::Float(value) => map.serialize_entry(column.name, &value(self.0))?,
+            }
+        }
+        map.end()
+    }
+}
+
+pub enum OrderExporter<W: Write + Send> {
+    Csv(csv::Writer<W>),
+    Jsonl(W),
+    Parquet {
+        writer: SerializedFileWriter<W>,
+        rows: Vec<Order>,
+    },
+}
+
+impl<W: Write + Send> OrderExporter<W> {
+    pub fn new(format: ExportFormat, out: W) -> eyre::Result<Self> {
+        Ok(match format {
+            ExportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(out);
+                writer.write_record(COLUMNS.iter().map(|column| column.name))?;
+                OrderExporter::Csv(writer)
+            }
+            ExportFormat::Jsonl => OrderExporter::Jsonl(out),
+            ExportFormat::Parquet => {
+                let properties = WriterProperties::builder()
+                    .set_compression(Compression::SNAPPY)
+                    .set_key_value_metadata(Some(
+                        COLUMNS
+                            .iter()
+                            .filter(|column| !column.unit.is_empty())
+                            .map(|column| {
+                                KeyValue::new(
+                                    format!("unit.{}", column.name),
+                                    column.unit.to_string(),
+                                )
+                            })
+                            .collect(),
+                    ))
+                    .build();
+
+                OrderExporter::Parquet {
+                    writer: SerializedFileWriter::new(
+                        out,
+                        Arc::new(parse_message_type(&parquet_schema())?),
+                        Arc::new(properties),
+                    )?,
+                    rows: Vec::new(),
+                }
+            }
+        })
+    }
+
+    pub fn write(&mut self, order: &Order) -> eyre::Result<()> {
+        match self {
+            OrderExporter::Csv(writer) => {
+                writer.write_record(COLUMNS.iter().map(|column| match column.value {
+                    ColumnValue::Text(value) => value(order),
+                    ColumnValue::UInt(value) => value(order).to_string(),
+                    ColumnValue::Float(value) => value(order).to_string(),
+                }))?;
+            }
+            OrderExporter::Jsonl(out) => {
+                serde_json::to_writer(&mut *out, &ExportRow(order))?;
+                out.write_all(b"\n")?;
+            }
+            OrderExporter::Parquet { writer, rows } => {
+                rows.push(order.clone());
+                if rows.len() >= ROW_GROUP_SIZE {
+                    write_row_group(writer, rows)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // pushes everything written so far to the underlying writer, apart from a partial row group
+    pub fn flush(&mut self) -> eyre::Result<()> {
+        match self {
+            OrderExporter::Csv(writer) => writer.flush()?,
+            OrderExporter::Jsonl(out) => out.flush()?,
+            OrderExporter::Parquet { .. } => (),
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> eyre::Result<()> {
+        match self {
+            OrderExporter::Csv(writer) => {
+                writer
+                    .into_inner()
+                    .map_err(|e| eyre::eyre!("Failed to flush csv: {}", e))?
+                    .flush()?;
+            }
+            OrderExporter::Jsonl(mut out) => out.flush()?,
+            OrderExporter::Parquet {
+                mut writer,
+                mut rows,
+            } => {
+                if !rows.is_empty() {
+                    write_row_group(&mut writer, &mut rows)?;
+                }
+                writer.into_inner()?.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parquet_schema() -> String {
+    let fields = COLUMNS
+        .iter()
+        .map(|column| match column.value {
+            ColumnValue::Text(_) => format!("REQUIRED BYTE_ARRAY {} (UTF8);", column.name),
+            ColumnValue::UInt(_) => {
+                format!("REQUIRED INT64 {} (INTEGER(64,false));", column.name)
+            }
+            ColumnValue::Float(_) => format!("REQUIRED DOUBLE {};", column.name),
+        })
+        .collect::<Vec<_>>();
+
+    format!("message order {{ {} }}", fields.join(" "))
+}
+
+fn write_row_group<W: Write + Send>(
+    writer: &mut SerializedFileWriter<W>,
+    rows: &mut Vec<Order>,
+) -> eyre::Result<()> {
+    let mut row_group = writer.next_row_group()?;
+    for column in COLUMNS {
+        let mut column_writer = row_group
+            .next_column()?
+            .ok_or_else(|| eyre::eyre!("Parquet schema has no column {}", column.name))?;
+
+        match column.value {
+            ColumnValue::Text(value) => {
+                let values = rows
+                    .iter()
+                    .map(|order| ByteArray::from(value(order).into_bytes()))
+                    .collect::<Vec<_>>();
+                column_writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, None, None)?;
+            }
+            ColumnValue::UInt(value) => {
+                // stored as the bit pattern, the column is annotated as unsigned
+                let values = rows
+                    .iter()
+                    .map(|order| value(order) as i64)
+                    .collect::<Vec<_>>();
+                column_writer
+                    .typed::<Int64Type>()
+                    .write_batch(&values, None, None)?;
+            }
+            ColumnValue::Float(value) => {
+                let values = rows.iter().map(value).collect::<Vec<_>>();
+                column_writer
+                    .typed::<DoubleType>()
+                    .write_batch(&values, None, None)?;
+            }
+        }
+        column_writer.close()?;
+    }
+    row_group.close()?;
+
+    rows.clear();
+    Ok(())
+}
+
+// An in-memory writer whose bytes can be taken out while the exporter still owns it
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Where an export reads its orders from
+pub enum OrderSource {
+    Table(OrderPages),
+    Archive(ArchivePages),
+    #[cfg(test)]
+    Pages(std::vec::IntoIter<eyre::Result<Vec<Order>>>),
+}
+
+impl OrderSource {
+    pub async fn open(
+        config: &Config,
+        client: &DynamoDbClient,
+        source: ExportSource,
+        query: OrderQuery,
+    ) -> eyre::Result<Self> {
+        Ok(match source {
+            ExportSource::Table => OrderSource::Table(OrderPages::new(client.clone(), query)),
+            ExportSource::Archive => OrderSource::Archive(ArchivePages::new(config, query).await?),
+        })
+    }
+
+    async fn next_page(&mut self) -> eyre::Result<Option<Vec<Order>>> {
+        match self {
+            OrderSource::Table(pages) => pages.next_page().await,
+            OrderSource::Archive(pages) => pages.next_page().await,
+            #[cfg(test)]
+            OrderSource::Pages(pages) => pages.next().transpose(),
+        }
+    }
+}
+
+// Writes every order of `source` page by page, calling `after_page` once each page is flushed.
+// Exporting stops early once `after_page` returns false.
+async fn export_pages<W, F, Fut>(
+    mut source: OrderSource,
+    format: ExportFormat,
+    out: W,
+    mut after_page: F,
+) -> eyre::Result<usize>
+where
+    W: Write + Send,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let mut exporter = OrderExporter::new(format, out)?;
+    let mut count = 0;
+
+    while let Some(orders) = source.next_page().await? {
+        for order in &orders {
+            exporter.write(order)?;
+        }
+        exporter.flush()?;
+        count += orders.len();
+
+        if !after_page().await {
+            return Ok(count);
+        }
+    }
+    exporter.finish()?;
+
+    Ok(count)
+}
+
+pub async fn export_orders(
+    config: &Config,
+    source: ExportSource,
+    query: OrderQuery,
+    format: ExportFormat,
+    output: Option<PathBuf>,
+) -> eyre::Result<()> {
+    let client = DynamoDbClient::new(config).await?;
+    let source = OrderSource::open(config, &client, source, query).await?;
+    let out: Box<dyn Write + Send> = match &output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(std::io::stdout())),
+    };
+
+    let count = export_pages(source, format, out, || async { true }).await?;
+
+    // stdout may be the export itself
+    eprintln!("Exported {} orders", count);
+    Ok(())
+}
+
+// Streams an export as chunks over `tx`, at most one row group or page is held in memory.
+// A failure is sent as the last chunk, and the export stops when the receiver goes away.
+pub async fn stream_orders(
+    source: OrderSource,
+    format: ExportFormat,
+    tx: mpsc::Sender<std::io::Result<Vec<u8>>>,
+) {
+    let buffer = SharedBuffer::default();
+    let result = export_pages(source, format, buffer.clone(), || {
+        let chunk = buffer.take();
+        let tx = tx.clone();
+        async move { chunk.is_empty() || tx.send(Ok(chunk)).await.is_ok() }
+    })
+    .await;
+
+    let last = match result {
+        Ok(_) => Ok(buffer.take()),
+        Err(e) => Err(std::io::Error::other(e.to_string())),
+    };
+    let _ = tx.send(last).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+    use serde_json::{json, Value};
+
+    fn order(uid: &str, block_number: u64) -> Order {
+        serde_json::from_value(json!({
+            "uid": uid,
+            "owner": "0x01",
+            "sell_token": "0x02",
+            "buy_token": "0x03",
+            "block_number": block_number,
+            "timestamp": 1_700_000_000,
+            "sell": 1.5,
+            "sell_usd": 2500.25,
+        }))
+        .unwrap()
+    }
+
+    fn export(format: ExportFormat, orders: &[Order]) -> Vec<u8> {
+        let buffer = SharedBuffer::default();
+        let mut exporter = OrderExporter::new(format, buffer.clone()).unwrap();
+        for order in orders {
+            exporter.write(order).unwrap();
+        }
+        exporter.finish().unwrap();
+        buffer.take()
+    }
+
+    fn column(name: &str) -> usize {
+        COLUMNS
+            .iter()
+            .position(|column| column.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn csv_has_a_header_then_a_row_per_order_in_column_order() {
+        let orders = [order("0xa", 1), order("0xb", 2)];
+        let csv = export(ExportFormat::Csv, &orders);
+        let mut reader = csv::Reader::from_reader(csv.as_slice());
+
+        let header = reader.headers().unwrap().clone();
+        assert!(header.iter().eq(COLUMNS.iter().map(|column| column.name)));
+
+        let rows = reader.records().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(rows.len(), 2);
+        for (row, order) in rows.iter().zip(&orders) {
+            assert_eq!(row.len(), COLUMNS.len());
+            assert_eq!(&row[column("uid")], order.uid());
+            assert_eq!(
+                row[column("block_number")],
+                order.block_number().to_string()
+            );
+            assert_eq!(&row[column("sell_amount_usd")], "2500.25");
+        }
+    }
+
+    #[test]
+    fn jsonl_has_an_object_per_line_keyed_by_column() {
+        let jsonl = export(ExportFormat::Jsonl, &[order("0xa", 1), order("0xb", 2)]);
+        let lines = String::from_utf8(jsonl).unwrap();
+        assert_eq!(lines.lines().count(), 2);
+
+        // the parsed map sorts its keys, so their order is checked on the text
+        for line in lines.lines() {
+            let mut rest = line;
+            for column in COLUMNS {
+                let key = format!("\"{}\":", column.name);
+                let at = rest
+                    .find(&key)
+                    .unwrap_or_else(|| panic!("{} is missing or out of order", column.name));
+                rest = &rest[at + key.len()..];
+            }
+        }
+        let rows = lines
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Map<String, Value>>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(rows[0].len(), COLUMNS.len());
+        assert_eq!(rows[1]["uid"], "0xb");
+        assert_eq!(rows[1]["block_number"], 2);
+        assert_eq!(rows[1]["sell_amount_usd"], 2500.25);
+    }
+
+    #[test]
+    fn parquet_reads_back() {
+        let orders = [order("0xa", 1), order("0xb", u64::MAX)];
+        let path = std::env::temp_dir().join(format!("export_{}.parquet", std::process::id()));
+        std::fs::write(&path, export(ExportFormat::Parquet, &orders)).unwrap();
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let metadata = reader.metadata().file_metadata();
+        assert_eq!(metadata.num_rows(), 2);
+        let fields = metadata.schema_descr().columns().to_vec();
+        assert!(fields
+            .iter()
+            .map(|field| field.name())
+            .eq(COLUMNS.iter().map(|column| column.name)));
+        assert!(metadata
+            .key_value_metadata()
+            .unwrap()
+            .iter()
+            .any(|kv| kv.key == "unit.sell_amount_usd" && kv.value.as_deref() == Some("USD")));
+
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows.len(), 2);
+        for (row, order) in rows.iter().zip(&orders) {
+            assert_eq!(row.get_string(column("uid")).unwrap(), order.uid());
+            assert_eq!(
+                row.get_ulong(column("block_number")).unwrap(),
+                *order.block_number()
+            );
+            assert_eq!(row.get_double(column("sell_amount_usd")).unwrap(), 2500.25);
+        }
+    }
+
+    async fn stream(pages: Vec<eyre::Result<Vec<Order>>>) -> Vec<std::io::Result<Vec<u8>>> {
+        let (tx, mut rx) = mpsc::channel(8);
+        stream_orders(
+            OrderSource::Pages(pages.into_iter()),
+            ExportFormat::Jsonl,
+            tx,
+        )
+        .await;
+
+        let mut chunks = Vec::new();
+        while let Some(chunk) = rx.recv().await {
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    fn lines(chunk: &[u8]) -> usize {
+        chunk.iter().filter(|byte| **byte == b'\n').count()
+    }
+
+    #[tokio::test]
+    async fn stream_sends_a_chunk_per_page() {
+        let chunks = stream(vec![
+            Ok(vec![order("0xa", 1), order("0xb", 2)]),
+            Ok(vec![]),
+            Ok(vec![order("0xc", 3)]),
+        ])
+        .await;
+
+        let chunks = chunks
+            .into_iter()
+            .collect::<std::io::Result<Vec<_>>>()
+            .unwrap();
+        // the empty page sends nothing, and the end sends what is left, here nothing
+        assert_eq!(
+            chunks.iter().map(|chunk| lines(chunk)).collect::<Vec<_>>(),
+            [2, 1, 0]
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_sends_a_failure_as_the_last_chunk() {
+        let chunks = stream(vec![
+            Ok(vec![order("0xa", 1)]),
+            Err(eyre::eyre!("table went away")),
+            Ok(vec![order("0xb", 2)]),
+        ])
+        .await;
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(lines(chunks[0].as_ref().unwrap()), 1);
+        let error = chunks[1].as_ref().unwrap_err();
+        assert_eq!(error.to_string(), "table went away");
+    }
+}
@@ -0,0 +1,56 @@
+use crate::order::Order;
+use crate::tokens::OrderWithTokens;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+// how far a subscriber may fall behind before it starts missing orders
+const FEED_CAPACITY: usize = 1024;
+
+// Every order the ingestion stores, published as it is stored
+#[derive(Clone)]
+pub struct OrderFeed(broadcast::Sender<Arc<OrderWithTokens>>);
+
+impl Default for OrderFeed {
+    fn default() -> Self {
+        Self(broadcast::channel(FEED_CAPACITY).0)
+    }
+}
+
+impl OrderFeed {
+    pub fn publish(&self, order: OrderWithTokens) {
+        // no subscribers is not an error
+        let _ = self.0.send(Arc::new(order));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<OrderWithTokens>> {
+        self.0.subscribe()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FeedFilter {
+    pub sell_token: Option<String>,
+    pub buy_token: Option<String>,
+    // orders without a USD valuation never pass a size filter
+    pub min_sell_usd: Option<f64>,
+    pub max_sell_usd: Option<f64>,
+}
+
+impl FeedFilter {
+    pub fn matches(&self, order: &Order) -> bool {
+        let token = |filter: &Option<String>, token: &str| {
+            filter
+                .as_ref()
+                .is_none_or(|filter| filter.eq_ignore_ascii_case(token))
+        };
+        let sized = self.min_sell_usd.is_none() && self.max_sell_usd.is_none()
+            || order.has_usd_valuation()
+                && self.min_sell_usd.is_none_or(|min| *order.sell_usd() >= min)
+                && self.max_sell_usd.is_none_or(|max| *order.sell_usd() <= max);
+
+        token(&self.sell_token, order.sell_token())
+            && token(&self.buy_token, order.buy_token())
+            && sized
+    }
+}
@@ -0,0 +1,218 @@
+use crate::config::Config;
+use crate::ingestion::{IngestionService, IngestionState};
+use crate::rpc::Rpc;
+use crate::services::{aws_dynamodb::new_client, aws_dynamodb_admin::describe_table};
+use aws_sdk_dynamodb::Client;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Serialize)]
+pub struct Liveness {
+    status: &'static str,
+    environment: &'static str,
+    uptime_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    name: &'static str,
+    ok: bool,
+    latency_ms: u64,
+    detail: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    ready: bool,
+    checks: Vec<Check>,
+    // reported only, a quiet market is no reason to take the API out of rotation
+    trade_freshness: Check,
+}
+
+impl Readiness {
+    pub fn ready(&self) -> bool {
+        self.ready
+    }
+}
+
+#[derive(Clone)]
+pub struct HealthProbe {
+    config: Config,
+    ingestion: IngestionService,
+    dynamodb: Client,
+    // the binary doesn't come and go while running, so it is checked once at startup
+    anvil: Check,
+    started: Instant,
+    // "aws_ec2" or "local"
+    environment: &'static str,
+}
+
+impl HealthProbe {
+    pub async fn new(
+        config: Config,
+        ingestion: IngestionService,
+        environment: &'static str,
+    ) -> Self {
+        let anvil = check("anvil", config.health_check_timeout(), check_anvil()).await;
+        Self {
+            dynamodb: new_client(&config).await,
+            config,
+            ingestion,
+            anvil,
+            started: Instant::now(),
+            environment,
+        }
+    }
+
+    // the process is up and answering, dependencies are left to readiness
+    pub fn live(&self) -> Liveness {
+        Liveness {
+            status: "ok",
+            environment: self.environment,
+            uptime_secs: self.started.elapsed().as_secs(),
+        }
+    }
+
+    pub async fn ready(&self) -> Readiness {
+        let timeout = self.config.health_check_timeout();
+        let (rpc, dynamodb) = tokio::join!(
+            check("rpc", timeout, check_rpc(&self.config)),
+            check(
+                "dynamodb",
+                timeout,
+                check_dynamodb(&self.dynamodb, self.config.orders_table())
+            ),
+        );
+        let checks = vec![rpc, self.anvil.clone(), dynamodb];
+
+        Readiness {
+            ready: checks.iter().all(|check| check.ok),
+            checks,
+            trade_freshness: self.check_trade_freshness(),
+        }
+    }
+
+    fn check_trade_freshness(&self) -> Check {
+        let status = self.ingestion.status();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        // settlements land every few blocks, a running listener that saw none for this long is stuck
+        let max_age = self.config.max_trade_age().as_secs();
+
+        let (ok, detail) = match (status.state(), status.last_trade_at()) {
+            // a stopped listener is a choice, reads are still served
+            (IngestionState::Stopped, _) => (true, "ingestion is stopped".to_string()),
+            (_, Some(at)) => {
+                let age = now.saturating_sub(*at);
+                (age <= max_age, format!("last trade seen {} secs ago", age))
+            }
+            (_, None) => {
+                let uptime = status.uptime_secs().unwrap_or_default();
+                (
+                    uptime <= max_age,
+                    format!("no trade seen in {} secs of ingestion", uptime),
+                )
+            }
+        };
+
+        Check {
+            name: "trade_freshness",
+            ok,
+            latency_ms: 0,
+            detail,
+        }
+    }
+}
+
+// a dependency that takes longer than the timeout to answer counts as down
+async fn check(
+    name: &'static str,
+    timeout: Duration,
+    probe: impl Future<Output = eyre::Result<String>>,
+) -> Check {
+    let started = Instant::now();
+    let result = match tokio::time::timeout(timeout, probe).await {
+        Ok(result) => result,
+        Err(_) => Err(eyre::eyre!("No answer within {} secs", timeout.as_secs())),
+    };
+
+    let (ok, detail) = match result {
+        Ok(detail) => (true, detail),
+        Err(e) => (false, e.to_string()),
+    };
+    Check {
+        name,
+        ok,
+        latency_ms: started.elapsed().as_millis() as u64,
+        detail,
+    }
+}
+
+// every endpoint is asked, and as many hosts as the quorum need to answer
+async fn check_rpc(config: &Config) -> eyre::Result<String> {
+    let rpc = Rpc::new(config);
+    let checks = rpc.check().await;
+
+    let answered = checks
+        .iter()
+        .filter(|(_, result)| result.is_ok())
+        .map(|(endpoint, _)| endpoint.host())
+        .collect::<HashSet<_>>()
+        .len();
+    let detail = checks
+        .iter()
+        .map(|(endpoint, result)| match result {
+            Ok(block) => format!("{}: block {}", endpoint, block),
+            Err(e) => format!("{}: {}", endpoint, e),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    if answered < rpc.quorum() {
+        return Err(eyre::eyre!(
+            "{} endpoint hosts answered, {} needed: {}",
+            answered,
+            rpc.quorum(),
+            detail
+        ));
+    }
+    Ok(detail)
+}
+
+// the Uniswap quote forks mainnet with anvil, so a missing binary fails every order
+async fn check_anvil() -> eyre::Result<String> {
+    let output = tokio::process::Command::new("anvil")
+        .arg("--version")
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| eyre::eyre!("Failed to run anvil: {}", e))?;
+
+    if !output.status.success() {
+        return Err(eyre::eyre!("anvil --version exited with {}", output.status));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string())
+}
+
+async fn check_dynamodb(client: &Client, table_name: &str) -> eyre::Result<String> {
+    let table = describe_table(client, table_name)
+        .await?
+        .ok_or_else(|| eyre::eyre!("Table {} is missing", table_name))?;
+    let status = table
+        .table_status()
+        .map(|s| s.as_str())
+        .unwrap_or("UNKNOWN");
+
+    if status != "ACTIVE" {
+        return Err(eyre::eyre!("Table {} is {}", table_name, status));
+    }
+    Ok(format!("table {} is {}", table_name, status))
+}
@@ -0,0 +1,424 @@
+use crate::config::Config;
+use crate::constant;
+use crate::feed::OrderFeed;
+use crate::metrics;
+use crate::rpc::{block_timestamp, Endpoint, Rpc};
+use crate::services::{
+    aws_dynamodb::{DynamoDbClient, WriteOutcome, WritePolicy},
+    aws_dynamodb_raw_events::{RawEventStore, RecordedTrade},
+    http_client::ApiClient,
+};
+use crate::tokens::TokenRegistry;
+use crate::{analyze_order, InFlightOrders, Settlement, TradeEvent};
+use ethers::{
+    contract::EthEvent,
+    providers::{Ipc, Provider, PubsubClient, Ws},
+    types::{Address, Filter},
+};
+use futures::StreamExt;
+use getset::Getters;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IngestionState {
+    Stopped,
+    Running,
+    // the trade stream dropped, a new connection is on its way
+    Reconnecting,
+}
+
+#[derive(Debug, Serialize, Getters)]
+#[getset(get = "pub")]
+pub struct IngestionStatus {
+    state: IngestionState,
+    // unix seconds
+    started_at: Option<u64>,
+    uptime_secs: Option<u64>,
+    // None when running until stopped
+    stops_at: Option<u64>,
+    last_block: Option<u64>,
+    // unix seconds of the last trade seen on the stream
+    last_trade_at: Option<u64>,
+    processed: u64,
+    skipped: u64,
+    failed: u64,
+    in_flight: usize,
+    last_error: Option<String>,
+}
+
+struct Run {
+    // tells a finished run apart from the one that replaced it
+    generation: u64,
+    started_at: SystemTime,
+    started: Instant,
+    duration: Option<Duration>,
+    handle: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct Counters {
+    last_block: AtomicU64,
+    last_trade_at: AtomicU64,
+    processed: AtomicU64,
+    skipped: AtomicU64,
+    failed: AtomicU64,
+}
+
+#[derive(Default)]
+struct Supervision {
+    run: Option<Run>,
+    state: Option<IngestionState>,
+    generation: u64,
+    last_error: Option<String>,
+}
+
+struct Inner {
+    config: Config,
+    in_flight: InFlightOrders,
+    feed: OrderFeed,
+    tokens: TokenRegistry,
+    counters: Counters,
+    supervision: Mutex<Supervision>,
+    // woken whenever a run ends, see stopped
+    run_ended: Notify,
+}
+
+// The settlement listener, of which at most one runs at a time. It reconnects whenever the
+// trade stream drops, until it is stopped or its duration is up.
+#[derive(Clone)]
+pub struct IngestionService(Arc<Inner>);
+
+impl IngestionService {
+    pub fn new(
+        config: Config,
+        in_flight: InFlightOrders,
+        feed: OrderFeed,
+        tokens: TokenRegistry,
+    ) -> Self {
+        Self(Arc::new(Inner {
+            config,
+            in_flight,
+            feed,
+            tokens,
+            counters: Counters::default(),
+            supervision: Mutex::new(Supervision::default()),
+            run_ended: Notify::new(),
+        }))
+    }
+
+    // Err when it is already running
+    pub fn start(&self, duration: Option<Duration>) -> eyre::Result<IngestionStatus> {
+        {
+            let mut supervision = self.0.supervision.lock().unwrap();
+            if supervision.run.is_some() {
+                return Err(eyre::eyre!("Ingestion is already running"));
+            }
+
+            supervision.generation += 1;
+            supervision.state = Some(IngestionState::Running);
+            supervision.last_error = None;
+            let generation = supervision.generation;
+            let service = self.clone();
+            supervision.run = Some(Run {
+                generation,
+                started_at: SystemTime::now(),
+                started: Instant::now(),
+                duration,
+                handle: tokio::spawn(async move {
+                    match duration {
+                        Some(duration) => {
+                            // use timeout to end the service
+                            let _ = tokio::time::timeout(duration, service.supervise()).await;
+                            println!("Service has ended after {} secs", duration.as_secs());
+                        }
+                        None => service.supervise().await,
+                    }
+                    service.finish(generation);
+                }),
+            });
+        }
+
+        Ok(self.status())
+    }
+
+    pub fn stop(&self) -> IngestionStatus {
+        {
+            let mut supervision = self.0.supervision.lock().unwrap();
+            if let Some(run) = supervision.run.take() {
+                // orders already being analysed are left to finish
+                run.handle.abort();
+                println!("Service has been stopped");
+            }
+            supervision.state = None;
+        }
+        self.0.run_ended.notify_waiters();
+
+        self.status()
+    }
+
+    // resolves once no run is left, after a stop or when the duration is up
+    pub async fn stopped(&self) {
+        loop {
+            // created before checking, so an end in between isn't missed
+            let run_ended = self.0.run_ended.notified();
+            if self.0.supervision.lock().unwrap().run.is_none() {
+                return;
+            }
+            run_ended.await;
+        }
+    }
+
+    pub fn status(&self) -> IngestionStatus {
+        let supervision = self.0.supervision.lock().unwrap();
+        let counters = &self.0.counters;
+        let run = supervision.run.as_ref();
+
+        IngestionStatus {
+            state: supervision.state.unwrap_or(IngestionState::Stopped),
+            started_at: run.map(|run| unix(run.started_at)),
+            uptime_secs: run.map(|run| run.started.elapsed().as_secs()),
+            stops_at: run.and_then(|run| run.duration.map(|d| unix(run.started_at + d))),
+            last_block: match counters.last_block.load(Ordering::Relaxed) {
+                0 => None,
+                block => Some(block),
+            },
+            last_trade_at: match counters.last_trade_at.load(Ordering::Relaxed) {
+                0 => None,
+                at => Some(at),
+            },
+            processed: counters.processed.load(Ordering::Relaxed),
+            skipped: counters.skipped.load(Ordering::Relaxed),
+            failed: counters.failed.load(Ordering::Relaxed),
+            in_flight: self.0.in_flight.len(),
+            last_error: supervision.last_error.clone(),
+        }
+    }
+
+    fn finish(&self, generation: u64) {
+        let mut supervision = self.0.supervision.lock().unwrap();
+        if supervision
+            .run
+            .as_ref()
+            .is_some_and(|run| run.generation == generation)
+        {
+            supervision.run = None;
+            supervision.state = None;
+        }
+        self.0.run_ended.notify_waiters();
+    }
+
+    fn set_state(&self, state: IngestionState, error: Option<String>) {
+        let mut supervision = self.0.supervision.lock().unwrap();
+        supervision.state = Some(state);
+        if error.is_some() {
+            supervision.last_error = error;
+        }
+    }
+
+    async fn supervise(&self) {
+        // waits between reconnects double from the first up to the last
+        let first_delay = self.0.config.reconnect_delay();
+        let max_delay = self.0.config.max_reconnect_delay();
+        let mut delay = first_delay;
+
+        loop {
+            let started = Instant::now();
+            let error = match self.run().await {
+                Ok(()) => "Trade stream ended".to_string(),
+                Err(e) => e.to_string(),
+            };
+
+            // a connection that held up for a while starts the backoff over
+            if started.elapsed() > max_delay {
+                delay = first_delay;
+            }
+            eprintln!("{}, reconnecting in {} secs", error, delay.as_secs());
+            metrics::WS_RECONNECTS.inc();
+            self.set_state(IngestionState::Reconnecting, Some(error));
+
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(max_delay);
+        }
+    }
+
+    // Streams trades from the healthiest WebSocket or IPC endpoint. An endpoint whose stream
+    // ends is passed over on the next connection, when there is another.
+    async fn run(&self) -> eyre::Result<()> {
+        let rpc = Rpc::new(&self.0.config);
+        let endpoint = rpc
+            .pubsub_endpoint()
+            .ok_or_else(|| eyre::eyre!("No ws:// or IPC endpoint to stream trades from"))?;
+
+        let result = match &endpoint {
+            Endpoint::Ws(url) => match Ws::connect(url.as_str()).await {
+                Ok(ws) => self.listen(&rpc, Provider::new(ws)).await,
+                Err(e) => Err(e.into()),
+            },
+            Endpoint::Ipc(path) => match Ipc::connect(path).await {
+                Ok(ipc) => self.listen(&rpc, Provider::new(ipc)).await,
+                Err(e) => Err(e.into()),
+            },
+            Endpoint::Http(_) => unreachable!("pubsub endpoints are ws or ipc"),
+        };
+        rpc.mark_failed(&endpoint);
+        result.map_err(|e| eyre::eyre!("{}: {}", endpoint, e))
+    }
+
+    async fn listen<P: PubsubClient + 'static>(
+        &self,
+        rpc: &Rpc,
+        provider: Provider<P>,
+    ) -> eyre::Result<()> {
+        let config = &self.0.config;
+        let provider = Arc::new(provider);
+        let block_provider = Arc::new(rpc.quorum_provider());
+        let api_client = ApiClient::new(config);
+
+        let aws_client = DynamoDbClient::new(config).await?;
+        let raw_events = RawEventStore::new(config).await?;
+
+        let settlement_contract = constant::GPv2SETTLEMENT.parse::<Address>()?;
+        let trade_filter = Filter::new().address(settlement_contract);
+
+        let trade_event = TradeEvent::new::<_, Provider<P>>(trade_filter, Arc::clone(&provider));
+        let mut stream = trade_event.stream().await?.with_meta();
+        self.set_state(IngestionState::Running, None);
+
+        while let Some(event) = stream.next().await {
+            let (trade, meta) = event?;
+            metrics::TRADES_SEEN.inc();
+            let order_uid = trade.order_uid.clone();
+            let block_number = meta.block_number.as_u64();
+            self.0
+                .counters
+                .last_block
+                .fetch_max(block_number, Ordering::Relaxed);
+            self.0
+                .counters
+                .last_trade_at
+                .store(unix(SystemTime::now()), Ordering::Relaxed);
+
+            let Some(in_flight_guard) = self.0.in_flight.claim(&order_uid.to_string()) else {
+                println!("Order {} is already being processed", order_uid);
+                metrics::ORDERS_SKIPPED
+                    .with_label_values(&["in_flight"])
+                    .inc();
+                continue;
+            };
+
+            let block_provider = Arc::clone(&block_provider);
+            let api_client = api_client.clone();
+            let aws_client_clone = aws_client.clone();
+            let raw_events_clone = raw_events.clone();
+            let service = self.clone();
+
+            tokio::spawn(async move {
+                let _in_flight_guard = in_flight_guard;
+                let counters = &service.0.counters;
+                // read again by the analytics later on when it can't be now
+                let timestamp = block_timestamp(&*block_provider, block_number)
+                    .await
+                    .unwrap_or_default();
+
+                raw_events_clone
+                    .record_trade(&RecordedTrade {
+                        trade,
+                        block_number,
+                        timestamp,
+                        transaction_hash: meta.transaction_hash,
+                        log_index: meta.log_index,
+                    })
+                    .await;
+
+                let uid = order_uid.to_string();
+                match aws_client_clone
+                    .is_duplicate(&uid, WritePolicy::IfNewerVersion)
+                    .await
+                {
+                    Ok(true) => {
+                        println!("Order {} was already recorded", uid);
+                        metrics::ORDERS_SKIPPED
+                            .with_label_values(&["duplicate"])
+                            .inc();
+                        counters.skipped.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    Ok(false) => (),
+                    // the conditional write is still there to catch it
+                    Err(e) => eprintln!("Failed to look up order {}: {}", uid, e),
+                }
+
+                let order = match analyze_order(
+                    &service.0.config,
+                    &service.0.tokens,
+                    &raw_events_clone,
+                    &api_client,
+                    &uid,
+                    Settlement::live(block_number, timestamp),
+                )
+                .await
+                {
+                    Ok(Some(order)) => order,
+                    Ok(None) => {
+                        counters.skipped.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to analyze order {}: {}", order_uid, e);
+                        metrics::ORDERS_FAILED
+                            .with_label_values(&["analysis"])
+                            .inc();
+                        counters.failed.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                };
+                println!("New settlement found at block number: {:?}", block_number);
+
+                match aws_client_clone
+                    .upload_order(&order, WritePolicy::IfNewerVersion)
+                    .await
+                {
+                    Ok(WriteOutcome::Written) => (),
+                    Ok(WriteOutcome::Duplicate) => {
+                        println!("Order {} was already recorded", order.uid());
+                        metrics::ORDERS_SKIPPED
+                            .with_label_values(&["duplicate"])
+                            .inc();
+                        counters.skipped.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to upload order {}: {}", order.uid(), e);
+                        metrics::ORDERS_FAILED.with_label_values(&["storage"]).inc();
+                        counters.failed.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                }
+
+                println!(
+                    "New order recorded in thread: {:?}\n{:#?}\n",
+                    std::thread::current().id(),
+                    order
+                );
+                metrics::ORDERS_STORED.inc();
+                counters.processed.fetch_add(1, Ordering::Relaxed);
+                let order = service.0.tokens.with_tokens(order).await;
+                service.0.feed.publish(order);
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn unix(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -1,26 +1,45 @@
+pub mod analytics;
+pub mod archive;
+pub mod backfill;
+pub mod config;
 mod constant;
 mod contract;
+pub mod dashboard;
+pub mod export;
+pub mod feed;
+pub mod health;
 pub mod helper;
+pub mod ingestion;
+pub mod metrics;
 pub mod order;
+pub mod reanalyze;
+pub mod rpc;
 pub mod services;
+pub mod tokens;
 
+use config::Config;
 use ethers::{
     contract::EthEvent,
-    middleware::Middleware,
-    providers::{Provider, Ws},
-    types::{Address, Bytes, Filter, U256},
+    types::{Address, Bytes, U256},
 };
-use futures::StreamExt;
-use helper::EnvConfig;
+use feed::OrderFeed;
 use order::Order;
+use rpc::{block_timestamp, Rpc};
 use serde::{Deserialize, Serialize};
 use services::{
-    aws_dynamodb::DynamoDbClient, cow_get_order_api::cowswap_get_order,
-    cow_post_quote_api::cowswap_quote_buy, uni_fork_swap::uni_swap_buy,
-    zerox_get_quote_api::zerox_quote_buy,
+    aws_dynamodb::{DynamoDbClient, WriteOutcome, WritePolicy},
+    aws_dynamodb_raw_events::{RawEventKind, RawEventStore, RecordedTrade},
+    cow_get_order_api::{cowswap_get_order, cowswap_get_trades, parse_cow_order, parse_cow_trades},
+    cow_native_price_api::{cowswap_native_price, parse_native_price},
+    cow_post_quote_api::{cowswap_quote_buy, parse_cowswap_quote},
+    http_client::ApiClient,
+    uni_fork_swap::uni_swap_buy,
+    zerox_get_quote_api::{parse_zerox_quote, zerox_quote_buy},
 };
-use std::sync::Arc;
-use std::time::Duration;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokens::TokenRegistry;
 
 #[derive(Clone, Debug, Serialize, Deserialize, EthEvent)]
 #[ethevent(name = "Trade")]
@@ -35,148 +54,320 @@ pub struct TradeEvent {
     pub order_uid: Bytes,
 }
 
+// Order uids currently being analysed, shared by every ingestion task so the same order is
+// never processed twice at once.
+#[derive(Clone, Default)]
+pub struct InFlightOrders(Arc<Mutex<HashSet<String>>>);
+
+impl InFlightOrders {
+    pub fn claim(&self, uid: &str) -> Option<InFlightGuard> {
+        let mut uids = self.0.lock().unwrap();
+        if !uids.insert(uid.to_string()) {
+            return None;
+        }
+
+        Some(InFlightGuard {
+            orders: self.clone(),
+            uid: uid.to_string(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub struct InFlightGuard {
+    orders: InFlightOrders,
+    uid: String,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.orders.0.lock().unwrap().remove(&self.uid);
+    }
+}
+
+// the raw response is recorded before parsing, so failed quotes can be looked into later
 macro_rules! fetch_quote_and_update_order {
-    ($quote_fn:expr, $order:expr, $update_method:ident, $error_msg:expr) => {
-        match $quote_fn.await {
-            Ok(quote) => $order.$update_method(&quote),
-            Err(e) => eprintln!("{}: {}", $error_msg, e),
+    ($quote_fn:expr, $parse_fn:expr, $raw_events:expr, $kind:expr, $order:expr, $update_method:ident, $error_msg:expr) => {
+        let timer = metrics::QUOTE_DURATION
+            .with_label_values(&[$kind.as_str()])
+            .start_timer();
+        let response = $quote_fn.await;
+        timer.observe_duration();
+        $raw_events.record($order.uid(), $kind, &response).await;
+        match response.and_then(|body| $parse_fn(&body)) {
+            Ok(quote) => {
+                metrics::QUOTES
+                    .with_label_values(&[$kind.as_str(), "success"])
+                    .inc();
+                $order.$update_method(&quote)
+            }
+            Err(e) => {
+                metrics::QUOTES
+                    .with_label_values(&[$kind.as_str(), "failure"])
+                    .inc();
+                eprintln!("{}: {}", $error_msg, e)
+            }
         }
     };
 }
 
-pub async fn handle_start_service(config: &EnvConfig) -> eyre::Result<String> {
-    tokio::spawn(run_with_timeout(Arc::new(config.clone())));
-
-    Ok("Blockchain service has started".to_string())
+// Where and when an order settled, and whether that was recent enough for today's quotes and
+// prices to say anything about it
+#[derive(Debug, Clone, Copy)]
+pub struct Settlement {
+    pub block_number: u64,
+    // 0 when unknown
+    pub timestamp: u64,
+    pub live: bool,
 }
 
-pub async fn run_with_timeout(config: Arc<EnvConfig>) -> eyre::Result<String> {
-    let duration = 15 * 60;
+impl Settlement {
+    // as the listener sees it, settled just now
+    pub fn live(block_number: u64, timestamp: u64) -> Self {
+        Self {
+            block_number,
+            timestamp,
+            live: true,
+        }
+    }
 
-    tokio::select! {
-        _ = run(&config) => Err(eyre::eyre!("Service error")),
-        // use timeout to end the service
-        _ = tokio::time::sleep(Duration::from_secs(duration)) => {
-            let message = format!("Service has ended after {} secs", duration);
-            println!("{}", message);
-            Ok(message)
+    // live only when it settled within LIVE_WINDOW_SECS
+    pub fn past(block_number: u64, timestamp: u64) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Self {
+            block_number,
+            timestamp,
+            live: timestamp != 0 && now.saturating_sub(timestamp) <= constant::LIVE_WINDOW_SECS,
         }
     }
 }
 
-pub async fn run(config: &EnvConfig) -> eyre::Result<()> {
-    let wss_provider = Provider::<Ws>::connect(config.get_alchemy_wss_url()).await?;
-    let wss_provider = Arc::new(wss_provider);
-
-    let aws_client = DynamoDbClient::new().await?;
-
-    let settlement_contract = constant::GPv2SETTLEMENT.parse::<Address>()?;
-    let trade_filter = Filter::new().address(settlement_contract);
-
-    let trade_event = TradeEvent::new::<_, Provider<Ws>>(trade_filter, Arc::clone(&wss_provider));
-    let mut stream = trade_event.stream().await?.with_meta();
-    while let Some(Ok((trade, meta))) = stream.next().await {
-        let order_uid = trade.order_uid;
-
-        let wss_provider_clone = Arc::clone(&wss_provider);
-        let config_clone = Arc::new(config.clone());
-        let aws_client_clone = Arc::new(aws_client.clone());
-
-        tokio::spawn(async move {
-            let api_client = reqwest::Client::new();
-            let (cow_api_response, should_proceed) =
-                match cowswap_get_order(&api_client, &order_uid.to_string()).await {
-                    Ok(response) => response,
-                    Err(e) => {
-                        eprintln!("Failed to get order from CowSwap API: {}", e);
-                        return;
-                    }
-                };
-
-            if should_proceed {
-                let block_number = meta.block_number.as_u64();
-                println!("New settlement found at block number: {:?}", block_number);
-
-                let timestamp = match wss_provider_clone.get_block(block_number).await {
-                    Ok(Some(block)) => block.timestamp.as_u64(),
-                    _ => 0,
-                };
-
-                let mut order = match Order::from_cow_api_response(
-                    Arc::clone(&wss_provider_clone),
-                    order_uid.to_string(),
-                    block_number,
-                    timestamp,
-                    &cow_api_response,
-                )
-                .await
-                {
-                    Ok(order) => order,
-                    Err(e) => {
-                        eprintln!("Failed to create order from Cow API response: {}", e);
-                        return; // Exit the task early
-                    }
-                };
-
-                let owner = cow_api_response.owner();
-                let sell_token = cow_api_response.sell_token();
-                let sell_amount = cow_api_response.sell();
-                let buy_token = cow_api_response.buy_token();
-
-                fetch_quote_and_update_order!(
-                    zerox_quote_buy(
-                        &config_clone,
-                        &api_client,
-                        "1",
-                        owner,
-                        sell_token,
-                        buy_token,
-                        sell_amount
-                    ),
-                    order,
-                    update_zerox_comparison,
-                    "0x get quote failed"
-                );
-
-                fetch_quote_and_update_order!(
-                    cowswap_quote_buy(&api_client, owner, sell_token, buy_token, sell_amount),
-                    order,
-                    update_cows_own_quote_comparison,
-                    "CowSwap own quote failed"
-                );
-
-                fetch_quote_and_update_order!(
-                    uni_swap_buy(
-                        &config_clone,
-                        block_number,
-                        owner,
-                        sell_token,
-                        buy_token,
-                        sell_amount
-                    ),
-                    order,
-                    update_univ3_swap_comparison,
-                    "Uni fork swap failed"
-                );
-
-                if order.no_successful_quote_at_all() {
-                    eprintln!("No successful quote at all");
-                    return;
-                }
-
-                if let Err(e) = aws_client_clone.upload_order(&order).await {
-                    eprintln!("Failed to upload order {}: {}", order.uid(), e);
-                    return;
-                }
-
-                println!(
-                    "New order recorded in thread: {:?}\n{:#?}\n",
-                    std::thread::current().id(),
-                    order
-                );
+// Runs a settled order through the CowSwap API and every venue, recording each raw response.
+// Orders that didn't settle live are only simulated on UniV3, and get no USD valuation.
+// Returns None for orders that can't be compared, which are buy orders and partial fills.
+pub async fn analyze_order(
+    config: &Config,
+    tokens: &TokenRegistry,
+    raw_events: &RawEventStore,
+    api_client: &ApiClient,
+    uid: &str,
+    settlement: Settlement,
+) -> eyre::Result<Option<Order>> {
+    let Settlement {
+        block_number,
+        timestamp,
+        live,
+    } = settlement;
+
+    let response = cowswap_get_order(config, api_client, uid).await;
+    raw_events
+        .record(uid, RawEventKind::CowOrder, &response)
+        .await;
+    let (cow_api_response, should_proceed) = response
+        .and_then(|body| parse_cow_order(&body))
+        .map_err(|e| eyre::eyre!("Failed to get order from CowSwap API: {}", e))?;
+    if !should_proceed {
+        let reason = if cow_api_response.is_sell() {
+            "partial_fill"
+        } else {
+            "buy_order"
+        };
+        metrics::ORDERS_SKIPPED.with_label_values(&[reason]).inc();
+        return Ok(None);
+    }
+
+    let mut order = Order::from_cow_api_response(
+        tokens,
+        uid.to_string(),
+        block_number,
+        timestamp,
+        &cow_api_response,
+    )
+    .await
+    .map_err(|e| eyre::eyre!("Failed to create order from Cow API response: {}", e))?;
+
+    let owner = cow_api_response.owner();
+    let sell_token = cow_api_response.sell_token();
+    let sell_amount = cow_api_response.sell();
+    let buy_token = cow_api_response.buy_token();
+
+    if live {
+        fetch_quote_and_update_order!(
+            zerox_quote_buy(
+                config,
+                api_client,
+                "1",
+                owner,
+                sell_token,
+                buy_token,
+                sell_amount
+            ),
+            parse_zerox_quote,
+            raw_events,
+            RawEventKind::ZeroxQuote,
+            order,
+            update_zerox_comparison,
+            "0x get quote failed"
+        );
+
+        fetch_quote_and_update_order!(
+            cowswap_quote_buy(
+                config,
+                api_client,
+                owner,
+                sell_token,
+                buy_token,
+                sell_amount
+            ),
+            |body: &str| parse_cowswap_quote(body, sell_amount),
+            raw_events,
+            RawEventKind::CowQuote,
+            order,
+            update_cows_own_quote_comparison,
+            "CowSwap own quote failed"
+        );
+    }
+
+    fetch_quote_and_update_order!(
+        uni_swap_buy(
+            config,
+            block_number,
+            owner,
+            sell_token,
+            buy_token,
+            sell_amount
+        ),
+        // the simulation has no response body, its raw record is the amount out
+        |amount: &str| eyre::Ok(amount.to_string()),
+        raw_events,
+        RawEventKind::Univ3Swap,
+        order,
+        update_univ3_swap_comparison,
+        "Uni fork swap failed"
+    );
+
+    if order.no_successful_quote_at_all() {
+        return Err(eyre::eyre!("No successful quote at all"));
+    }
+
+    if live {
+        match fetch_native_prices(config, api_client, raw_events, uid, sell_token, buy_token).await
+        {
+            Ok([sell_price, buy_price, usdc_price]) => {
+                order.update_usd_valuation(sell_price, buy_price, usdc_price)
             }
-        });
+            Err(e) => eprintln!("CowSwap native price failed: {}", e),
+        }
+    }
+
+    Ok(Some(order))
+}
+
+// the sell token's, the buy token's and USDC's native price, in that order
+async fn fetch_native_prices(
+    config: &Config,
+    api_client: &ApiClient,
+    raw_events: &RawEventStore,
+    uid: &str,
+    sell_token: &str,
+    buy_token: &str,
+) -> eyre::Result<[f64; 3]> {
+    let tokens = [
+        (RawEventKind::SellTokenPrice, sell_token),
+        (RawEventKind::BuyTokenPrice, buy_token),
+        (RawEventKind::UsdcPrice, constant::USDC),
+    ];
+
+    let mut prices = [0.0; 3];
+    for (price, (kind, token)) in prices.iter_mut().zip(tokens) {
+        // CowSwap prices native ETH as WETH
+        let token = if token == constant::WETH {
+            constant::WRAPPED_ETH
+        } else {
+            token
+        };
+        let response = cowswap_native_price(config, api_client, token).await;
+        raw_events.record(uid, kind, &response).await;
+        *price = parse_native_price(&response?)?;
     }
 
-    Ok(())
+    Ok(prices)
+}
+
+// Analyses a settled order by its uid, finding its settlement through the CowSwap trades API.
+// Nothing is stored but what `raw_events` records.
+pub async fn analyze_uid(
+    config: &Config,
+    tokens: &TokenRegistry,
+    raw_events: &RawEventStore,
+    uid: &str,
+) -> eyre::Result<Option<Order>> {
+    let uid = uid.to_lowercase();
+    let provider = Rpc::new(config).quorum_provider();
+    let api_client = ApiClient::new(config);
+
+    let trades = parse_cow_trades(&cowswap_get_trades(config, &api_client, &uid).await?)?;
+    let trade = trades
+        .first()
+        .ok_or_else(|| eyre::eyre!("Order {} has not been settled", uid))?;
+    let block_number = *trade.block_number();
+    let timestamp = block_timestamp(&provider, block_number)
+        .await
+        .unwrap_or_default();
+    raw_events
+        .record_trade(&RecordedTrade::from_cow_trade(trade, timestamp)?)
+        .await;
+
+    analyze_order(
+        config,
+        tokens,
+        raw_events,
+        &api_client,
+        &uid,
+        Settlement::past(block_number, timestamp),
+    )
+    .await
+}
+
+// Analyses an order that settled while nothing was listening, looking its settlement up through
+// the CowSwap trades API, stores it and publishes it to `feed`. An order that is already stored
+// is returned as is.
+pub async fn analyze_settled_order(
+    config: &Config,
+    tokens: &TokenRegistry,
+    feed: &OrderFeed,
+    uid: &str,
+) -> eyre::Result<Option<Order>> {
+    let uid = uid.to_lowercase();
+    let aws_client = DynamoDbClient::new(config).await?;
+    if let Some(order) = aws_client.get_order(&uid).await? {
+        return Ok(Some(order));
+    }
+
+    let raw_events = RawEventStore::new(config).await?;
+    let Some(order) = analyze_uid(config, tokens, &raw_events, &uid).await? else {
+        return Ok(None);
+    };
+
+    match aws_client
+        .upload_order(&order, WritePolicy::IfNewerVersion)
+        .await?
+    {
+        WriteOutcome::Written => {
+            feed.publish(tokens.with_tokens(order.clone()).await);
+            Ok(Some(order))
+        }
+        // stored by the listener in the meantime
+        WriteOutcome::Duplicate => Ok(aws_client.get_order(&uid).await?.or(Some(order))),
+    }
 }
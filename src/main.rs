@@ -1,56 +1,1069 @@
 use axum::{
-    extract::Extension,
-    http::StatusCode,
+    body::Body,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Extension, MatchedPath, Path, Query, Request, State,
+    },
+    http::{header, HeaderName, HeaderValue, Method, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        Html, IntoResponse, Response,
+    },
     routing::{get, post},
     Json, Router,
 };
-use cow_quote::handle_start_service;
-use cow_quote::helper::EnvConfig;
-use cow_quote::order::Order;
+use clap::{Args, Parser, Subcommand};
+use cow_quote::analytics::estimate::{estimate_surplus, Estimate, EstimateParams};
+use cow_quote::analytics::stats::{compute_stats, StatsParams, StatsReport};
+use cow_quote::analytics::timeseries::{compute_timeseries, Timeseries, TimeseriesParams};
+use cow_quote::analytics::wallet::{compute_wallet_report, WalletReport};
+use cow_quote::archive::archive;
+use cow_quote::backfill::backfill;
+use cow_quote::config::{Config, ConfigArgs};
+use cow_quote::dashboard::{self, DashboardParams};
+use cow_quote::export::{export_orders, stream_orders, ExportFormat, ExportSource, OrderSource};
+use cow_quote::feed::{FeedFilter, OrderFeed};
+use cow_quote::health::{HealthProbe, Liveness, Readiness};
+use cow_quote::ingestion::{IngestionService, IngestionStatus};
+use cow_quote::metrics;
+use cow_quote::reanalyze::reanalyze;
 use cow_quote::services::{
-    aws_dynamodb::fetch_latest_from_database, aws_ec2::is_running_in_aws_ec2,
+    aws_dynamodb::{
+        fetch_latest_from_database, fetch_orders_from_database, DynamoDbClient, OrderQuery, Page,
+        PageRequest,
+    },
+    aws_dynamodb_admin,
+    aws_dynamodb_raw_events::RawEventStore,
+    aws_ec2::is_running_in_aws_ec2,
 };
-use tower_http::cors::CorsLayer;
+use cow_quote::tokens::{seed_tokens, OrderWithTokens, TokenInfo, TokenRegistry};
+use cow_quote::{analyze_settled_order, analyze_uid, InFlightOrders};
+use ethers::types::Address;
+use futures::stream;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast::error::RecvError, mpsc};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+#[derive(Parser)]
+#[command(name = "cow-quote", about = "CowSwap settlement price comparison")]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the API server, the default without a command
+    Serve,
+    /// Listen to settlements in the foreground, storing every analysed order, until Ctrl-C
+    Watch {
+        /// Stop after this long, defaults to RUN_DURATION_SECS
+        #[arg(long)]
+        duration_secs: Option<u64>,
+    },
+    /// Analyse every order settled in a block range and print them as JSON lines, storing nothing
+    Backfill {
+        /// First block, inclusive
+        #[arg(long)]
+        from: u64,
+        /// Last block, inclusive
+        #[arg(long)]
+        to: u64,
+        /// File to write to, stdout when omitted
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Analyse one settled order and print it as JSON, storing nothing
+    Analyze {
+        /// The order's uid
+        uid: String,
+    },
+    /// Provision and migrate the DynamoDB tables
+    #[command(subcommand)]
+    Admin(AdminCommand),
+    /// Rebuild stored orders from the raw event store
+    Reanalyze {
+        /// Only rebuild this order, instead of every recorded trade
+        #[arg(long)]
+        uid: Option<String>,
+        /// Tag to give the rebuilt orders, defaults to the current analysis version
+        #[arg(long)]
+        analysis_version: Option<u32>,
+    },
+    /// Export stored orders as csv, jsonl or parquet
+    Export(ExportArgs),
+    /// Move expiring orders to the archive bucket, then delete them from the table
+    Archive,
+    /// Manage the token metadata table
+    #[command(subcommand)]
+    Tokens(TokensCommand),
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    /// csv, jsonl or parquet
+    #[arg(long, default_value = "csv")]
+    format: ExportFormat,
+    /// table, or archive to read the orders moved to ARCHIVE_BUCKET
+    #[arg(long, default_value = "table")]
+    source: ExportSource,
+    /// File to write to, stdout when omitted
+    #[arg(long)]
+    output: Option<PathBuf>,
+    #[arg(long)]
+    from_block: Option<u64>,
+    #[arg(long)]
+    to_block: Option<u64>,
+    #[arg(long)]
+    from_timestamp: Option<u64>,
+    #[arg(long)]
+    to_timestamp: Option<u64>,
+    #[arg(long)]
+    buy_token: Option<String>,
+    #[arg(long)]
+    sell_token: Option<String>,
+    #[arg(long)]
+    owner: Option<String>,
+}
+
+impl ExportArgs {
+    fn query(&self) -> OrderQuery {
+        OrderQuery {
+            buy_token: self.buy_token.clone(),
+            sell_token: self.sell_token.clone(),
+            owner: self.owner.clone(),
+            from_timestamp: self.from_timestamp,
+            to_timestamp: self.to_timestamp,
+            from_block: self.from_block,
+            to_block: self.to_block,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum TokensCommand {
+    /// Store every mainnet token of a Uniswap style token list file
+    Seed {
+        /// Path to the token list JSON
+        path: PathBuf,
+    },
+}
+
+#[derive(Args)]
+struct MigrateArgs {
+    /// Drop and rebuild this index when its key schema is out of date, repeat for several.
+    /// Queries on it fail until it is active again.
+    #[arg(long = "recreate-index")]
+    recreate_index: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum AdminCommand {
+    /// Create or verify the tables, then apply pending migrations
+    Setup(MigrateArgs),
+    /// Apply pending migrations to the existing tables
+    Migrate(MigrateArgs),
+    /// Show the tables and which migrations have been applied
+    Status,
+}
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
-    if is_running_in_aws_ec2() {
+    let cli = Cli::parse();
+    let config = Config::load(&cli.config)?;
+
+    match cli.command {
+        Some(Command::Admin(AdminCommand::Setup(args))) => {
+            aws_dynamodb_admin::setup(&config, &args.recreate_index).await
+        }
+        Some(Command::Admin(AdminCommand::Migrate(args))) => {
+            aws_dynamodb_admin::migrate(&config, &args.recreate_index).await
+        }
+        Some(Command::Admin(AdminCommand::Status)) => aws_dynamodb_admin::status(&config).await,
+        Some(Command::Reanalyze {
+            uid,
+            analysis_version,
+        }) => reanalyze(&config, uid, analysis_version).await,
+        Some(Command::Export(args)) => {
+            export_orders(&config, args.source, args.query(), args.format, args.output).await
+        }
+        Some(Command::Archive) => archive(&config).await,
+        Some(Command::Tokens(TokensCommand::Seed { path })) => {
+            let seeded = seed_tokens(&config, &path).await?;
+            println!("Seeded {} tokens from {}", seeded, path.display());
+            Ok(())
+        }
+        Some(Command::Watch { duration_secs }) => watch(config, duration_secs).await,
+        Some(Command::Backfill { from, to, output }) => backfill(&config, from, to, output).await,
+        Some(Command::Analyze { uid }) => {
+            let tokens = TokenRegistry::chain_only(&config)?;
+            let raw_events = RawEventStore::dry_run(&config).await?;
+            match analyze_uid(&config, &tokens, &raw_events, &uid).await? {
+                Some(order) => {
+                    println!("{}", serde_json::to_string_pretty(&order)?);
+                    Ok(())
+                }
+                None => Err(eyre::eyre!(
+                    "Order {} is a buy order or a partial fill, which aren't compared",
+                    uid
+                )),
+            }
+        }
+        Some(Command::Serve) | None => serve(config).await,
+    }
+}
+
+async fn watch(config: Config, duration_secs: Option<u64>) -> eyre::Result<()> {
+    let duration = duration_secs
+        .map(Duration::from_secs)
+        .or(*config.run_duration());
+    let tokens = TokenRegistry::new(&config).await?;
+    let ingestion = IngestionService::new(
+        config,
+        InFlightOrders::default(),
+        OrderFeed::default(),
+        tokens,
+    );
+
+    ingestion.start(duration)?;
+    tokio::select! {
+        _ = ingestion.stopped() => (),
+        _ = tokio::signal::ctrl_c() => {
+            ingestion.stop();
+        }
+    }
+
+    let status = ingestion.status();
+    println!(
+        "Watched settlements: {} stored, {} skipped, {} failed",
+        status.processed(),
+        status.skipped(),
+        status.failed()
+    );
+    Ok(())
+}
+
+async fn serve(config: Config) -> eyre::Result<()> {
+    let environment = if is_running_in_aws_ec2() {
         println!("Running on AWS EC2");
+        "aws_ec2"
     } else {
         println!("Running locally");
-    }
+        "local"
+    };
+
+    metrics::register();
+    let in_flight = InFlightOrders::default();
+    let feed = OrderFeed::default();
+    let tokens = TokenRegistry::new(&config).await?;
+    let ingestion = IngestionService::new(
+        config.clone(),
+        in_flight.clone(),
+        feed.clone(),
+        tokens.clone(),
+    );
+    let health = HealthProbe::new(config.clone(), ingestion.clone(), environment).await;
+    // one client for every request, building it loads the AWS credentials
+    let dynamodb = DynamoDbClient::new(&config).await?;
 
-    let config = EnvConfig::new();
+    if config.api_keys().is_empty() {
+        println!("API_KEYS is not set, the mutating routes are closed");
+    }
 
-    let api_router = Router::new()
+    // routes that spend RPC and quote API quota, read a whole table, or change what the service
+    // does
+    let mutating_routes = Router::new()
         .route("/start", post(start_service))
+        .route("/stop", post(stop_service))
+        .route("/orders/:uid/analyze", post(analyze_order))
+        .route("/export", get(export))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::new(config.api_keys().clone()),
+            require_api_key,
+        ));
+
+    let read_routes = Router::new()
+        .route("/status", get(service_status))
         .route("/latest-data", get(fetch_latest_data))
-        .layer(Extension(config))
-        // Add CORS middleware
-        .layer(CorsLayer::permissive());
+        .route("/orders", get(fetch_orders))
+        .route("/orders/stream", get(stream_orders_sse))
+        .route("/ws", get(stream_orders_ws))
+        .route("/orders/:uid", get(fetch_order))
+        .route("/tokens/:address", get(fetch_token))
+        .route("/stats", get(fetch_stats))
+        .route("/timeseries", get(fetch_timeseries))
+        .route("/estimate", get(fetch_estimate))
+        .route("/wallets/:address", get(fetch_wallet))
+        .route("/dashboard", get(dashboard_overview))
+        .route("/dashboard/pairs", get(dashboard_pairs))
+        .route("/dashboard/orders/:uid", get(dashboard_order))
+        .route("/dashboard/assets/style.css", get(dashboard_style))
+        .route("/dashboard/assets/app.js", get(dashboard_script))
+        .route_layer(middleware::from_fn_with_state(
+            RateLimiter::new(config.rate_limit_per_minute(), config.trust_forwarded_for()),
+            rate_limit,
+        ));
+
+    let bind_address = config.bind_address();
+    let api_router = read_routes
+        .merge(mutating_routes)
+        // scraped by Prometheus and the orchestrator, so kept out of the rate limit
+        .route("/metrics", get(fetch_metrics))
+        .route("/healthz", get(liveness))
+        .route("/readyz", get(readiness))
+        .layer(middleware::from_fn(track_http))
+        .layer(Extension(health))
+        .layer(Extension(dynamodb))
+        .layer(Extension(ingestion.clone()))
+        .layer(Extension(tokens))
+        .layer(Extension(in_flight))
+        .layer(Extension(feed))
+        .layer(cors(&config)?)
+        .layer(Extension(config));
 
     // Run the API server
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
-    println!("API server running on http://0.0.0.0:3000");
-    axum::serve(listener, api_router).await?;
+    let listener = tokio::net::TcpListener::bind(bind_address).await?;
+    println!("API server running on http://{}", bind_address);
+    axum::serve(
+        listener,
+        api_router.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
     Ok(())
 }
 
+fn cors(config: &Config) -> eyre::Result<CorsLayer> {
+    let origins = config.cors_allowed_origins();
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(
+            origins
+                .iter()
+                .map(|origin| HeaderValue::from_str(origin))
+                .collect::<Result<Vec<_>, _>>()?,
+        )
+    };
+
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION, API_KEY_HEADER]))
+}
+
+const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-api-key");
+
+// takes the key from either X-API-Key or Authorization: Bearer
+async fn require_api_key(
+    State(api_keys): State<Arc<Vec<String>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let headers = request.headers();
+    let key = headers
+        .get(&API_KEY_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| {
+            headers
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+        });
+
+    match key {
+        Some(key)
+            if api_keys
+                .iter()
+                .any(|api_key| constant_time_eq(api_key, key)) =>
+        {
+            next.run(request).await
+        }
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+// doesn't give away how much of a key matched through its timing
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+// Above this many tracked clients, new ones share the bucket of clients without a known IP
+// until idle ones are forgotten
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+// a bucket left alone this long is full again, so forgetting it changes nothing
+const BUCKET_IDLE: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Buckets {
+    // None is shared by clients without a known IP
+    clients: HashMap<Option<IpAddr>, Bucket>,
+    swept: Instant,
+}
+
+// A token bucket per client, holding up to a minute worth of requests
+#[derive(Clone)]
+struct RateLimiter {
+    per_minute: f64,
+    trust_forwarded_for: bool,
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    fn new(per_minute: u32, trust_forwarded_for: bool) -> Self {
+        Self {
+            per_minute: per_minute as f64,
+            trust_forwarded_for,
+            buckets: Arc::new(Mutex::new(Buckets {
+                clients: HashMap::new(),
+                swept: Instant::now(),
+            })),
+        }
+    }
+
+    // Err with the seconds until the client may try again
+    fn acquire(&self, client: Option<IpAddr>) -> Result<(), u64> {
+        self.acquire_at(client, Instant::now())
+    }
+
+    fn acquire_at(&self, client: Option<IpAddr>, now: Instant) -> Result<(), u64> {
+        let mut buckets = self.buckets.lock().unwrap();
+        // at most one sweep per BUCKET_IDLE, however many requests come in while full
+        if buckets.clients.len() >= MAX_TRACKED_CLIENTS
+            && now.duration_since(buckets.swept) >= BUCKET_IDLE
+        {
+            buckets
+                .clients
+                .retain(|_, bucket| now.duration_since(bucket.updated) < BUCKET_IDLE);
+            buckets.swept = now;
+        }
+
+        let client = if buckets.clients.len() >= MAX_TRACKED_CLIENTS
+            && !buckets.clients.contains_key(&client)
+        {
+            None
+        } else {
+            client
+        };
+        let bucket = buckets.clients.entry(client).or_insert(Bucket {
+            tokens: self.per_minute,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_minute / 60.0).min(self.per_minute);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) * 60.0 / self.per_minute).ceil() as u64)
+        }
+    }
+
+    fn client(&self, request: &Request) -> Option<IpAddr> {
+        let forwarded_for = self
+            .trust_forwarded_for
+            .then(|| request.headers().get("x-forwarded-for"))
+            .flatten()
+            .and_then(|value| value.to_str().ok())
+            // the rightmost entry is the one the proxy added, the rest is up to the client
+            .and_then(|value| value.rsplit(',').next())
+            .and_then(|client| client.trim().parse().ok());
+
+        forwarded_for.or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(address)| address.ip())
+        })
+    }
+}
+
+async fn rate_limit(State(limiter): State<RateLimiter>, request: Request, next: Next) -> Response {
+    match limiter.acquire(limiter.client(&request)) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, retry_after.to_string())],
+        )
+            .into_response(),
+    }
+}
+
+async fn track_http(request: Request, next: Next) -> Response {
+    // label by route template so order uids don't explode the series count
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+    let method = request.method().to_string();
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    metrics::HTTP_DURATION
+        .with_label_values(&[&method, &route])
+        .observe(started.elapsed().as_secs_f64());
+    metrics::HTTP_REQUESTS
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+    response
+}
+
+async fn fetch_metrics() -> Result<impl IntoResponse, StatusCode> {
+    let body = metrics::render().map_err(|e| {
+        eprintln!("Error rendering metrics: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body))
+}
+
+async fn liveness(Extension(health): Extension<HealthProbe>) -> Json<Liveness> {
+    Json(health.live())
+}
+
+async fn readiness(Extension(health): Extension<HealthProbe>) -> (StatusCode, Json<Readiness>) {
+    let readiness = health.ready().await;
+    let status = if readiness.ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness))
+}
+
+#[derive(Deserialize)]
+struct StartParams {
+    // falls back to the configured run duration, and runs until stopped without one
+    duration_secs: Option<u64>,
+}
+
 async fn start_service(
-    Extension(config): Extension<EnvConfig>,
-) -> Result<Json<String>, StatusCode> {
-    match handle_start_service(&config).await {
-        Ok(message) => Ok(Json(message)),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    Extension(config): Extension<Config>,
+    Extension(ingestion): Extension<IngestionService>,
+    Query(params): Query<StartParams>,
+) -> Result<Json<IngestionStatus>, StatusCode> {
+    match ingestion.start(
+        params
+            .duration_secs
+            .map(Duration::from_secs)
+            .or(*config.run_duration()),
+    ) {
+        Ok(status) => Ok(Json(status)),
+        // already running
+        Err(_) => Err(StatusCode::CONFLICT),
     }
 }
 
+async fn stop_service(Extension(ingestion): Extension<IngestionService>) -> Json<IngestionStatus> {
+    Json(ingestion.stop())
+}
+
+async fn service_status(
+    Extension(ingestion): Extension<IngestionService>,
+) -> Json<IngestionStatus> {
+    Json(ingestion.status())
+}
+
 async fn fetch_latest_data(
-    Extension(config): Extension<EnvConfig>,
-) -> Result<Json<Vec<Order>>, StatusCode> {
+    Extension(config): Extension<Config>,
+    Extension(dynamodb): Extension<DynamoDbClient>,
+    Extension(tokens): Extension<TokenRegistry>,
+    Query(page): Query<PageRequest>,
+) -> Result<Json<Page<OrderWithTokens>>, StatusCode> {
+    if page.start_key().is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
     // Fetch the latest data from the database
-    match fetch_latest_from_database(&config).await {
-        Ok(data) => Ok(Json(data)),
+    match fetch_latest_from_database(&config, &dynamodb, &page).await {
+        Ok(data) => Ok(Json(tokens.page_with_tokens(data).await)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn fetch_orders(
+    Extension(dynamodb): Extension<DynamoDbClient>,
+    Extension(tokens): Extension<TokenRegistry>,
+    Query(query): Query<OrderQuery>,
+    Query(page): Query<PageRequest>,
+) -> Result<Json<Page<OrderWithTokens>>, StatusCode> {
+    if page.start_key().is_err() || query.validate().is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match fetch_orders_from_database(&dynamodb, &query, &page).await {
+        Ok(data) => Ok(Json(tokens.page_with_tokens(data).await)),
         Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
     }
 }
+
+// Orders as the ingestion stores them, as `order` events. A subscriber that falls too far behind
+// gets a `lagged` event with the number of orders it missed.
+async fn stream_orders_sse(
+    Extension(feed): Extension<OrderFeed>,
+    Query(filter): Query<FeedFilter>,
+) -> Sse<impl futures::Stream<Item = Result<Event, Infallible>>> {
+    let events = stream::unfold(feed.subscribe(), move |mut rx| {
+        let filter = filter.clone();
+        async move {
+            loop {
+                let event = match rx.recv().await {
+                    Ok(order) if filter.matches(order.order()) => {
+                        Event::default().event("order").json_data(&*order).ok()?
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(missed)) => {
+                        Event::default().event("lagged").data(missed.to_string())
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((Ok(event), rx));
+            }
+        }
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+// The same feed as /orders/stream, with each order as a JSON text message and missed orders
+// reported as {"lagged": n}
+async fn stream_orders_ws(
+    Extension(feed): Extension<OrderFeed>,
+    Query(filter): Query<FeedFilter>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    upgrade.on_upgrade(move |socket| forward_orders(socket, feed, filter))
+}
+
+async fn forward_orders(mut socket: WebSocket, feed: OrderFeed, filter: FeedFilter) {
+    let mut rx = feed.subscribe();
+
+    loop {
+        let text = tokio::select! {
+            received = rx.recv() => match received {
+                Ok(order) if filter.matches(order.order()) => match serde_json::to_string(&*order) {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                },
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => format!("{{\"lagged\":{}}}", missed),
+                Err(RecvError::Closed) => return,
+            },
+            // the client only ever closes the socket, anything else it sends is ignored
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        if socket.send(Message::Text(text)).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn fetch_order(
+    Extension(dynamodb): Extension<DynamoDbClient>,
+    Extension(tokens): Extension<TokenRegistry>,
+    Path(uid): Path<String>,
+) -> Result<Json<OrderWithTokens>, StatusCode> {
+    match dynamodb.get_order(&uid.to_lowercase()).await {
+        Ok(Some(order)) => Ok(Json(tokens.with_tokens(order).await)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn fetch_token(
+    Extension(tokens): Extension<TokenRegistry>,
+    Path(address): Path<String>,
+) -> Result<Json<TokenInfo>, StatusCode> {
+    if address.parse::<Address>().is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match tokens.resolve(&address).await {
+        Ok(token) => Ok(Json(token)),
+        // no ERC20 contract that answers decimals() at this address
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+#[derive(Deserialize)]
+struct AnalyzeParams {
+    // false to answer right away and analyse in the background
+    wait: Option<bool>,
+}
+
+async fn analyze_order(
+    Extension(config): Extension<Config>,
+    Extension(tokens): Extension<TokenRegistry>,
+    Extension(in_flight): Extension<InFlightOrders>,
+    Extension(feed): Extension<OrderFeed>,
+    Path(uid): Path<String>,
+    Query(params): Query<AnalyzeParams>,
+) -> Result<Response, StatusCode> {
+    let uid = uid.to_lowercase();
+    let Some(in_flight_guard) = in_flight.claim(&uid) else {
+        return Err(StatusCode::CONFLICT);
+    };
+
+    if params.wait == Some(false) {
+        let message = format!("Analysis of order {} has started", uid);
+        tokio::spawn(async move {
+            let _in_flight_guard = in_flight_guard;
+            if let Err(e) = analyze_settled_order(&config, &tokens, &feed, &uid).await {
+                eprintln!("Failed to analyze order {}: {}", uid, e);
+            }
+        });
+        return Ok((StatusCode::ACCEPTED, Json(message)).into_response());
+    }
+
+    match analyze_settled_order(&config, &tokens, &feed, &uid).await {
+        Ok(Some(order)) => Ok(Json(tokens.with_tokens(order).await).into_response()),
+        // buy orders and partial fills aren't compared
+        Ok(None) => Err(StatusCode::UNPROCESSABLE_ENTITY),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn fetch_stats(
+    Extension(dynamodb): Extension<DynamoDbClient>,
+    Query(query): Query<OrderQuery>,
+    Query(params): Query<StatsParams>,
+) -> Result<Json<StatsReport>, StatusCode> {
+    query.validate().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let query = params.query(query).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    match compute_stats(&dynamodb, query, &params).await {
+        Ok(report) => Ok(Json(report)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn fetch_timeseries(
+    Extension(config): Extension<Config>,
+    Extension(dynamodb): Extension<DynamoDbClient>,
+    Query(query): Query<OrderQuery>,
+    Query(params): Query<TimeseriesParams>,
+) -> Result<Json<Timeseries>, StatusCode> {
+    query.validate().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let query = params.query(query).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    match compute_timeseries(&config, &dynamodb, query, &params).await {
+        Ok(timeseries) => Ok(Json(timeseries)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn fetch_estimate(
+    Extension(dynamodb): Extension<DynamoDbClient>,
+    Query(params): Query<EstimateParams>,
+) -> Result<Json<Estimate>, StatusCode> {
+    if params.sell_token.parse::<Address>().is_err()
+        || params.buy_token.parse::<Address>().is_err()
+        || !params.sell_amount.is_finite()
+        || params.sell_amount <= 0.0
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match estimate_surplus(&dynamodb, &params).await {
+        Ok(estimate) => Ok(Json(estimate)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn fetch_wallet(
+    Extension(dynamodb): Extension<DynamoDbClient>,
+    Extension(tokens): Extension<TokenRegistry>,
+    Path(address): Path<String>,
+    Query(page): Query<PageRequest>,
+) -> Result<Json<WalletReport>, StatusCode> {
+    if address.parse::<Address>().is_err() || page.start_key().is_err() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    match compute_wallet_report(&dynamodb, &tokens, &address, &page).await {
+        Ok(report) => Ok(Json(report)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn dashboard_overview(
+    Extension(dynamodb): Extension<DynamoDbClient>,
+    Extension(tokens): Extension<TokenRegistry>,
+    Query(params): Query<DashboardParams>,
+) -> Result<Html<String>, StatusCode> {
+    match dashboard::overview_page(&dynamodb, &tokens, &params).await {
+        Ok(page) => Ok(Html(page)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn dashboard_pairs(
+    Extension(dynamodb): Extension<DynamoDbClient>,
+    Extension(tokens): Extension<TokenRegistry>,
+    Query(params): Query<DashboardParams>,
+) -> Result<Html<String>, StatusCode> {
+    match dashboard::pairs_page(&dynamodb, &tokens, &params).await {
+        Ok(page) => Ok(Html(page)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn dashboard_order(
+    Extension(dynamodb): Extension<DynamoDbClient>,
+    Extension(tokens): Extension<TokenRegistry>,
+    Path(uid): Path<String>,
+) -> Result<Html<String>, StatusCode> {
+    match dashboard::order_page(&dynamodb, &tokens, &uid).await {
+        Ok(Some(page)) => Ok(Html(page)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn dashboard_style() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/css")], dashboard::STYLE)
+}
+
+async fn dashboard_script() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/javascript")],
+        dashboard::SCRIPT,
+    )
+}
+
+#[derive(Deserialize)]
+struct ExportParams {
+    format: ExportFormat,
+    #[serde(default)]
+    source: ExportSource,
+}
+
+async fn export(
+    Extension(config): Extension<Config>,
+    Extension(dynamodb): Extension<DynamoDbClient>,
+    Query(params): Query<ExportParams>,
+    Query(query): Query<OrderQuery>,
+) -> Result<Response, StatusCode> {
+    query.validate().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let source = OrderSource::open(&config, &dynamodb, params.source, query)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    // the export runs in its own task and hands over a chunk per page, so nothing is buffered
+    // beyond the page (or parquet row group) currently being written
+    let (tx, rx) = mpsc::channel(4);
+    tokio::spawn(stream_orders(source, params.format, tx));
+    let body = Body::from_stream(stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    }));
+
+    let disposition = format!(
+        "attachment; filename=\"orders.{}\"",
+        params.format.extension()
+    );
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                params.format.content_type().to_string(),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::ServiceExt;
+
+    async fn ok() -> &'static str {
+        "ok"
+    }
+
+    fn with_api_keys(keys: &[&str]) -> Router {
+        Router::new()
+            .route("/", get(ok))
+            .route_layer(middleware::from_fn_with_state(
+                Arc::new(keys.iter().map(|key| key.to_string()).collect::<Vec<_>>()),
+                require_api_key,
+            ))
+    }
+
+    fn with_rate_limit(limiter: RateLimiter) -> Router {
+        Router::new()
+            .route("/", get(ok))
+            .route_layer(middleware::from_fn_with_state(limiter, rate_limit))
+    }
+
+    async fn status(router: Router, headers: &[(&str, &str)]) -> StatusCode {
+        let mut request = Request::builder().uri("/");
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        router
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    fn ip(last: u32) -> Option<IpAddr> {
+        Some(IpAddr::from(last.to_be_bytes()))
+    }
+
+    #[test]
+    fn keys_compare_equal_only_when_identical() {
+        assert!(constant_time_eq("secret", "secret"));
+        assert!(!constant_time_eq("secret", "secreT"));
+        assert!(!constant_time_eq("secret", "secret1"));
+        assert!(!constant_time_eq("", "secret"));
+        assert!(constant_time_eq("", ""));
+    }
+
+    #[tokio::test]
+    async fn api_key_is_required() {
+        let router = with_api_keys(&["first", "second"]);
+        assert_eq!(status(router.clone(), &[]).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            status(router.clone(), &[("x-api-key", "third")]).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(router.clone(), &[("authorization", "second")]).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(router.clone(), &[("x-api-key", "first")]).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(router, &[("authorization", "Bearer second")]).await,
+            StatusCode::OK
+        );
+    }
+
+    #[tokio::test]
+    async fn no_api_keys_lets_nobody_in() {
+        let router = with_api_keys(&[]);
+        assert_eq!(
+            status(router, &[("x-api-key", "")]).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn an_empty_bucket_answers_429_with_retry_after() {
+        let router = with_rate_limit(RateLimiter::new(2, false));
+        assert_eq!(status(router.clone(), &[]).await, StatusCode::OK);
+        assert_eq!(status(router.clone(), &[]).await, StatusCode::OK);
+
+        let response = router
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        // two a minute refill one every 30 seconds
+        assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+    }
+
+    #[tokio::test]
+    async fn only_the_rightmost_forwarded_for_entry_is_trusted() {
+        let router = with_rate_limit(RateLimiter::new(1, true));
+        let forwarded = |value| [("x-forwarded-for", value)];
+
+        assert_eq!(
+            status(router.clone(), &forwarded("1.1.1.1, 10.0.0.1")).await,
+            StatusCode::OK
+        );
+        // a different spoofed entry is still the same client to the proxy
+        assert_eq!(
+            status(router.clone(), &forwarded("2.2.2.2, 10.0.0.1")).await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(
+            status(router, &forwarded("1.1.1.1, 10.0.0.2")).await,
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_unless_trusted() {
+        let request = Request::builder()
+            .header("x-forwarded-for", "1.1.1.1")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(RateLimiter::new(1, false).client(&request), None);
+        assert_eq!(
+            RateLimiter::new(1, true).client(&request),
+            "1.1.1.1".parse().ok()
+        );
+    }
+
+    #[test]
+    fn new_clients_share_a_bucket_once_too_many_are_tracked() {
+        let limiter = RateLimiter::new(1, false);
+        let now = Instant::now();
+        for client in 0..MAX_TRACKED_CLIENTS as u32 {
+            assert!(limiter.acquire_at(ip(client), now).is_ok());
+        }
+
+        // the next new client takes the shared bucket's only token
+        assert!(limiter.acquire_at(ip(u32::MAX), now).is_ok());
+        assert!(limiter.acquire_at(ip(u32::MAX - 1), now).is_err());
+        assert!(limiter.acquire_at(None, now).is_err());
+        // the tracked clients and the shared bucket
+        assert_eq!(
+            limiter.buckets.lock().unwrap().clients.len(),
+            MAX_TRACKED_CLIENTS + 1
+        );
+    }
+
+    #[test]
+    fn idle_clients_are_forgotten_when_full() {
+        let limiter = RateLimiter::new(1, false);
+        let start = Instant::now();
+        for client in 0..MAX_TRACKED_CLIENTS as u32 - 1 {
+            assert!(limiter.acquire_at(ip(client), start).is_ok());
+        }
+        let active = start + BUCKET_IDLE / 2;
+        assert!(limiter.acquire_at(ip(u32::MAX), active).is_ok());
+
+        // not idle for long enough yet
+        let early = start + BUCKET_IDLE - Duration::from_secs(1);
+        assert!(limiter.acquire_at(ip(u32::MAX - 1), early).is_ok());
+        assert!(limiter.acquire_at(ip(u32::MAX - 2), early).is_err());
+
+        let later = start + BUCKET_IDLE;
+        assert!(limiter.acquire_at(ip(u32::MAX - 2), later).is_ok());
+        let buckets = limiter.buckets.lock().unwrap();
+        // the client seen within BUCKET_IDLE, the shared bucket and the new client
+        assert_eq!(buckets.clients.len(), 3);
+        assert!(buckets.clients.contains_key(&ip(u32::MAX)));
+    }
+}
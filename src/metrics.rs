@@ -0,0 +1,158 @@
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, TextEncoder,
+};
+use std::sync::LazyLock;
+
+// Every metric lives in the default registry, and is registered the first time it is used.
+// Registering only fails for duplicate names, which is a bug rather than a runtime condition.
+
+pub static TRADES_SEEN: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "cow_quote_trades_seen_total",
+        "Trade events received from the settlement contract"
+    )
+    .unwrap()
+});
+
+pub static ORDERS_SKIPPED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "cow_quote_orders_skipped_total",
+        "Orders not stored, by reason",
+        &["reason"]
+    )
+    .unwrap()
+});
+
+pub static ORDERS_STORED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "cow_quote_orders_stored_total",
+        "Orders analysed and stored"
+    )
+    .unwrap()
+});
+
+pub static ORDERS_FAILED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "cow_quote_orders_failed_total",
+        "Orders that could not be analysed or stored, by stage",
+        &["stage"]
+    )
+    .unwrap()
+});
+
+pub static QUOTES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "cow_quote_quotes_total",
+        "Quote requests, by source and result",
+        &["source", "result"]
+    )
+    .unwrap()
+});
+
+pub static QUOTE_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "cow_quote_quote_duration_seconds",
+        "Time taken by a quote request, by source",
+        &["source"],
+        vec![0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]
+    )
+    .unwrap()
+});
+
+pub static ANVIL_SPAWN_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "cow_quote_anvil_spawn_duration_seconds",
+        "Time taken to spawn an Anvil fork",
+        vec![0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0]
+    )
+    .unwrap()
+});
+
+pub static DYNAMODB_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "cow_quote_dynamodb_write_duration_seconds",
+        "Time taken by a DynamoDB write, by operation",
+        &["operation"],
+        vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5]
+    )
+    .unwrap()
+});
+
+pub static DYNAMODB_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "cow_quote_dynamodb_write_errors_total",
+        "Failed DynamoDB writes, by operation",
+        &["operation"]
+    )
+    .unwrap()
+});
+
+pub static WS_RECONNECTS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "cow_quote_ws_reconnects_total",
+        "Times the trade stream dropped and was reconnected"
+    )
+    .unwrap()
+});
+
+pub static RPC_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "cow_quote_rpc_failures_total",
+        "RPC requests an endpoint didn't answer, each failing over to the next, by endpoint",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+
+pub static HTTP_CLIENT_RETRIES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "cow_quote_http_client_retries_total",
+        "Outbound API requests retried, by host and the status or failure that caused it",
+        &["host", "reason"]
+    )
+    .unwrap()
+});
+
+pub static HTTP_REQUESTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "cow_quote_http_requests_total",
+        "HTTP requests served, by method, route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+pub static HTTP_DURATION: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "cow_quote_http_request_duration_seconds",
+        "Time taken to answer an HTTP request, by method and route",
+        &["method", "route"]
+    )
+    .unwrap()
+});
+
+// the Prometheus text format of every registered metric
+pub fn render() -> eyre::Result<String> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+// so every metric shows up on /metrics from the start, not only once it was first touched
+pub fn register() {
+    LazyLock::force(&TRADES_SEEN);
+    LazyLock::force(&ORDERS_SKIPPED);
+    LazyLock::force(&ORDERS_STORED);
+    LazyLock::force(&ORDERS_FAILED);
+    LazyLock::force(&QUOTES);
+    LazyLock::force(&QUOTE_DURATION);
+    LazyLock::force(&ANVIL_SPAWN_DURATION);
+    LazyLock::force(&DYNAMODB_DURATION);
+    LazyLock::force(&DYNAMODB_ERRORS);
+    LazyLock::force(&WS_RECONNECTS);
+    LazyLock::force(&RPC_FAILURES);
+    LazyLock::force(&HTTP_CLIENT_RETRIES);
+    LazyLock::force(&HTTP_REQUESTS);
+    LazyLock::force(&HTTP_DURATION);
+}
@@ -1,23 +1,17 @@
 use crate::constant;
-use crate::contract::ierc20::get_token_decimals;
 use crate::helper::format_decimals_into_f;
 use crate::services::{
-    aws_dynamodb::{extract_number, extract_string},
-    cow_get_order_api::CowGetResponse,
+    aws_dynamodb::Item, aws_dynamodb_serde::from_item, cow_get_order_api::CowGetResponse,
 };
-use aws_sdk_dynamodb::types::AttributeValue;
+use crate::tokens::TokenRegistry;
 use getset::Getters;
 
-use ethers::{
-    providers::{Provider, Ws},
-    types::Address,
-};
-use serde::Serialize;
-use std::collections::HashMap;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Clone, Default, Getters)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Getters)]
 #[getset(get = "pub")]
+// fields missing from older records read back as their defaults
+#[serde(default)]
 pub struct Order {
     uid: String,
     owner: String,
@@ -32,52 +26,69 @@ pub struct Order {
     executed_buy: f64,
     executed_sell: f64,
 
+    // the same amounts as integers in the tokens' smallest unit, as reported by the CowSwap API
+    min_buy_raw: String,
+    sell_raw: String,
+    executed_buy_raw: String,
+    executed_sell_raw: String,
+
     net_surplus: f64,
     surplus_percentage: f64,
 
     zerox_quote_buy: f64,
+    zerox_quote_buy_raw: String,
     compared_executed_with_zerox_quote: f64,
     compared_with_zerox_percentage: f64,
 
     cows_own_quote_buy: f64,
+    cows_own_quote_buy_raw: String,
     compared_executed_with_cows_own_quote: f64,
     compared_with_cows_own_quote_percentage: f64,
 
     univ3_swap_buy: f64,
+    univ3_swap_buy_raw: String,
     compared_executed_with_univ3_swap: f64,
     compared_with_univ3_swap_percentage: f64,
 
+    // USD per whole token, and the order valued with them. 0 when CowSwap had no price
+    sell_token_usd_price: f64,
+    buy_token_usd_price: f64,
+    sell_usd: f64,
+    net_surplus_usd: f64,
+
     block_number: u64,
     // in unlikely cases where timestamp == 0, data user can query it from block_number
     timestamp: u64,
+
+    analysis_version: u32,
 }
 
 impl Order {
     pub async fn from_cow_api_response(
-        provider: Arc<Provider<Ws>>,
+        tokens: &TokenRegistry,
         uid: String,
         block_number: u64,
         timestamp: u64,
         response: &CowGetResponse,
     ) -> eyre::Result<Self> {
         let (buy_decimals, min_buy, executed_buy) = process_order_info(
-            Arc::clone(&provider),
-            &response.buy_token(),
-            &response.buy(),
-            &response.executed_buy(),
+            tokens,
+            response.buy_token(),
+            response.buy(),
+            response.executed_buy(),
         )
         .await?;
 
         let (sell_decimals, sell, executed_sell) = process_order_info(
-            Arc::clone(&provider),
-            &response.sell_token(),
-            &response.sell(),
-            &response.executed_sell(),
+            tokens,
+            response.sell_token(),
+            response.sell(),
+            response.executed_sell(),
         )
         .await?;
 
         let net_surplus = executed_buy - min_buy;
-        let surplus_percentage = net_surplus / min_buy;
+        let surplus_percentage = relative_to_buy(net_surplus, min_buy, executed_buy);
 
         Ok(Order {
             uid,
@@ -90,68 +101,86 @@ impl Order {
             sell,
             executed_buy,
             executed_sell,
+            min_buy_raw: response.buy().to_string(),
+            sell_raw: response.sell().to_string(),
+            executed_buy_raw: response.executed_buy().to_string(),
+            executed_sell_raw: response.executed_sell().to_string(),
             net_surplus,
             surplus_percentage,
             block_number,
             timestamp,
+            analysis_version: constant::ANALYSIS_VERSION,
             ..Default::default()
         })
     }
 
-    pub fn from_dynamodb_item(item: &HashMap<String, AttributeValue>) -> Self {
-        Order {
-            uid: extract_string(item, "uid"),
-            owner: extract_string(item, "owner"),
-            buy_token: extract_string(item, "buy_token"),
-            sell_token: extract_string(item, "sell_token"),
-            buy_decimals: extract_number(item, "buy_decimals") as u8,
-            sell_decimals: extract_number(item, "sell_decimals") as u8,
-            min_buy: extract_number(item, "min_buy") as f64,
-            sell: extract_number(item, "sell") as f64,
-            executed_buy: extract_number(item, "executed_buy") as f64,
-            executed_sell: extract_number(item, "executed_sell") as f64,
-            net_surplus: extract_number(item, "net_surplus") as f64,
-            surplus_percentage: extract_number(item, "surplus_percentage") as f64,
-            zerox_quote_buy: extract_number(item, "zerox_quote_buy") as f64,
-            compared_executed_with_zerox_quote: extract_number(
-                item,
+    pub fn from_dynamodb_item(item: &Item) -> eyre::Result<Self> {
+        from_item(item)
+    }
+
+    // DynamoDB has no number for NaN or infinity, and serde_json would store them as nulls
+    pub fn check_finite(&self) -> eyre::Result<()> {
+        let floats = [
+            ("min_buy", self.min_buy),
+            ("sell", self.sell),
+            ("executed_buy", self.executed_buy),
+            ("executed_sell", self.executed_sell),
+            ("net_surplus", self.net_surplus),
+            ("surplus_percentage", self.surplus_percentage),
+            ("zerox_quote_buy", self.zerox_quote_buy),
+            (
                 "compared_executed_with_zerox_quote",
-            ) as f64,
-            compared_with_zerox_percentage: extract_number(item, "compared_with_zerox_percentage")
-                as f64,
-            cows_own_quote_buy: extract_number(item, "cows_own_quote_buy") as f64,
-            compared_executed_with_cows_own_quote: extract_number(
-                item,
+                self.compared_executed_with_zerox_quote,
+            ),
+            (
+                "compared_with_zerox_percentage",
+                self.compared_with_zerox_percentage,
+            ),
+            ("cows_own_quote_buy", self.cows_own_quote_buy),
+            (
                 "compared_executed_with_cows_own_quote",
-            ) as f64,
-            compared_with_cows_own_quote_percentage: extract_number(
-                item,
+                self.compared_executed_with_cows_own_quote,
+            ),
+            (
                 "compared_with_cows_own_quote_percentage",
-            ) as f64,
-            univ3_swap_buy: extract_number(item, "univ3_swap_buy") as f64,
-            compared_executed_with_univ3_swap: extract_number(
-                item,
+                self.compared_with_cows_own_quote_percentage,
+            ),
+            ("univ3_swap_buy", self.univ3_swap_buy),
+            (
                 "compared_executed_with_univ3_swap",
-            ) as f64,
-            compared_with_univ3_swap_percentage: extract_number(
-                item,
+                self.compared_executed_with_univ3_swap,
+            ),
+            (
                 "compared_with_univ3_swap_percentage",
-            ) as f64,
-            block_number: extract_number(item, "block_number") as u64,
-            timestamp: extract_number(item, "timestamp") as u64,
+                self.compared_with_univ3_swap_percentage,
+            ),
+            ("sell_token_usd_price", self.sell_token_usd_price),
+            ("buy_token_usd_price", self.buy_token_usd_price),
+            ("sell_usd", self.sell_usd),
+            ("net_surplus_usd", self.net_surplus_usd),
+        ];
+
+        match floats.iter().find(|(_, value)| !value.is_finite()) {
+            Some((name, value)) => Err(eyre::eyre!(
+                "Can't store {}, {} is {}",
+                self.uid,
+                name,
+                value
+            )),
+            None => Ok(()),
         }
     }
 
+    pub fn with_analysis_version(mut self, analysis_version: u32) -> Self {
+        self.analysis_version = analysis_version;
+        self
+    }
+
     fn calculate_percentage(&self, input: f64) -> f64 {
         if input == 0.0 {
             1.0
         } else {
-            let denominator = if self.min_buy == 0.0 {
-                &self.executed_buy
-            } else {
-                &self.min_buy
-            };
-            self.compare(input) / denominator
+            relative_to_buy(self.compare(input), self.min_buy, self.executed_buy)
         }
     }
 
@@ -161,12 +190,14 @@ impl Order {
 
     pub fn update_zerox_comparison(&mut self, quote_buy: &str) {
         self.zerox_quote_buy = format_decimals_into_f(quote_buy, self.buy_decimals);
+        self.zerox_quote_buy_raw = quote_buy.to_string();
         self.compared_executed_with_zerox_quote = self.compare(self.zerox_quote_buy);
         self.compared_with_zerox_percentage = self.calculate_percentage(self.zerox_quote_buy);
     }
 
     pub fn update_cows_own_quote_comparison(&mut self, quote_buy: &str) {
         self.cows_own_quote_buy = format_decimals_into_f(quote_buy, self.buy_decimals);
+        self.cows_own_quote_buy_raw = quote_buy.to_string();
         self.compared_executed_with_cows_own_quote = self.compare(self.cows_own_quote_buy);
         self.compared_with_cows_own_quote_percentage =
             self.calculate_percentage(self.cows_own_quote_buy);
@@ -174,10 +205,37 @@ impl Order {
 
     pub fn update_univ3_swap_comparison(&mut self, quote_buy: &str) {
         self.univ3_swap_buy = format_decimals_into_f(quote_buy, self.buy_decimals);
+        self.univ3_swap_buy_raw = quote_buy.to_string();
         self.compared_executed_with_univ3_swap = self.compare(self.univ3_swap_buy);
         self.compared_with_univ3_swap_percentage = self.calculate_percentage(self.univ3_swap_buy);
     }
 
+    // CowSwap's native prices are in wei per smallest token unit, USDC's turns them into USD
+    pub fn update_usd_valuation(
+        &mut self,
+        sell_token_native_price: f64,
+        buy_token_native_price: f64,
+        usdc_native_price: f64,
+    ) {
+        // an unpriced USDC would make every USD figure infinite
+        if usdc_native_price <= 0.0 {
+            return;
+        }
+        let usd_price = |native_price: f64, decimals: u8| {
+            native_price / usdc_native_price
+                * 10f64.powi(decimals as i32 - constant::USDC_DECIMALS as i32)
+        };
+
+        self.sell_token_usd_price = usd_price(sell_token_native_price, self.sell_decimals);
+        self.buy_token_usd_price = usd_price(buy_token_native_price, self.buy_decimals);
+        self.sell_usd = self.sell * self.sell_token_usd_price;
+        self.net_surplus_usd = self.net_surplus * self.buy_token_usd_price;
+    }
+
+    pub fn has_usd_valuation(&self) -> bool {
+        self.sell_token_usd_price > 0.0 && self.buy_token_usd_price > 0.0
+    }
+
     pub fn no_successful_quote_at_all(&self) -> bool {
         self.zerox_quote_buy == 0.0
             && self.compared_executed_with_zerox_quote == 0.0
@@ -191,15 +249,102 @@ impl Order {
     }
 }
 
+// An amount relative to the order's minimum buy, or to its executed buy when it had none. Zero
+// when there is nothing to compare with, a NaN or infinity can't be stored.
+fn relative_to_buy(amount: f64, min_buy: f64, executed_buy: f64) -> f64 {
+    let denominator = if min_buy == 0.0 {
+        executed_buy
+    } else {
+        min_buy
+    };
+    if denominator == 0.0 {
+        0.0
+    } else {
+        amount / denominator
+    }
+}
+
+// The venues an order's execution is compared against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Venue {
+    Zerox,
+    CowsOwnQuote,
+    Univ3Swap,
+}
+
+impl Venue {
+    pub const ALL: [Venue; 3] = [Venue::Zerox, Venue::CowsOwnQuote, Venue::Univ3Swap];
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Venue::Zerox => "zerox",
+            Venue::CowsOwnQuote => "cows_own_quote",
+            Venue::Univ3Swap => "univ3_swap",
+        }
+    }
+
+    // the Order attributes holding the venue's buy amount and executed minus that amount
+    pub const fn attributes(&self) -> (&'static str, &'static str) {
+        match self {
+            Venue::Zerox => ("zerox_quote_buy", "compared_executed_with_zerox_quote"),
+            Venue::CowsOwnQuote => (
+                "cows_own_quote_buy",
+                "compared_executed_with_cows_own_quote",
+            ),
+            Venue::Univ3Swap => ("univ3_swap_buy", "compared_executed_with_univ3_swap"),
+        }
+    }
+
+    pub fn quote_buy(&self, order: &Order) -> f64 {
+        match self {
+            Venue::Zerox => order.zerox_quote_buy,
+            Venue::CowsOwnQuote => order.cows_own_quote_buy,
+            Venue::Univ3Swap => order.univ3_swap_buy,
+        }
+    }
+
+    pub fn executed_minus_quote(&self, order: &Order) -> f64 {
+        match self {
+            Venue::Zerox => order.compared_executed_with_zerox_quote,
+            Venue::CowsOwnQuote => order.compared_executed_with_cows_own_quote,
+            Venue::Univ3Swap => order.compared_executed_with_univ3_swap,
+        }
+    }
+
+    // None when the venue couldn't quote the order, which a zero buy amount stands for
+    pub fn outcome(&self, order: &Order) -> Option<Outcome> {
+        if self.quote_buy(order) == 0.0 {
+            return None;
+        }
+
+        let difference = self.executed_minus_quote(order);
+        Some(if difference > 0.0 {
+            Outcome::Won
+        } else if difference < 0.0 {
+            Outcome::Lost
+        } else {
+            Outcome::Tied
+        })
+    }
+}
+
+// how CoW's execution did against a venue
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Won,
+    Lost,
+    Tied,
+}
+
 async fn process_order_info(
-    provider: Arc<Provider<Ws>>,
+    tokens: &TokenRegistry,
     address: &str,
     planned_amount: &str,
     executed_amount: &str,
 ) -> eyre::Result<(u8, f64, f64)> {
-    let is_weth = address == constant::WETH;
-    let decimals =
-        get_token_decimals(Arc::clone(&provider), address.parse::<Address>()?, is_weth).await?;
+    let decimals = *tokens.resolve(address).await?.decimals();
 
     let planned = format_decimals_into_f(planned_amount, decimals);
     let executed = format_decimals_into_f(executed_amount, decimals);
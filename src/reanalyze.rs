@@ -0,0 +1,165 @@
+use crate::config::Config;
+use crate::constant;
+use crate::order::Order;
+use crate::services::{
+    aws_dynamodb::{DynamoDbClient, PageRequest, WriteOutcome, WritePolicy},
+    aws_dynamodb_raw_events::{RawEvent, RawEventKind, RawEventStore, RecordedTrade},
+    cow_get_order_api::parse_cow_order,
+    cow_native_price_api::parse_native_price,
+    cow_post_quote_api::parse_cowswap_quote,
+    zerox_get_quote_api::parse_zerox_quote,
+};
+use crate::tokens::TokenRegistry;
+use std::collections::HashSet;
+
+// Rebuilds Order records from the raw event store instead of calling the APIs again, tagging
+// them with `analysis_version`. Stored orders are only replaced by a newer version.
+pub async fn reanalyze(
+    config: &Config,
+    uid: Option<String>,
+    analysis_version: Option<u32>,
+) -> eyre::Result<()> {
+    let analysis_version = analysis_version.unwrap_or(constant::ANALYSIS_VERSION);
+    let raw_events = RawEventStore::new(config).await?;
+    let aws_client = DynamoDbClient::new(config).await?;
+    let tokens = TokenRegistry::new(config).await?;
+
+    let uids = match uid {
+        Some(uid) => vec![uid],
+        None => recorded_trade_uids(&raw_events).await?,
+    };
+
+    let (mut written, mut skipped, mut failed) = (0, 0, 0);
+    for uid in uids {
+        let order = match reanalyze_order(&raw_events, &tokens, &uid, analysis_version).await {
+            Ok(Some(order)) => order,
+            Ok(None) => {
+                skipped += 1;
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Failed to reanalyze order {}: {}", uid, e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        match aws_client
+            .upload_order(&order, WritePolicy::IfNewerVersion)
+            .await
+        {
+            Ok(WriteOutcome::Written) => written += 1,
+            // already stored with this or a newer analysis_version
+            Ok(WriteOutcome::Duplicate) => skipped += 1,
+            Err(e) => {
+                eprintln!("Failed to upload order {}: {}", uid, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "Reanalysis with version {} done: {} written, {} skipped, {} failed",
+        analysis_version, written, skipped, failed
+    );
+    Ok(())
+}
+
+// None when the order wouldn't have been analysed in the first place
+pub async fn reanalyze_order(
+    raw_events: &RawEventStore,
+    tokens: &TokenRegistry,
+    uid: &str,
+    analysis_version: u32,
+) -> eyre::Result<Option<Order>> {
+    let events = raw_events.events_for(uid).await?;
+
+    let trade = latest_success(&events, RawEventKind::Trade)
+        .ok_or_else(|| eyre::eyre!("No trade recorded"))?;
+    let trade: RecordedTrade = serde_json::from_str(&trade.payload)?;
+
+    let cow_order = latest_success(&events, RawEventKind::CowOrder)
+        .ok_or_else(|| eyre::eyre!("No CowSwap order recorded"))?;
+    let (response, should_proceed) = parse_cow_order(&cow_order.payload)?;
+    if !should_proceed {
+        return Ok(None);
+    }
+
+    let mut order = Order::from_cow_api_response(
+        tokens,
+        uid.to_string(),
+        trade.block_number,
+        trade.timestamp,
+        &response,
+    )
+    .await?
+    .with_analysis_version(analysis_version);
+
+    // quotes and prices taken long after the settlement, when an order was analysed late, say
+    // nothing about it
+    let live = |event: &&RawEvent| {
+        trade.timestamp == 0
+            || event.recorded_at / 1000 <= trade.timestamp + constant::LIVE_WINDOW_SECS
+    };
+    let latest_live = |kind| latest_success(&events, kind).filter(live);
+
+    if let Some(event) = latest_live(RawEventKind::ZeroxQuote) {
+        match parse_zerox_quote(&event.payload) {
+            Ok(quote) => order.update_zerox_comparison(&quote),
+            Err(e) => eprintln!("0x get quote of {} unusable: {}", uid, e),
+        }
+    }
+    if let Some(event) = latest_live(RawEventKind::CowQuote) {
+        match parse_cowswap_quote(&event.payload, response.sell()) {
+            Ok(quote) => order.update_cows_own_quote_comparison(&quote),
+            Err(e) => eprintln!("CowSwap own quote of {} unusable: {}", uid, e),
+        }
+    }
+    if let Some(event) = latest_success(&events, RawEventKind::Univ3Swap) {
+        order.update_univ3_swap_comparison(&event.payload);
+    }
+
+    if order.no_successful_quote_at_all() {
+        return Ok(None);
+    }
+
+    let price = |kind| latest_live(kind).and_then(|event| parse_native_price(&event.payload).ok());
+    if let (Some(sell_price), Some(buy_price), Some(usdc_price)) = (
+        price(RawEventKind::SellTokenPrice),
+        price(RawEventKind::BuyTokenPrice),
+        price(RawEventKind::UsdcPrice),
+    ) {
+        order.update_usd_valuation(sell_price, buy_price, usdc_price);
+    }
+
+    Ok(Some(order))
+}
+
+fn latest_success(events: &[RawEvent], kind: RawEventKind) -> Option<&RawEvent> {
+    events
+        .iter()
+        .filter(|event| event.kind == kind && event.error.is_none())
+        .max_by_key(|event| event.recorded_at)
+}
+
+async fn recorded_trade_uids(raw_events: &RawEventStore) -> eyre::Result<Vec<String>> {
+    let mut uids = Vec::new();
+    let mut seen = HashSet::new();
+    let mut page = PageRequest::default();
+
+    loop {
+        let result = raw_events.trade_uids(&page).await?;
+        // a replayed trade is recorded once per replay
+        uids.extend(
+            result
+                .items
+                .into_iter()
+                .filter(|uid| seen.insert(uid.clone())),
+        );
+
+        match result.next_cursor {
+            Some(cursor) => page.cursor = Some(cursor),
+            None => return Ok(uids),
+        }
+    }
+}
@@ -0,0 +1,717 @@
+use crate::config::Config;
+use crate::metrics;
+use async_trait::async_trait;
+use ethers::providers::{
+    Http, Ipc, JsonRpcClient, JsonRpcError, Middleware, Provider, ProviderError, RpcError, Ws,
+};
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug, Display};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+pub type RpcProvider = Provider<RpcClient>;
+
+// An RPC endpoint: an http(s):// or ws(s):// URL, or the path of an IPC socket
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    Http(String),
+    Ws(String),
+    Ipc(PathBuf),
+}
+
+impl FromStr for Endpoint {
+    type Err = eyre::Report;
+
+    fn from_str(endpoint: &str) -> eyre::Result<Self> {
+        let scheme = endpoint.split_once("://").map(|(scheme, _)| scheme);
+        match scheme {
+            Some("http" | "https") => {
+                reqwest::Url::parse(endpoint)?;
+                Ok(Self::Http(endpoint.to_string()))
+            }
+            Some("ws" | "wss") => {
+                reqwest::Url::parse(endpoint)?;
+                Ok(Self::Ws(endpoint.to_string()))
+            }
+            Some("ipc") => Ok(Self::Ipc(PathBuf::from(&endpoint["ipc://".len()..]))),
+            Some(scheme) => Err(eyre::eyre!("unsupported scheme {}://", scheme)),
+            None if endpoint.ends_with(".ipc") => Ok(Self::Ipc(PathBuf::from(endpoint))),
+            None => Err(eyre::eyre!(
+                "expected an http(s):// or ws(s):// URL, or a path ending in .ipc"
+            )),
+        }
+    }
+}
+
+impl Endpoint {
+    // as given, for the clients and the anvil fork
+    pub fn url(&self) -> String {
+        match self {
+            Self::Http(url) | Self::Ws(url) => url.clone(),
+            Self::Ipc(path) => path.display().to_string(),
+        }
+    }
+
+    // The node behind the endpoint, as far as can be told. A provider's HTTP and WebSocket URLs
+    // share a host, and are one node to the quorum.
+    pub fn host(&self) -> String {
+        match self {
+            Self::Http(url) | Self::Ws(url) => reqwest::Url::parse(url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string))
+                .unwrap_or_else(|| url.clone()),
+            Self::Ipc(path) => path.display().to_string(),
+        }
+    }
+
+    // endpoints that can stream the trade events
+    pub fn is_pubsub(&self) -> bool {
+        !matches!(self, Self::Http(_))
+    }
+}
+
+// Scheme, host and port only, provider URLs tend to carry the API key in their path
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(url) | Self::Ws(url) => match reqwest::Url::parse(url) {
+                Ok(url) => write!(f, "{}", url.origin().ascii_serialization()),
+                Err(_) => write!(f, "invalid url"),
+            },
+            Self::Ipc(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl Debug for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+#[derive(Debug)]
+enum Connection {
+    Http(Http),
+    Ws(Ws),
+    Ipc(Ipc),
+    #[cfg(test)]
+    Stub(ethers::providers::MockProvider),
+}
+
+// One per endpoint and process, so every client shares its connection and what is known about
+// its health
+struct EndpointState {
+    endpoint: Endpoint,
+    // WebSocket and IPC endpoints are connected on first use, and again after an error
+    connection: tokio::sync::Mutex<Option<Arc<Connection>>>,
+    failed_at: Mutex<Option<Instant>>,
+}
+
+static ENDPOINTS: LazyLock<Mutex<HashMap<Endpoint, Arc<EndpointState>>>> =
+    LazyLock::new(Default::default);
+
+impl EndpointState {
+    fn get(endpoint: &Endpoint) -> Arc<Self> {
+        let mut endpoints = ENDPOINTS.lock().unwrap();
+        let state = endpoints.entry(endpoint.clone()).or_insert_with(|| {
+            Arc::new(Self {
+                endpoint: endpoint.clone(),
+                connection: tokio::sync::Mutex::new(None),
+                failed_at: Mutex::new(None),
+            })
+        });
+        Arc::clone(state)
+    }
+
+    fn is_healthy(&self, cooldown: Duration) -> bool {
+        self.failed_at
+            .lock()
+            .unwrap()
+            .is_none_or(|at| at.elapsed() > cooldown)
+    }
+
+    fn mark_failed(&self) {
+        *self.failed_at.lock().unwrap() = Some(Instant::now());
+        metrics::RPC_FAILURES
+            .with_label_values(&[&self.endpoint.to_string()])
+            .inc();
+    }
+
+    fn mark_healthy(&self) {
+        *self.failed_at.lock().unwrap() = None;
+    }
+
+    async fn connection(&self) -> Result<Arc<Connection>, RpcClientError> {
+        let mut connection = self.connection.lock().await;
+        if let Some(connection) = connection.as_ref() {
+            return Ok(Arc::clone(connection));
+        }
+
+        let connected = Arc::new(match &self.endpoint {
+            Endpoint::Http(url) => Connection::Http(
+                Http::from_str(url).map_err(|e| RpcClientError::Transport(e.to_string()))?,
+            ),
+            Endpoint::Ws(url) => Connection::Ws(
+                Ws::connect(url.as_str())
+                    .await
+                    .map_err(|e| RpcClientError::Transport(e.to_string()))?,
+            ),
+            Endpoint::Ipc(path) => Connection::Ipc(
+                Ipc::connect(path)
+                    .await
+                    .map_err(|e| RpcClientError::Transport(e.to_string()))?,
+            ),
+        });
+        *connection = Some(Arc::clone(&connected));
+        Ok(connected)
+    }
+
+    async fn request(&self, method: &str, params: &Value) -> Result<Value, RpcClientError> {
+        let connection = match self.connection().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                self.mark_failed();
+                return Err(self.redact(e));
+            }
+        };
+        let result = match connection.as_ref() {
+            Connection::Http(client) => client.request(method, params).await.map_err(classify),
+            Connection::Ws(client) => client.request(method, params).await.map_err(classify),
+            Connection::Ipc(client) => client.request(method, params).await.map_err(classify),
+            #[cfg(test)]
+            Connection::Stub(client) => client.request(method, params).await.map_err(classify),
+        };
+
+        match result {
+            // the node answered, even when it was with an error
+            Ok(_) | Err(RpcClientError::JsonRpc(_)) => {
+                self.mark_healthy();
+                result
+            }
+            Err(e) => {
+                self.mark_failed();
+                *self.connection.lock().await = None;
+                Err(self.redact(e))
+            }
+        }
+    }
+
+    // transport errors quote the URL they failed on
+    fn redact(&self, e: RpcClientError) -> RpcClientError {
+        match e {
+            RpcClientError::Transport(e) => RpcClientError::Transport(
+                e.replace(&self.endpoint.url(), &self.endpoint.to_string()),
+            ),
+            e => e,
+        }
+    }
+}
+
+// an error response is the node's answer, anything else is the endpoint's fault
+fn classify(e: impl RpcError) -> RpcClientError {
+    match e.as_error_response() {
+        Some(response) => RpcClientError::JsonRpc(response.clone()),
+        None => RpcClientError::Transport(e.to_string()),
+    }
+}
+
+#[derive(Debug)]
+pub enum RpcClientError {
+    JsonRpc(JsonRpcError),
+    Transport(String),
+    Serde(serde_json::Error),
+    // the quorum, and what the endpoints answered instead
+    NoQuorum(usize, String),
+}
+
+impl Display for RpcClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::JsonRpc(e) => write!(f, "{}", e),
+            Self::Transport(e) => write!(f, "{}", e),
+            Self::Serde(e) => write!(f, "{}", e),
+            Self::NoQuorum(quorum, detail) => {
+                write!(f, "No {} RPC endpoints agreed: {}", quorum, detail)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RpcClientError {}
+
+impl From<serde_json::Error> for RpcClientError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Serde(e)
+    }
+}
+
+impl RpcError for RpcClientError {
+    fn as_error_response(&self) -> Option<&JsonRpcError> {
+        match self {
+            Self::JsonRpc(e) => Some(e),
+            _ => None,
+        }
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        match self {
+            Self::Serde(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<RpcClientError> for ProviderError {
+    fn from(e: RpcClientError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(e))
+    }
+}
+
+// Sends each request to the first healthy endpoint, failing over to the next when an endpoint
+// doesn't answer. With a quorum above 1 every endpoint is asked at once instead, and the first
+// answer that many endpoint hosts agree on is taken.
+#[derive(Clone)]
+pub struct RpcClient {
+    endpoints: Vec<Arc<EndpointState>>,
+    quorum: usize,
+    cooldown: Duration,
+}
+
+impl Debug for RpcClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcClient")
+            .field(
+                "endpoints",
+                &self
+                    .endpoints
+                    .iter()
+                    .map(|state| &state.endpoint)
+                    .collect::<Vec<_>>(),
+            )
+            .field("quorum", &self.quorum)
+            .finish()
+    }
+}
+
+impl RpcClient {
+    // healthy endpoints first, in the configured order, the others as a last resort
+    fn by_health(&self) -> Vec<&Arc<EndpointState>> {
+        let (mut healthy, unhealthy): (Vec<_>, Vec<_>) = self
+            .endpoints
+            .iter()
+            .partition(|state| state.is_healthy(self.cooldown));
+        healthy.extend(unhealthy);
+        healthy
+    }
+
+    async fn failover(&self, method: &str, params: &Value) -> Result<Value, RpcClientError> {
+        let mut last_error = None;
+        for state in self.by_health() {
+            match state.request(method, params).await {
+                Err(RpcClientError::Transport(e)) => {
+                    eprintln!(
+                        "RPC endpoint {} failed, failing over: {}",
+                        state.endpoint, e
+                    );
+                    last_error = Some(RpcClientError::Transport(e));
+                }
+                result => return result,
+            }
+        }
+        Err(last_error.unwrap_or(RpcClientError::Transport("No RPC endpoints".to_string())))
+    }
+
+    async fn quorum(&self, method: &str, params: &Value) -> Result<Value, RpcClientError> {
+        let mut requests = self
+            .endpoints
+            .iter()
+            .map(
+                |state| async move { (state.endpoint.host(), state.request(method, params).await) },
+            )
+            .collect::<FuturesUnordered<_>>();
+
+        // by the hosts that gave each answer, endpoints on one host only count once
+        let mut answers: Vec<(Value, HashSet<String>)> = Vec::new();
+        let mut errors = Vec::new();
+        // that many nodes answering with an error, like a revert, is an answer as well
+        let mut error_responses: Vec<JsonRpcError> = Vec::new();
+        let mut error_hosts = HashSet::new();
+        while let Some((host, result)) = requests.next().await {
+            match result {
+                Ok(value) => {
+                    let hosts = match answers.iter_mut().position(|(answer, _)| *answer == value) {
+                        Some(i) => &mut answers[i].1,
+                        None => {
+                            answers.push((value.clone(), HashSet::new()));
+                            &mut answers.last_mut().unwrap().1
+                        }
+                    };
+                    hosts.insert(host);
+                    if hosts.len() >= self.quorum {
+                        return Ok(value);
+                    }
+                }
+                Err(RpcClientError::JsonRpc(e)) => {
+                    errors.push(e.to_string());
+                    error_responses.push(e);
+                    error_hosts.insert(host);
+                    if error_hosts.len() >= self.quorum {
+                        return Err(RpcClientError::JsonRpc(error_responses.swap_remove(0)));
+                    }
+                }
+                Err(e) => errors.push(e.to_string()),
+            }
+        }
+
+        let detail = if errors.is_empty() {
+            format!("{} different answers", answers.len())
+        } else {
+            errors.join(", ")
+        };
+        Err(RpcClientError::NoQuorum(self.quorum, detail))
+    }
+}
+
+#[async_trait]
+impl JsonRpcClient for RpcClient {
+    type Error = RpcClientError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        // a request without parameters still sends an empty list, some nodes reject null
+        let params = match serde_json::to_value(params)? {
+            Value::Null => Value::Array(Vec::new()),
+            params => params,
+        };
+
+        let value = if self.quorum > 1 {
+            self.quorum(method, &params).await?
+        } else {
+            self.failover(method, &params).await?
+        };
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+// The configured RPC endpoints, see the [rpc] section of the config
+#[derive(Clone, Debug)]
+pub struct Rpc {
+    client: RpcClient,
+    archive: Endpoint,
+}
+
+impl Rpc {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            client: RpcClient {
+                endpoints: config
+                    .rpc_endpoints()
+                    .iter()
+                    .map(EndpointState::get)
+                    .collect(),
+                quorum: config.rpc_quorum(),
+                cooldown: config.rpc_failover_cooldown(),
+            },
+            archive: config.archive_rpc_endpoint().clone(),
+        }
+    }
+
+    // for everyday reads, answered by one endpoint
+    pub fn provider(&self) -> RpcProvider {
+        Provider::new(RpcClient {
+            quorum: 1,
+            ..self.client.clone()
+        })
+    }
+
+    // for reads that end up stored, like token decimals and block timestamps, answered once
+    // the configured quorum of endpoints agrees
+    pub fn quorum_provider(&self) -> RpcProvider {
+        Provider::new(self.client.clone())
+    }
+
+    // forked by the Uniswap simulation, which reads state at past blocks
+    pub fn archive(&self) -> &Endpoint {
+        &self.archive
+    }
+
+    // the healthy WebSocket or IPC endpoint to take the trade stream from, if any
+    pub fn pubsub_endpoint(&self) -> Option<Endpoint> {
+        self.client
+            .by_health()
+            .into_iter()
+            .map(|state| &state.endpoint)
+            .find(|endpoint| endpoint.is_pubsub())
+            .cloned()
+    }
+
+    // lets the next connection try another endpoint
+    pub fn mark_failed(&self, endpoint: &Endpoint) {
+        EndpointState::get(endpoint).mark_failed();
+    }
+
+    // Asks every endpoint for the latest block, returning each one's block number or error
+    pub async fn check(&self) -> Vec<(Endpoint, eyre::Result<u64>)> {
+        let checks = self.client.endpoints.iter().map(|state| async move {
+            let client = RpcClient {
+                endpoints: vec![Arc::clone(state)],
+                quorum: 1,
+                cooldown: self.client.cooldown,
+            };
+            let result = Provider::new(client)
+                .get_block_number()
+                .await
+                .map(|block| block.as_u64())
+                .map_err(eyre::Report::from);
+            (state.endpoint.clone(), result)
+        });
+        futures::future::join_all(checks).await
+    }
+
+    pub fn quorum(&self) -> usize {
+        self.client.quorum
+    }
+}
+
+// Unix seconds of a block. Orders keep it for good, so it is best read through the quorum.
+pub async fn block_timestamp<M: Middleware>(provider: &M, block_number: u64) -> eyre::Result<u64> {
+    provider
+        .get_block(block_number)
+        .await
+        .map_err(|e| eyre::eyre!("{}", e))?
+        .map(|block| block.timestamp.as_u64())
+        .ok_or_else(|| eyre::eyre!("Block {} not found", block_number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{MockError, MockProvider, MockResponse};
+    use ethers::types::U64;
+
+    // an endpoint answering from `stub`, which fails like a dead node once out of responses
+    fn stub(endpoint: &str) -> (Arc<EndpointState>, MockProvider) {
+        let stub = MockProvider::new();
+        let state = Arc::new(EndpointState {
+            endpoint: endpoint.parse().unwrap(),
+            connection: tokio::sync::Mutex::new(Some(Arc::new(Connection::Stub(stub.clone())))),
+            failed_at: Mutex::new(None),
+        });
+        (state, stub)
+    }
+
+    fn answering(endpoint: &str, block: u64) -> Arc<EndpointState> {
+        let (state, stub) = stub(endpoint);
+        stub.push(U64::from(block)).unwrap();
+        state
+    }
+
+    fn client(endpoints: Vec<Arc<EndpointState>>, quorum: usize) -> RpcClient {
+        RpcClient {
+            endpoints,
+            quorum,
+            cooldown: Duration::from_secs(60),
+        }
+    }
+
+    async fn block_number(client: &RpcClient) -> Result<U64, RpcClientError> {
+        client.request("eth_blockNumber", ()).await
+    }
+
+    fn reverted() -> JsonRpcError {
+        JsonRpcError {
+            code: 3,
+            message: "execution reverted".into(),
+            data: None,
+        }
+    }
+
+    #[test]
+    fn endpoints_parse_by_scheme() {
+        assert_eq!(
+            "https://eth.example.com/v2/key"
+                .parse::<Endpoint>()
+                .unwrap(),
+            Endpoint::Http("https://eth.example.com/v2/key".into())
+        );
+        assert_eq!(
+            "wss://eth.example.com".parse::<Endpoint>().unwrap(),
+            Endpoint::Ws("wss://eth.example.com".into())
+        );
+        assert_eq!(
+            "ipc:///tmp/geth.sock".parse::<Endpoint>().unwrap(),
+            Endpoint::Ipc("/tmp/geth.sock".into())
+        );
+        assert_eq!(
+            "/var/run/geth.ipc".parse::<Endpoint>().unwrap(),
+            Endpoint::Ipc("/var/run/geth.ipc".into())
+        );
+        for invalid in ["ftp://eth.example.com", "eth.example.com", "https://"] {
+            assert!(invalid.parse::<Endpoint>().is_err(), "{}", invalid);
+        }
+
+        let http = "http://eth.example.com".parse::<Endpoint>().unwrap();
+        assert!(!http.is_pubsub());
+        assert!("ws://eth.example.com"
+            .parse::<Endpoint>()
+            .unwrap()
+            .is_pubsub());
+    }
+
+    #[test]
+    fn endpoints_show_without_their_path() {
+        let endpoint = "https://eth.example.com/v2/secret-key"
+            .parse::<Endpoint>()
+            .unwrap();
+        assert_eq!(endpoint.to_string(), "https://eth.example.com");
+        assert_eq!(format!("{:?}", endpoint), "https://eth.example.com");
+        // the clients still get the full URL
+        assert_eq!(endpoint.url(), "https://eth.example.com/v2/secret-key");
+
+        let endpoint = "wss://eth.example.com:8546/secret-key"
+            .parse::<Endpoint>()
+            .unwrap();
+        assert_eq!(endpoint.to_string(), "wss://eth.example.com:8546");
+    }
+
+    #[test]
+    fn transport_errors_are_redacted() {
+        let (state, _) = stub("https://eth.example.com/v2/secret-key");
+        let error = state.redact(RpcClientError::Transport(
+            "error sending request for url (https://eth.example.com/v2/secret-key)".into(),
+        ));
+        assert!(!error.to_string().contains("secret-key"), "{}", error);
+    }
+
+    #[test]
+    fn http_and_ws_urls_of_a_provider_share_a_host() {
+        let http = "https://eth.example.com/v2/key"
+            .parse::<Endpoint>()
+            .unwrap();
+        let ws = "wss://eth.example.com/ws/v2/key"
+            .parse::<Endpoint>()
+            .unwrap();
+        assert_eq!(http.host(), ws.host());
+        assert_ne!(
+            http.host(),
+            "https://other.example.com"
+                .parse::<Endpoint>()
+                .unwrap()
+                .host()
+        );
+    }
+
+    #[tokio::test]
+    async fn a_failing_endpoint_is_failed_over() {
+        let (down, _) = stub("https://down.example.com");
+        let client = client(
+            vec![down.clone(), answering("https://up.example.com", 7)],
+            1,
+        );
+
+        assert_eq!(block_number(&client).await.unwrap(), U64::from(7));
+        assert!(!down.is_healthy(client.cooldown));
+        // the failed endpoint goes last until its cooldown is over
+        assert_eq!(
+            client.by_health()[0].endpoint.to_string(),
+            "https://up.example.com"
+        );
+    }
+
+    #[tokio::test]
+    async fn an_error_response_is_not_failed_over() {
+        let (reverting, stub_reverting) = stub("https://a.example.com");
+        stub_reverting.push_response(MockResponse::Error(reverted()));
+        let (next, stub_next) = stub("https://b.example.com");
+        let client = client(vec![reverting.clone(), next], 1);
+
+        assert!(matches!(
+            block_number(&client).await,
+            Err(RpcClientError::JsonRpc(_))
+        ));
+        assert!(reverting.is_healthy(client.cooldown));
+        assert!(matches!(
+            stub_next.assert_request("eth_blockNumber", ()),
+            Err(MockError::EmptyRequests)
+        ));
+    }
+
+    #[tokio::test]
+    async fn every_endpoint_failing_is_an_error() {
+        let (a, _) = stub("https://a.example.com");
+        let (b, _) = stub("https://b.example.com");
+        assert!(matches!(
+            block_number(&client(vec![a, b], 1)).await,
+            Err(RpcClientError::Transport(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn the_quorum_takes_an_answer_enough_hosts_agree_on() {
+        let client = client(
+            vec![
+                answering("https://a.example.com", 7),
+                answering("https://b.example.com", 8),
+                answering("https://c.example.com", 7),
+            ],
+            2,
+        );
+        assert_eq!(block_number(&client).await.unwrap(), U64::from(7));
+    }
+
+    #[tokio::test]
+    async fn a_disagreeing_quorum_is_rejected() {
+        let client = client(
+            vec![
+                answering("https://a.example.com", 7),
+                answering("https://b.example.com", 8),
+                answering("https://c.example.com", 9),
+            ],
+            2,
+        );
+        let error = block_number(&client).await.unwrap_err();
+        assert!(matches!(error, RpcClientError::NoQuorum(2, _)), "{}", error);
+        assert!(
+            error.to_string().contains("3 different answers"),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn endpoints_on_one_host_count_once() {
+        let client = client(
+            vec![
+                answering("https://a.example.com/v2/key", 7),
+                answering("wss://a.example.com/ws/v2/key", 7),
+                answering("https://b.example.com", 8),
+            ],
+            2,
+        );
+        assert!(matches!(
+            block_number(&client).await,
+            Err(RpcClientError::NoQuorum(2, _))
+        ));
+    }
+
+    #[tokio::test]
+    async fn enough_hosts_answering_with_an_error_is_the_answer() {
+        let (a, stub_a) = stub("https://a.example.com");
+        stub_a.push_response(MockResponse::Error(reverted()));
+        let (b, stub_b) = stub("https://b.example.com");
+        stub_b.push_response(MockResponse::Error(reverted()));
+
+        assert!(matches!(
+            block_number(&client(vec![a, b], 2)).await,
+            Err(RpcClientError::JsonRpc(e)) if e.message == "execution reverted"
+        ));
+    }
+}
@@ -1,119 +1,523 @@
-use crate::helper::EnvConfig;
-use crate::order::Order;
-use aws_sdk_dynamodb::{operation::get_item::GetItemOutput, types::AttributeValue, Client, Error};
-use ethers::{
-    middleware::Middleware,
-    providers::{Http, Provider},
-};
-use std::collections::HashMap;
+use crate::config::Config;
+use crate::constant::ANALYSIS_VERSION;
+use crate::metrics;
+use crate::order::{Order, Outcome, Venue};
+use crate::rpc::{block_timestamp, Rpc};
+use crate::services::aws_dynamodb_serde::to_item;
+use aws_sdk_dynamodb::{operation::get_item::GetItemOutput, types::AttributeValue, Client};
+use ethers::middleware::Middleware;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub type Item = HashMap<String, AttributeValue>;
+
+// items per page when a request doesn't ask for a limit, and the most it may ask for
+const DEFAULT_PAGE_SIZE: i32 = 100;
+const MAX_PAGE_SIZE: i32 = 1000;
+
+// when an order is due to be archived, in unix seconds. It is not a TTL, orders are only
+// deleted once `archive` has stored them
+pub const EXPIRES_AT: &str = "expires_at";
+
+// Every order is written with feed = FEED, so FeedIndex lists the whole table by timestamp.
+// That puts every write, and every query naming neither a token nor an owner, on one partition,
+// good for roughly 1000 writes and 3000 reads a second. Orders settle far slower than that, but
+// the feed would have to be sharded, e.g. by day, before unfiltered reads grow anywhere near it.
+pub const FEED: &str = "order";
+
+#[derive(Debug, Clone, Copy)]
+pub enum OrderIndex {
+    BuyToken,
+    SellToken,
+    Owner,
+    Feed,
+}
+
+impl OrderIndex {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            OrderIndex::BuyToken => "BuyTokenIndex",
+            OrderIndex::SellToken => "SellTokenIndex",
+            OrderIndex::Owner => "OwnerIndex",
+            OrderIndex::Feed => "FeedIndex",
+        }
+    }
+
+    pub const fn partition_key(&self) -> &'static str {
+        match self {
+            OrderIndex::BuyToken => "buy_token",
+            OrderIndex::SellToken => "sell_token",
+            OrderIndex::Owner => "owner",
+            OrderIndex::Feed => "feed",
+        }
+    }
+}
+
+// Amount filters are compared as DynamoDB numbers, which hold 38 digits at most. Anything under
+// a token's smallest unit (10^-18) is no filter at all.
+const MIN_AMOUNT: f64 = 1e-18;
+const MAX_AMOUNT: f64 = 1e30;
+
+fn check_amount(name: &str, value: f64) -> eyre::Result<()> {
+    if !value.is_finite() || !(0.0..=MAX_AMOUNT).contains(&value) {
+        return Err(eyre::eyre!(
+            "{} must be between 0 and {}, got {}",
+            name,
+            MAX_AMOUNT,
+            value
+        ));
+    }
+    if value > 0.0 && value < MIN_AMOUNT {
+        return Err(eyre::eyre!(
+            "{} must be 0 or at least {}, got {}",
+            name,
+            MIN_AMOUNT,
+            value
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Newest,
+    Oldest,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct OrderQuery {
+    pub buy_token: Option<String>,
+    pub sell_token: Option<String>,
+    pub owner: Option<String>,
+    pub from_timestamp: Option<u64>,
+    pub to_timestamp: Option<u64>,
+    pub from_block: Option<u64>,
+    pub to_block: Option<u64>,
+    // in sell token units, so only accepted together with sell_token
+    pub min_sell_amount: Option<f64>,
+    // the same in USD, for a minimum trade size across tokens. Orders CowSwap had no price for
+    // are left out
+    pub min_sell_usd: Option<f64>,
+    // orders CoW executed better, or worse, than this venue's quote
+    pub beat: Option<Venue>,
+    pub lost_to: Option<Venue>,
+    #[serde(default)]
+    pub sort: SortOrder,
+}
+
+impl OrderQuery {
+    // 1 WBTC and 1 USDC aren't the same trade size
+    pub fn validate(&self) -> eyre::Result<()> {
+        if self.min_sell_amount.is_some() && self.sell_token.is_none() {
+            return Err(eyre::eyre!(
+                "min_sell_amount is in sell token units and needs sell_token, use min_sell_usd \
+                 across tokens"
+            ));
+        }
+        for (name, value) in [
+            ("min_sell_amount", self.min_sell_amount),
+            ("min_sell_usd", self.min_sell_usd),
+        ] {
+            if let Some(value) = value {
+                check_amount(name, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    // the most selective index wins, the rest of the filters are applied on top of it
+    pub fn index(&self) -> (OrderIndex, String) {
+        [
+            (OrderIndex::Owner, &self.owner),
+            (OrderIndex::BuyToken, &self.buy_token),
+            (OrderIndex::SellToken, &self.sell_token),
+        ]
+        .into_iter()
+        .find_map(|(index, value)| value.as_ref().map(|v| (index, v.to_lowercase())))
+        .unwrap_or((OrderIndex::Feed, FEED.to_string()))
+    }
+
+    fn filters(&self, index: OrderIndex) -> Vec<(&'static str, String)> {
+        [
+            (OrderIndex::Owner, &self.owner),
+            (OrderIndex::BuyToken, &self.buy_token),
+            (OrderIndex::SellToken, &self.sell_token),
+        ]
+        .into_iter()
+        .filter(|(i, _)| i.partition_key() != index.partition_key())
+        .filter_map(|(i, value)| {
+            value
+                .as_ref()
+                .map(|v| (i.partition_key(), v.to_lowercase()))
+        })
+        .collect()
+    }
+
+    // the same filters applied in memory, for orders that don't come from the table
+    pub fn matches(&self, order: &Order) -> bool {
+        let equals = |filter: &Option<String>, value: &str| {
+            filter
+                .as_ref()
+                .is_none_or(|filter| filter.eq_ignore_ascii_case(value))
+        };
+        let within = |from: Option<u64>, to: Option<u64>, value: u64| {
+            from.is_none_or(|from| value >= from) && to.is_none_or(|to| value <= to)
+        };
+
+        equals(&self.owner, order.owner())
+            && equals(&self.buy_token, order.buy_token())
+            && equals(&self.sell_token, order.sell_token())
+            && within(self.from_timestamp, self.to_timestamp, *order.timestamp())
+            && within(self.from_block, self.to_block, *order.block_number())
+            && self
+                .min_sell_amount
+                .is_none_or(|amount| *order.sell() >= amount)
+            && self.min_sell_usd.is_none_or(|usd| *order.sell_usd() >= usd)
+            && self
+                .beat
+                .is_none_or(|venue| venue.outcome(order) == Some(Outcome::Won))
+            && self
+                .lost_to
+                .is_none_or(|venue| venue.outcome(order) == Some(Outcome::Lost))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PageRequest {
+    pub limit: Option<i32>,
+    pub cursor: Option<String>,
+}
+
+impl PageRequest {
+    pub fn limit(&self) -> i32 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    pub fn start_key(&self) -> eyre::Result<Option<Item>> {
+        self.cursor.as_deref().map(decode_cursor).transpose()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+
+    pub fn try_map<U>(self, f: impl FnMut(T) -> eyre::Result<U>) -> eyre::Result<Page<U>> {
+        Ok(Page {
+            items: self.items.into_iter().map(f).collect::<eyre::Result<_>>()?,
+            next_cursor: self.next_cursor,
+        })
+    }
+}
+
+// a cursor is the LastEvaluatedKey of DynamoDB, which only ever holds key attributes
+#[derive(Serialize, Deserialize)]
+enum CursorValue {
+    S(String),
+    N(String),
+}
+
+fn encode_cursor(key: &Item) -> eyre::Result<String> {
+    let mut values = BTreeMap::new();
+    for (name, value) in key {
+        let value = match value {
+            AttributeValue::S(s) => CursorValue::S(s.clone()),
+            AttributeValue::N(n) => CursorValue::N(n.clone()),
+            _ => return Err(eyre::eyre!("Unsupported key attribute type for {}", name)),
+        };
+        values.insert(name.clone(), value);
+    }
+
+    Ok(hex::encode(serde_json::to_vec(&values)?))
+}
+
+fn decode_cursor(cursor: &str) -> eyre::Result<Item> {
+    let bytes = hex::decode(cursor).map_err(|e| eyre::eyre!("Invalid cursor: {}", e))?;
+    let values: BTreeMap<String, CursorValue> =
+        serde_json::from_slice(&bytes).map_err(|e| eyre::eyre!("Invalid cursor: {}", e))?;
+
+    Ok(values
+        .into_iter()
+        .map(|(name, value)| match value {
+            CursorValue::S(s) => (name, AttributeValue::S(s)),
+            CursorValue::N(n) => (name, AttributeValue::N(n)),
+        })
+        .collect())
+}
+
+// Keeps requesting pages of `limit` items until that many have matched or the table is
+// exhausted. DynamoDB applies Limit before a filter, so requests aren't narrowed down to what is
+// still missing, which would have a selective filter read one item per request. A page that
+// overshoots is cut at `limit` instead, and the next one starts after its last item, whose key
+// is made of `key_attributes`: the table's key, plus the index's for a query on an index.
+pub(crate) async fn paginate<F, Fut>(
+    page: &PageRequest,
+    key_attributes: &[&str],
+    mut fetch: F,
+) -> eyre::Result<Page<Item>>
+where
+    F: FnMut(Option<Item>, i32) -> Fut,
+    Fut: Future<Output = eyre::Result<(Vec<Item>, Option<Item>)>>,
+{
+    let limit = page.limit();
+    let mut items: Vec<Item> = Vec::new();
+    let mut start_key = page.start_key()?;
+
+    loop {
+        let (page_items, last_key) = fetch(start_key, limit).await?;
+        let room = limit as usize - items.len();
+        if page_items.len() > room {
+            items.extend(page_items.into_iter().take(room));
+            let last = items.last().expect("a page holds at least one item");
+            return Ok(Page {
+                next_cursor: Some(encode_cursor(&key_of(last, key_attributes)?)?),
+                items,
+            });
+        }
+        items.extend(page_items);
+
+        match last_key.filter(|key| !key.is_empty()) {
+            Some(key) if items.len() as i32 >= limit => {
+                return Ok(Page {
+                    items,
+                    next_cursor: Some(encode_cursor(&key)?),
+                });
+            }
+            Some(key) => start_key = Some(key),
+            None => {
+                return Ok(Page {
+                    items,
+                    next_cursor: None,
+                })
+            }
+        }
+    }
+}
+
+fn key_of(item: &Item, key_attributes: &[&str]) -> eyre::Result<Item> {
+    key_attributes
+        .iter()
+        .map(|name| {
+            let value = item
+                .get(*name)
+                .ok_or_else(|| eyre::eyre!("Item without key attribute {}", name))?;
+            Ok((name.to_string(), value.clone()))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WritePolicy {
+    // only write orders that aren't stored yet
+    IfAbsent,
+    // replace a stored order only if it was analysed by an older ANALYSIS_VERSION
+    IfNewerVersion,
+    Overwrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteOutcome {
+    Written,
+    // the stored order was kept, its duplicate_count was bumped instead
+    Duplicate,
+}
 
 #[derive(Clone)]
 pub struct DynamoDbClient {
     client: Client,
     table_name: String,
+    // seconds an order is kept after settlement
+    retention: Option<u64>,
 }
 
 impl DynamoDbClient {
-    pub async fn new() -> eyre::Result<Self> {
-        let config = aws_config::defaults(aws_config::BehaviorVersion::latest())
-            .load()
-            .await;
-        let client = Client::new(&config);
+    pub async fn new(config: &Config) -> eyre::Result<Self> {
+        let client = new_client(config).await;
 
         Ok(Self {
             client,
-            table_name: "orders".to_string(),
+            table_name: config.orders_table().to_string(),
+            retention: config
+                .order_retention_days()
+                .map(|days| days * 24 * 60 * 60),
         })
     }
 
-    pub async fn upload_order(&self, order: &Order) -> eyre::Result<()> {
-        let item: HashMap<String, AttributeValue> = HashMap::from([
-            ("uid".to_string(), to_attr(order.uid(), "S")),
-            ("owner".to_string(), to_attr(order.owner(), "S")),
-            ("buy_token".to_string(), to_attr(order.buy_token(), "S")),
-            ("sell_token".to_string(), to_attr(order.sell_token(), "S")),
-            (
-                "buy_decimals".to_string(),
-                to_attr(order.buy_decimals(), "N"),
-            ),
-            (
-                "sell_decimals".to_string(),
-                to_attr(order.sell_decimals(), "N"),
-            ),
-            ("min_buy".to_string(), to_attr(order.min_buy(), "N")),
-            ("sell".to_string(), to_attr(order.sell(), "N")),
-            (
-                "executed_buy".to_string(),
-                to_attr(order.executed_buy(), "N"),
-            ),
-            (
-                "executed_sell".to_string(),
-                to_attr(order.executed_sell(), "N"),
-            ),
-            ("net_surplus".to_string(), to_attr(order.net_surplus(), "N")),
-            (
-                "surplus_percentage".to_string(),
-                to_attr(order.surplus_percentage(), "N"),
-            ),
-            (
-                "zerox_quote_buy".to_string(),
-                to_attr(order.zerox_quote_buy(), "N"),
-            ),
-            (
-                "compared_executed_with_zerox_quote".to_string(),
-                to_attr(order.compared_executed_with_zerox_quote(), "N"),
-            ),
-            (
-                "compared_with_zerox_percentage".to_string(),
-                to_attr(order.compared_with_zerox_percentage(), "N"),
-            ),
-            (
-                "cows_own_quote_buy".to_string(),
-                to_attr(order.cows_own_quote_buy(), "N"),
-            ),
-            (
-                "compared_executed_with_cows_own_quote".to_string(),
-                to_attr(order.compared_executed_with_cows_own_quote(), "N"),
-            ),
-            (
-                "compared_with_cows_own_quote_percentage".to_string(),
-                to_attr(order.compared_with_cows_own_quote_percentage(), "N"),
-            ),
-            (
-                "univ3_swap_buy".to_string(),
-                to_attr(order.univ3_swap_buy(), "N"),
-            ),
-            (
-                "compared_executed_with_univ3_swap".to_string(),
-                to_attr(order.compared_executed_with_univ3_swap(), "N"),
-            ),
-            (
-                "compared_with_univ3_swap_percentage".to_string(),
-                to_attr(order.compared_with_univ3_swap_percentage(), "N"),
-            ),
-            (
-                "block_number".to_string(),
-                to_attr(order.block_number(), "N"),
-            ),
-            ("timestamp".to_string(), to_attr(order.timestamp(), "N")),
-        ]);
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
 
-        self.client
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    pub async fn upload_order(
+        &self,
+        order: &Order,
+        policy: WritePolicy,
+    ) -> eyre::Result<WriteOutcome> {
+        order.check_finite()?;
+        let mut item = to_item(order)?;
+        item.insert(
+            OrderIndex::Feed.partition_key().to_string(),
+            AttributeValue::S(FEED.to_string()),
+        );
+        if let Some(retention) = self.retention {
+            // orders whose settlement time is unknown are kept for the retention from now on
+            let settled_at = match *order.timestamp() {
+                0 => SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+                timestamp => timestamp,
+            };
+            item.insert(
+                EXPIRES_AT.to_string(),
+                AttributeValue::N((settled_at + retention).to_string()),
+            );
+        }
+
+        let mut request = self
+            .client
             .put_item()
             .table_name(&self.table_name)
-            .set_item(Some(item))
+            .set_item(Some(item));
+
+        request = match policy {
+            WritePolicy::IfAbsent => request.condition_expression("attribute_not_exists(uid)"),
+            WritePolicy::IfNewerVersion => request
+                .condition_expression(
+                    "attribute_not_exists(uid) OR attribute_not_exists(analysis_version) \
+                     OR analysis_version < :version",
+                )
+                .expression_attribute_values(
+                    ":version",
+                    AttributeValue::N(order.analysis_version().to_string()),
+                ),
+            WritePolicy::Overwrite => request,
+        };
+
+        let timer = metrics::DYNAMODB_DURATION
+            .with_label_values(&["put_order"])
+            .start_timer();
+        let result = request.send().await;
+        timer.observe_duration();
+
+        match result {
+            Ok(_) => Ok(WriteOutcome::Written),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                self.record_duplicate(order.uid()).await?;
+                Ok(WriteOutcome::Duplicate)
+            }
+            Err(e) => {
+                metrics::DYNAMODB_ERRORS
+                    .with_label_values(&["put_order"])
+                    .inc();
+                Err(e.into())
+            }
+        }
+    }
+
+    // Checked before an order is analysed, so a replayed trade doesn't spend quotes and a fork on
+    // an order the policy would keep as stored. upload_order's condition still catches races.
+    pub async fn is_duplicate(&self, uid: &str, policy: WritePolicy) -> eyre::Result<bool> {
+        if policy == WritePolicy::Overwrite {
+            return Ok(false);
+        }
+        let Some(item) = self
+            .get_item(&self.table_name, "uid", AttributeValue::S(uid.to_string()))
+            .await?
+        else {
+            return Ok(false);
+        };
+
+        let stored_version = item
+            .get("analysis_version")
+            .and_then(|version| version.as_n().ok())
+            .and_then(|version| version.parse::<u32>().ok());
+        let duplicate = match policy {
+            WritePolicy::IfAbsent => true,
+            WritePolicy::IfNewerVersion => {
+                stored_version.is_some_and(|version| version >= ANALYSIS_VERSION)
+            }
+            WritePolicy::Overwrite => false,
+        };
+
+        if duplicate {
+            self.record_duplicate(uid).await?;
+        }
+        Ok(duplicate)
+    }
+
+    async fn record_duplicate(&self, uid: &str) -> eyre::Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("uid", AttributeValue::S(uid.to_string()))
+            .update_expression("ADD duplicate_count :one SET last_duplicate_at = :now")
+            .condition_expression("attribute_exists(uid)")
+            .expression_attribute_values(":one", AttributeValue::N("1".to_string()))
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
             .send()
             .await?;
 
         Ok(())
     }
 
+    // fills in the timestamp of an order stored without one
+    pub async fn set_timestamp(&self, uid: &str, timestamp: u64) -> eyre::Result<()> {
+        let result = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .key("uid", AttributeValue::S(uid.to_string()))
+            .update_expression("SET #timestamp = :timestamp")
+            .condition_expression("#timestamp = :unknown")
+            .expression_attribute_names("#timestamp", "timestamp")
+            .expression_attribute_values(":timestamp", AttributeValue::N(timestamp.to_string()))
+            .expression_attribute_values(":unknown", AttributeValue::N("0".to_string()))
+            .send()
+            .await;
+
+        match result {
+            // filled in or deleted in the meantime
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(())
+            }
+            result => {
+                result?;
+                Ok(())
+            }
+        }
+    }
+
     pub async fn get_item(
         &self,
         table_name: &str,
         key: &str,
         value: AttributeValue,
-    ) -> eyre::Result<Option<HashMap<String, AttributeValue>>> {
+    ) -> eyre::Result<Option<Item>> {
         let result: GetItemOutput = self
             .client
             .get_item()
@@ -125,67 +529,527 @@ impl DynamoDbClient {
         Ok(result.item)
     }
 
-    pub async fn get_items_with_timestamp(
+    pub async fn get_order(&self, uid: &str) -> eyre::Result<Option<Order>> {
+        self.get_item(&self.table_name, "uid", AttributeValue::S(uid.to_string()))
+            .await?
+            .map(|item| Order::from_dynamodb_item(&item))
+            .transpose()
+    }
+
+    pub async fn query_by_buy_token(
         &self,
-        table_name: &str,
-        key: &str,
-        timestamp: AttributeValue,
-    ) -> eyre::Result<Vec<HashMap<String, AttributeValue>>> {
-        let result = self
+        buy_token: &str,
+        from_timestamp: Option<u64>,
+        to_timestamp: Option<u64>,
+        page: &PageRequest,
+    ) -> eyre::Result<Page<Item>> {
+        let query = OrderQuery {
+            buy_token: Some(buy_token.to_string()),
+            from_timestamp,
+            to_timestamp,
+            ..Default::default()
+        };
+        self.query_orders(&query, page).await
+    }
+
+    pub async fn query_by_sell_token(
+        &self,
+        sell_token: &str,
+        from_timestamp: Option<u64>,
+        to_timestamp: Option<u64>,
+        page: &PageRequest,
+    ) -> eyre::Result<Page<Item>> {
+        let query = OrderQuery {
+            sell_token: Some(sell_token.to_string()),
+            from_timestamp,
+            to_timestamp,
+            ..Default::default()
+        };
+        self.query_orders(&query, page).await
+    }
+
+    pub async fn query_by_owner(
+        &self,
+        owner: &str,
+        from_timestamp: Option<u64>,
+        to_timestamp: Option<u64>,
+        page: &PageRequest,
+    ) -> eyre::Result<Page<Item>> {
+        let query = OrderQuery {
+            owner: Some(owner.to_string()),
+            from_timestamp,
+            to_timestamp,
+            ..Default::default()
+        };
+        self.query_orders(&query, page).await
+    }
+
+    // Goes through the owner or a token index when the query names one, and through FeedIndex
+    // otherwise, so results always come back sorted by timestamp.
+    pub async fn query_orders(
+        &self,
+        query: &OrderQuery,
+        page: &PageRequest,
+    ) -> eyre::Result<Page<Item>> {
+        let (index, value) = query.index();
+        let mut expression = Expression::default();
+
+        let condition = expression.equals(index.partition_key(), &value);
+        expression.key_conditions.push(condition);
+        if let Some(range) = expression.range("timestamp", query.from_timestamp, query.to_timestamp)
+        {
+            expression.key_conditions.push(range);
+        }
+
+        for (key, value) in query.filters(index) {
+            let condition = expression.equals(key, &value);
+            expression.filters.push(condition);
+        }
+        if let Some(range) = expression.range("block_number", query.from_block, query.to_block) {
+            expression.filters.push(range);
+        }
+        if let Some(amount) = query.min_sell_amount {
+            let condition = expression.compare("sell", ">=", AttributeValue::N(amount.to_string()));
+            expression.filters.push(condition);
+        }
+        if let Some(usd) = query.min_sell_usd {
+            let condition =
+                expression.compare("sell_usd", ">=", AttributeValue::N(usd.to_string()));
+            expression.filters.push(condition);
+        }
+        // a venue that couldn't quote has a zero buy amount, and counts as neither
+        for (venue, operator) in [(query.beat, ">"), (query.lost_to, "<")] {
+            if let Some(venue) = venue {
+                let (quote, difference) = venue.attributes();
+                let zero = AttributeValue::N("0".to_string());
+                let quoted = expression.compare(quote, ">", zero.clone());
+                let outcome = expression.compare(difference, operator, zero);
+                expression
+                    .filters
+                    .push(format!("{} AND {}", quoted, outcome));
+            }
+        }
+
+        let request = self
+            .client
+            .query()
+            .table_name(&self.table_name)
+            .index_name(index.name())
+            .scan_index_forward(query.sort == SortOrder::Oldest)
+            .key_condition_expression(expression.key_conditions.join(" AND "))
+            .set_filter_expression(expression.filter_expression())
+            .set_expression_attribute_names(expression.names())
+            .set_expression_attribute_values(expression.values());
+
+        let key_attributes = ["uid", index.partition_key(), "timestamp"];
+        paginate(page, &key_attributes, |start_key, limit| {
+            let request = request
+                .clone()
+                .set_exclusive_start_key(start_key)
+                .limit(limit);
+            async move {
+                let result = request.send().await?;
+                Ok((result.items.unwrap_or_default(), result.last_evaluated_key))
+            }
+        })
+        .await
+    }
+
+    // Orders due to be archived by `cutoff`, plus, when `settled_before` is given, orders
+    // written before retention was configured that settled before it.
+    pub async fn expiring_orders(
+        &self,
+        cutoff: u64,
+        settled_before: Option<u64>,
+        page: &PageRequest,
+    ) -> eyre::Result<Page<Item>> {
+        let mut expression = Expression::default();
+        let expired = expression.compare(EXPIRES_AT, "<=", AttributeValue::N(cutoff.to_string()));
+        let filter = match settled_before {
+            Some(settled_before) => {
+                let expires_at = expression.name(EXPIRES_AT);
+                // a zero timestamp means the settlement time is unknown
+                let settled = expression.range("timestamp", Some(1), Some(settled_before));
+                format!(
+                    "{} OR (attribute_not_exists({}) AND {})",
+                    expired,
+                    expires_at,
+                    settled.unwrap_or_default()
+                )
+            }
+            None => expired,
+        };
+        expression.filters.push(filter);
+
+        let request = self
             .client
             .scan()
-            .table_name(table_name)
-            .filter_expression(format!("{} > :val", key))
-            .expression_attribute_values(":val", timestamp)
+            .table_name(&self.table_name)
+            .set_filter_expression(expression.filter_expression())
+            .set_expression_attribute_names(expression.names())
+            .set_expression_attribute_values(expression.values());
+
+        paginate(page, &["uid"], |start_key, limit| {
+            let request = request
+                .clone()
+                .set_exclusive_start_key(start_key)
+                .limit(limit);
+            async move {
+                let result = request.send().await?;
+                Ok((result.items.unwrap_or_default(), result.last_evaluated_key))
+            }
+        })
+        .await
+    }
+
+    // Deletes an order unless it was rewritten with another analysis_version in the meantime.
+    // Returns whether it was deleted.
+    pub async fn delete_order(&self, order: &Order) -> eyre::Result<bool> {
+        let timer = metrics::DYNAMODB_DURATION
+            .with_label_values(&["delete_order"])
+            .start_timer();
+        let result = self
+            .client
+            .delete_item()
+            .table_name(&self.table_name)
+            .key("uid", AttributeValue::S(order.uid().to_string()))
+            .condition_expression(
+                "attribute_not_exists(analysis_version) OR analysis_version = :version",
+            )
+            .expression_attribute_values(
+                ":version",
+                AttributeValue::N(order.analysis_version().to_string()),
+            )
             .send()
-            .await?;
+            .await;
+        timer.observe_duration();
 
-        if result.items.is_none() || result.items.as_ref().unwrap().is_empty() {
-            return Err(eyre::eyre!("No items found in the result"));
+        match result {
+            Ok(_) => Ok(true),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => {
+                metrics::DYNAMODB_ERRORS
+                    .with_label_values(&["delete_order"])
+                    .inc();
+                Err(e.into())
+            }
         }
-        Ok(result.items.unwrap())
     }
 }
 
-fn to_attr(value: impl ToString, attr_type: &str) -> AttributeValue {
-    match attr_type {
-        "N" => AttributeValue::N(value.to_string()),
-        _ => AttributeValue::S(value.to_string()),
+// Condition and filter expressions with every attribute behind a placeholder, since
+// "owner" and "timestamp" are both DynamoDB reserved words.
+#[derive(Default)]
+struct Expression {
+    key_conditions: Vec<String>,
+    filters: Vec<String>,
+    names: HashMap<String, String>,
+    values: HashMap<String, AttributeValue>,
+}
+
+impl Expression {
+    fn name(&mut self, attribute: &str) -> String {
+        let placeholder = format!("#a{}", self.names.len());
+        self.names
+            .insert(placeholder.clone(), attribute.to_string());
+        placeholder
+    }
+
+    fn value(&mut self, value: AttributeValue) -> String {
+        let placeholder = format!(":v{}", self.values.len());
+        self.values.insert(placeholder.clone(), value);
+        placeholder
+    }
+
+    fn equals(&mut self, attribute: &str, value: &str) -> String {
+        let name = self.name(attribute);
+        let value = self.value(AttributeValue::S(value.to_string()));
+        format!("{} = {}", name, value)
+    }
+
+    fn compare(&mut self, attribute: &str, operator: &str, value: AttributeValue) -> String {
+        let name = self.name(attribute);
+        let value = self.value(value);
+        format!("{} {} {}", name, operator, value)
+    }
+
+    // inclusive on both ends, and a single condition so it can serve as a sort key condition
+    fn range(&mut self, attribute: &str, from: Option<u64>, to: Option<u64>) -> Option<String> {
+        let number = |n: u64| AttributeValue::N(n.to_string());
+        match (from, to) {
+            (Some(from), Some(to)) => {
+                let name = self.name(attribute);
+                let from = self.value(number(from));
+                let to = self.value(number(to));
+                Some(format!("{} BETWEEN {} AND {}", name, from, to))
+            }
+            (Some(from), None) => Some(self.compare(attribute, ">=", number(from))),
+            (None, Some(to)) => Some(self.compare(attribute, "<=", number(to))),
+            (None, None) => None,
+        }
     }
+
+    fn filter_expression(&self) -> Option<String> {
+        (!self.filters.is_empty()).then(|| self.filters.join(" AND "))
+    }
+
+    fn names(&self) -> Option<HashMap<String, String>> {
+        (!self.names.is_empty()).then(|| self.names.clone())
+    }
+
+    fn values(&self) -> Option<HashMap<String, AttributeValue>> {
+        (!self.values.is_empty()).then(|| self.values.clone())
+    }
+}
+
+// Walks every order matching a query, one DynamoDB page at a time
+pub struct OrderPages {
+    client: DynamoDbClient,
+    query: OrderQuery,
+    page: PageRequest,
+    done: bool,
 }
 
-pub async fn fetch_latest_from_database(config: &EnvConfig) -> eyre::Result<Vec<Order>> {
-    let client = DynamoDbClient::new().await?;
-    let provider = Provider::<Http>::try_from(config.get_alchemy_http_url())?;
+impl OrderPages {
+    pub fn new(client: DynamoDbClient, query: OrderQuery) -> Self {
+        Self {
+            client,
+            query,
+            page: PageRequest {
+                limit: Some(MAX_PAGE_SIZE),
+                cursor: None,
+            },
+            done: false,
+        }
+    }
+
+    pub async fn next_page(&mut self) -> eyre::Result<Option<Vec<Order>>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let page = self
+            .client
+            .query_orders(&self.query, &self.page)
+            .await?
+            .try_map(|item| Order::from_dynamodb_item(&item))?;
 
-    let table_name = "orders";
-    let key = "block_number";
-    let block_number = provider.get_block_number().await?;
-    let value = AttributeValue::N((block_number - 15).to_string());
+        match page.next_cursor {
+            Some(cursor) => self.page.cursor = Some(cursor),
+            None => self.done = true,
+        }
+        Ok(Some(page.items))
+    }
+}
 
-    let items = client
-        .get_items_with_timestamp(table_name, key, value)
-        .await?;
+pub async fn new_client(config: &Config) -> Client {
+    let sdk_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
+        .load()
+        .await;
 
-    let mut orders = Vec::new();
-    for item in items {
-        let order = Order::from_dynamodb_item(&item);
-        println!("Get Order: {:?}", order);
-        orders.push(order);
+    match config.dynamodb_endpoint_url() {
+        Some(endpoint_url) => Client::from_conf(
+            aws_sdk_dynamodb::config::Builder::from(&sdk_config)
+                .endpoint_url(endpoint_url)
+                .build(),
+        ),
+        None => Client::new(&sdk_config),
     }
-    Ok(orders)
 }
 
-pub fn extract_number(item: &HashMap<String, AttributeValue>, key: &str) -> u64 {
-    item.get(key)
-        .and_then(|v| v.as_n().ok())
-        .and_then(|v| v.parse::<u64>().ok())
-        .unwrap_or_default()
+// Orders of the last `latest_blocks` blocks, newest first. Timestamps grow with blocks, so this
+// is FeedIndex after the timestamp of the block before them.
+pub async fn fetch_latest_from_database(
+    config: &Config,
+    client: &DynamoDbClient,
+    page: &PageRequest,
+) -> eyre::Result<Page<Order>> {
+    let provider = Rpc::new(config).provider();
+
+    let head = provider.get_block_number().await?.as_u64();
+    let after = block_timestamp(&provider, head.saturating_sub(config.latest_blocks())).await?;
+    let query = OrderQuery {
+        from_timestamp: Some(after + 1),
+        ..Default::default()
+    };
+
+    let items = client.query_orders(&query, page).await?;
+
+    items.try_map(|item| Order::from_dynamodb_item(&item))
+}
+
+pub async fn fetch_orders_from_database(
+    client: &DynamoDbClient,
+    query: &OrderQuery,
+    page: &PageRequest,
+) -> eyre::Result<Page<Order>> {
+    let items = client.query_orders(query, page).await?;
+
+    items.try_map(|item| Order::from_dynamodb_item(&item))
 }
 
-pub fn extract_string(item: &HashMap<String, AttributeValue>, key: &str) -> String {
-    item.get(key)
-        .and_then(|v| v.as_s().ok())
-        .map(|v| v.to_string()) // &String to String
-        .unwrap_or_else(|| String::new())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> Item {
+        Item::from([
+            ("uid".to_string(), AttributeValue::S("0xabc".to_string())),
+            ("feed".to_string(), AttributeValue::S(FEED.to_string())),
+            (
+                "timestamp".to_string(),
+                AttributeValue::N("1700000000".to_string()),
+            ),
+        ])
+    }
+
+    fn items(count: usize) -> Vec<Item> {
+        (0..count)
+            .map(|i| Item::from([("uid".to_string(), AttributeValue::S(i.to_string()))]))
+            .collect()
+    }
+
+    fn uid(uid: &str) -> Item {
+        Item::from([("uid".to_string(), AttributeValue::S(uid.to_string()))])
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = encode_cursor(&key()).unwrap();
+        assert_eq!(decode_cursor(&cursor).unwrap(), key());
+    }
+
+    #[test]
+    fn cursor_rejects_garbage() {
+        assert!(decode_cursor("not hex").is_err());
+        assert!(decode_cursor(&hex::encode("{\"uid\":1}")).is_err());
+    }
+
+    #[test]
+    fn cursor_only_holds_key_types() {
+        let key = Item::from([("flag".to_string(), AttributeValue::Bool(true))]);
+        assert!(encode_cursor(&key).is_err());
+    }
+
+    #[test]
+    fn page_limit_defaults_and_clamps() {
+        let page = |limit| PageRequest {
+            limit,
+            cursor: None,
+        };
+        assert_eq!(page(None).limit(), DEFAULT_PAGE_SIZE);
+        assert_eq!(page(Some(0)).limit(), 1);
+        assert_eq!(page(Some(5)).limit(), 5);
+        assert_eq!(page(Some(i32::MAX)).limit(), MAX_PAGE_SIZE);
+    }
+
+    #[tokio::test]
+    async fn paginate_asks_for_full_pages_and_cuts_the_last_one() {
+        let page = PageRequest {
+            limit: Some(3),
+            cursor: None,
+        };
+        let mut requested = Vec::new();
+        let result = paginate(&page, &["uid"], |_, limit| {
+            requested.push(limit);
+            // a filter keeps only 2 of the items each page evaluates
+            async move { Ok((items(2), Some(key()))) }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(result.items.len(), 3);
+        // never narrowed down to the 1 item still missing
+        assert_eq!(requested, [3, 3]);
+        // the second page's second item was cut, so the next page starts after its first
+        assert_eq!(
+            decode_cursor(&result.next_cursor.unwrap()).unwrap(),
+            uid("0")
+        );
+    }
+
+    #[tokio::test]
+    async fn paginate_takes_the_cursor_of_a_page_that_fills_the_limit() {
+        let page = PageRequest {
+            limit: Some(2),
+            cursor: None,
+        };
+        let result = paginate(&page, &["uid"], |_, _| async {
+            Ok((items(2), Some(key())))
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(result.items.len(), 2);
+        assert_eq!(decode_cursor(&result.next_cursor.unwrap()).unwrap(), key());
+    }
+
+    #[tokio::test]
+    async fn paginate_starts_at_the_cursor() {
+        let page = PageRequest {
+            limit: Some(2),
+            cursor: Some(encode_cursor(&key()).unwrap()),
+        };
+        let mut start_keys = Vec::new();
+        paginate(&page, &["uid"], |start_key, _| {
+            start_keys.push(start_key);
+            async { Ok((items(1), None)) }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(start_keys, [Some(key())]);
+    }
+
+    #[test]
+    fn amount_filters_must_be_storable_numbers() {
+        let usd = |min_sell_usd| OrderQuery {
+            min_sell_usd: Some(min_sell_usd),
+            ..Default::default()
+        };
+        for valid in [0.0, 1e-18, 2500.5, 1e30] {
+            assert!(usd(valid).validate().is_ok(), "{}", valid);
+        }
+        for invalid in [f64::NAN, f64::INFINITY, -1.0, 1e300, 1e-300] {
+            assert!(usd(invalid).validate().is_err(), "{}", invalid);
+        }
+
+        let amount = OrderQuery {
+            sell_token: Some("0x01".into()),
+            min_sell_amount: Some(f64::NEG_INFINITY),
+            ..Default::default()
+        };
+        assert!(amount.validate().is_err());
+    }
+
+    #[test]
+    fn key_of_needs_every_key_attribute() {
+        assert_eq!(key_of(&key(), &["uid"]).unwrap(), uid("0xabc"));
+        assert_eq!(
+            key_of(&key(), &["uid", "feed", "timestamp"]).unwrap(),
+            key()
+        );
+        assert!(key_of(&uid("0xabc"), &["uid", "timestamp"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn paginate_follows_keys_until_the_end() {
+        let mut calls = 0;
+        let result = paginate(&PageRequest::default(), &["uid"], |start_key, _| {
+            calls += 1;
+            let last_key = start_key.is_none().then(key);
+            async move { Ok((items(1), last_key)) }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(calls, 2);
+        assert_eq!(result.items.len(), 2);
+        assert!(result.next_cursor.is_none());
+    }
 }
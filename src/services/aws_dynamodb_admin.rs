@@ -0,0 +1,784 @@
+use crate::config::Config;
+use crate::services::aws_dynamodb::{new_client, Item, OrderIndex, EXPIRES_AT, FEED};
+use aws_sdk_dynamodb::{
+    types::{
+        AttributeDefinition, AttributeValue, BillingMode, CreateGlobalSecondaryIndexAction,
+        DeleteGlobalSecondaryIndexAction, GlobalSecondaryIndexUpdate, IndexStatus,
+        KeySchemaElement, KeyType, Projection, ProjectionType, ScalarAttributeType,
+        TableDescription, TableStatus, TimeToLiveSpecification, TimeToLiveStatus,
+    },
+    Client,
+};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy)]
+pub enum AttributeKind {
+    S,
+    N,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct KeyAttribute {
+    pub name: &'static str,
+    pub kind: AttributeKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IndexSpec {
+    pub name: &'static str,
+    pub partition_key: KeyAttribute,
+    pub sort_key: Option<KeyAttribute>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TableSpec {
+    pub name: String,
+    pub partition_key: KeyAttribute,
+    pub sort_key: Option<KeyAttribute>,
+}
+
+impl TableSpec {
+    pub fn orders(config: &Config) -> Self {
+        Self {
+            name: config.orders_table().to_string(),
+            partition_key: KeyAttribute {
+                name: "uid",
+                kind: AttributeKind::S,
+            },
+            sort_key: None,
+        }
+    }
+
+    pub fn migrations(config: &Config) -> Self {
+        Self {
+            name: config.migrations_table().to_string(),
+            partition_key: KeyAttribute {
+                name: "version",
+                kind: AttributeKind::N,
+            },
+            sort_key: None,
+        }
+    }
+
+    pub fn raw_events(config: &Config) -> Self {
+        Self {
+            name: config.raw_events_table().to_string(),
+            partition_key: KeyAttribute {
+                name: "order_uid",
+                kind: AttributeKind::S,
+            },
+            sort_key: Some(KeyAttribute {
+                name: "event_key",
+                kind: AttributeKind::S,
+            }),
+        }
+    }
+
+    pub fn tokens(config: &Config) -> Self {
+        Self {
+            name: config.tokens_table().to_string(),
+            partition_key: KeyAttribute {
+                name: "address",
+                kind: AttributeKind::S,
+            },
+            sort_key: None,
+        }
+    }
+
+    fn keys(&self) -> Vec<(KeyAttribute, KeyType)> {
+        key_schema(self.partition_key, self.sort_key)
+    }
+}
+
+pub enum MigrationStep {
+    CreateIndex(IndexSpec),
+    // returns the attributes to SET on an item, or None when the item is already up to date
+    Backfill(fn(&Item) -> Option<Item>),
+    // stops DynamoDB deleting items by the given attribute, when it does
+    DisableTtl(&'static str),
+    // kept for its version only, it is no longer applied
+    Retired,
+}
+
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    pub step: MigrationStep,
+}
+
+const TIMESTAMP: KeyAttribute = KeyAttribute {
+    name: "timestamp",
+    kind: AttributeKind::N,
+};
+
+const fn order_index(index: OrderIndex) -> IndexSpec {
+    IndexSpec {
+        name: index.name(),
+        partition_key: KeyAttribute {
+            name: index.partition_key(),
+            kind: AttributeKind::S,
+        },
+        sort_key: Some(TIMESTAMP),
+    }
+}
+
+// Append only: a migration that has been applied anywhere must never be edited or reordered.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "Create BuyTokenIndex (buy_token, timestamp)",
+        step: MigrationStep::CreateIndex(order_index(OrderIndex::BuyToken)),
+    },
+    Migration {
+        version: 2,
+        description: "Create SellTokenIndex (sell_token, timestamp)",
+        step: MigrationStep::CreateIndex(order_index(OrderIndex::SellToken)),
+    },
+    Migration {
+        version: 3,
+        description: "Create OwnerIndex (owner, timestamp)",
+        step: MigrationStep::CreateIndex(order_index(OrderIndex::Owner)),
+    },
+    // TTL deleted orders whether or not they had been archived, migration 7 turns it off where
+    // this ran
+    Migration {
+        version: 4,
+        description: "Enable TTL on expires_at (retired)",
+        step: MigrationStep::Retired,
+    },
+    Migration {
+        version: 5,
+        description: "Create FeedIndex (feed, timestamp)",
+        step: MigrationStep::CreateIndex(order_index(OrderIndex::Feed)),
+    },
+    Migration {
+        version: 6,
+        description: "Backfill feed on orders written before FeedIndex",
+        step: MigrationStep::Backfill(backfill_feed),
+    },
+    Migration {
+        version: 7,
+        description: "Disable TTL on expires_at, orders are deleted once archived",
+        step: MigrationStep::DisableTtl(EXPIRES_AT),
+    },
+];
+
+fn backfill_feed(item: &Item) -> Option<Item> {
+    let feed = OrderIndex::Feed.partition_key();
+    (!item.contains_key(feed))
+        .then(|| Item::from([(feed.to_string(), AttributeValue::S(FEED.to_string()))]))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TableState {
+    Created,
+    Verified,
+}
+
+// `recreate` names the indexes that may be dropped and built again when their key schema is
+// out of date, see `create_index`
+pub async fn setup(config: &Config, recreate: &[String]) -> eyre::Result<()> {
+    let client = new_client(config).await;
+
+    for spec in tables(config) {
+        match ensure_table(&client, &spec).await? {
+            TableState::Created => println!("Table {} created", spec.name),
+            TableState::Verified => println!("Table {} verified", spec.name),
+        }
+    }
+
+    migrate_with_client(&client, config, recreate).await
+}
+
+pub async fn migrate(config: &Config, recreate: &[String]) -> eyre::Result<()> {
+    let client = new_client(config).await;
+    migrate_with_client(&client, config, recreate).await
+}
+
+pub async fn status(config: &Config) -> eyre::Result<()> {
+    let client = new_client(config).await;
+
+    for spec in tables(config) {
+        match describe_table(&client, &spec.name).await? {
+            Some(table) => println!(
+                "Table {}: {}",
+                spec.name,
+                table
+                    .table_status()
+                    .map(|s| s.as_str())
+                    .unwrap_or("UNKNOWN")
+            ),
+            None => println!("Table {}: missing", spec.name),
+        }
+    }
+
+    let applied = applied_migrations(&client, config.migrations_table()).await?;
+    for migration in MIGRATIONS {
+        let state = if applied.contains(&migration.version) {
+            "applied"
+        } else {
+            "pending"
+        };
+        println!(
+            "Migration {:>3} [{}] {}",
+            migration.version, state, migration.description
+        );
+    }
+
+    Ok(())
+}
+
+fn tables(config: &Config) -> [TableSpec; 4] {
+    [
+        TableSpec::orders(config),
+        TableSpec::migrations(config),
+        TableSpec::raw_events(config),
+        TableSpec::tokens(config),
+    ]
+}
+
+pub async fn ensure_table(client: &Client, spec: &TableSpec) -> eyre::Result<TableState> {
+    if let Some(table) = describe_table(client, &spec.name).await? {
+        check_key_schema(&spec.name, table.key_schema(), &spec.keys())?;
+        wait_until_active(client, &spec.name, None).await?;
+        return Ok(TableState::Verified);
+    }
+
+    let keys = spec.keys();
+    client
+        .create_table()
+        .table_name(&spec.name)
+        .set_attribute_definitions(Some(
+            keys.iter()
+                .map(|(key, _)| attribute_definition(*key))
+                .collect::<eyre::Result<_>>()?,
+        ))
+        .set_key_schema(Some(
+            keys.into_iter()
+                .map(|(key, key_type)| key_schema_element(key, key_type))
+                .collect::<eyre::Result<_>>()?,
+        ))
+        .billing_mode(BillingMode::PayPerRequest)
+        .send()
+        .await?;
+
+    wait_until_active(client, &spec.name, None).await?;
+    Ok(TableState::Created)
+}
+
+async fn migrate_with_client(
+    client: &Client,
+    config: &Config,
+    recreate: &[String],
+) -> eyre::Result<()> {
+    let applied = applied_migrations(client, config.migrations_table()).await?;
+    let pending = MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .collect::<Vec<_>>();
+
+    if pending.is_empty() {
+        println!("No pending migrations");
+        return Ok(());
+    }
+
+    for migration in pending {
+        println!(
+            "Applying migration {}: {}",
+            migration.version, migration.description
+        );
+        apply_migration(client, config.orders_table(), &migration.step, recreate).await?;
+        record_migration(client, config.migrations_table(), migration).await?;
+    }
+
+    Ok(())
+}
+
+async fn apply_migration(
+    client: &Client,
+    table_name: &str,
+    step: &MigrationStep,
+    recreate: &[String],
+) -> eyre::Result<()> {
+    match step {
+        MigrationStep::CreateIndex(index) => {
+            let recreate = recreate.iter().any(|name| name == index.name);
+            create_index(client, table_name, index, recreate).await
+        }
+        MigrationStep::Backfill(backfill) => run_backfill(client, table_name, *backfill).await,
+        MigrationStep::DisableTtl(attribute) => disable_ttl(client, table_name, attribute).await,
+        MigrationStep::Retired => Ok(()),
+    }
+}
+
+async fn disable_ttl(client: &Client, table_name: &str, attribute: &str) -> eyre::Result<()> {
+    let current = client
+        .describe_time_to_live()
+        .table_name(table_name)
+        .send()
+        .await?
+        .time_to_live_description;
+
+    let enabled = current.as_ref().is_some_and(|current| {
+        current.attribute_name() == Some(attribute)
+            && matches!(
+                current.time_to_live_status(),
+                Some(TimeToLiveStatus::Enabled | TimeToLiveStatus::Enabling)
+            )
+    });
+    if !enabled {
+        println!("TTL on {} not enabled", attribute);
+        return Ok(());
+    }
+
+    client
+        .update_time_to_live()
+        .table_name(table_name)
+        .time_to_live_specification(
+            TimeToLiveSpecification::builder()
+                .enabled(false)
+                .attribute_name(attribute)
+                .build()?,
+        )
+        .send()
+        .await?;
+
+    Ok(())
+}
+
+async fn create_index(
+    client: &Client,
+    table_name: &str,
+    index: &IndexSpec,
+    recreate: bool,
+) -> eyre::Result<()> {
+    let table = describe_table(client, table_name)
+        .await?
+        .ok_or_else(|| eyre::eyre!("Table {} does not exist, run `admin setup`", table_name))?;
+
+    // tables created before the migrations existed may already have the index, hash only as
+    // create-table.sh made them before they were sorted by timestamp. A GSI's key schema can't
+    // be changed in place, and queries on it fail while it is built again, so it is only
+    // dropped when the operator asks for it.
+    if let Some(existing) = table
+        .global_secondary_indexes()
+        .iter()
+        .find(|i| i.index_name() == Some(index.name))
+    {
+        match check_key_schema(
+            index.name,
+            existing.key_schema(),
+            &key_schema(index.partition_key, index.sort_key),
+        ) {
+            Ok(()) => {
+                println!("Index {} already exists", index.name);
+                return wait_until_active(client, table_name, Some(index.name)).await;
+            }
+            Err(e) if recreate => {
+                println!("{}, recreating it", e);
+                delete_index(client, table_name, index.name).await?;
+            }
+            Err(e) => {
+                return Err(eyre::eyre!(
+                    "{}. Queries on it fail from when it is dropped until it is built again, \
+                     run again with --recreate-index {} to do so",
+                    e,
+                    index.name
+                ))
+            }
+        }
+    }
+
+    let keys = key_schema(index.partition_key, index.sort_key);
+    let action = CreateGlobalSecondaryIndexAction::builder()
+        .index_name(index.name)
+        .set_key_schema(Some(
+            keys.iter()
+                .map(|(key, key_type)| key_schema_element(*key, key_type.clone()))
+                .collect::<eyre::Result<_>>()?,
+        ))
+        .projection(
+            Projection::builder()
+                .projection_type(ProjectionType::All)
+                .build(),
+        )
+        .build()?;
+
+    client
+        .update_table()
+        .table_name(table_name)
+        .set_attribute_definitions(Some(
+            keys.iter()
+                .map(|(key, _)| attribute_definition(*key))
+                .collect::<eyre::Result<_>>()?,
+        ))
+        .global_secondary_index_updates(
+            GlobalSecondaryIndexUpdate::builder().create(action).build(),
+        )
+        .send()
+        .await?;
+
+    wait_until_active(client, table_name, Some(index.name)).await
+}
+
+// returns once the index is gone, queries on it fail until it is created again
+async fn delete_index(client: &Client, table_name: &str, index_name: &str) -> eyre::Result<()> {
+    wait_until_active(client, table_name, Some(index_name)).await?;
+    client
+        .update_table()
+        .table_name(table_name)
+        .global_secondary_index_updates(
+            GlobalSecondaryIndexUpdate::builder()
+                .delete(
+                    DeleteGlobalSecondaryIndexAction::builder()
+                        .index_name(index_name)
+                        .build()?,
+                )
+                .build(),
+        )
+        .send()
+        .await?;
+
+    loop {
+        let table = describe_table(client, table_name)
+            .await?
+            .ok_or_else(|| eyre::eyre!("Table {} disappeared", table_name))?;
+        let deleting = table
+            .global_secondary_indexes()
+            .iter()
+            .any(|i| i.index_name() == Some(index_name));
+        if !deleting && table.table_status() == Some(&TableStatus::Active) {
+            return Ok(());
+        }
+
+        println!("Waiting for {} to be deleted...", index_name);
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+fn check_key_schema(
+    name: &str,
+    actual: &[KeySchemaElement],
+    expected: &[(KeyAttribute, KeyType)],
+) -> eyre::Result<()> {
+    let actual = actual
+        .iter()
+        .map(|key| (key.attribute_name(), key.key_type().clone()))
+        .collect::<Vec<_>>();
+    let expected = expected
+        .iter()
+        .map(|(key, key_type)| (key.name, key_type.clone()))
+        .collect::<Vec<_>>();
+
+    if actual != expected {
+        return Err(eyre::eyre!(
+            "{} has key schema {:?} but {:?} is expected",
+            name,
+            actual,
+            expected
+        ));
+    }
+    Ok(())
+}
+
+async fn run_backfill(
+    client: &Client,
+    table_name: &str,
+    backfill: fn(&Item) -> Option<Item>,
+) -> eyre::Result<()> {
+    let mut start_key = None;
+    let mut updated = 0;
+
+    loop {
+        let result = client
+            .scan()
+            .table_name(table_name)
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await?;
+
+        for item in result.items() {
+            let Some(changes) = backfill(item) else {
+                continue;
+            };
+            let uid = item
+                .get("uid")
+                .cloned()
+                .ok_or_else(|| eyre::eyre!("Item without uid in {}", table_name))?;
+
+            let mut request = client.update_item().table_name(table_name).key("uid", uid);
+            let mut assignments = Vec::new();
+            for (i, (name, value)) in changes.into_iter().enumerate() {
+                assignments.push(format!("#a{} = :a{}", i, i));
+                request = request
+                    .expression_attribute_names(format!("#a{}", i), name)
+                    .expression_attribute_values(format!(":a{}", i), value);
+            }
+            request
+                .update_expression(format!("SET {}", assignments.join(", ")))
+                .send()
+                .await?;
+            updated += 1;
+        }
+
+        match result.last_evaluated_key {
+            Some(key) if !key.is_empty() => start_key = Some(key),
+            _ => break,
+        }
+    }
+
+    println!("Backfilled {} items", updated);
+    Ok(())
+}
+
+async fn applied_migrations(client: &Client, migrations_table: &str) -> eyre::Result<Vec<u32>> {
+    if describe_table(client, migrations_table).await?.is_none() {
+        return Ok(Vec::new());
+    }
+
+    let mut versions = Vec::new();
+    let mut start_key = None;
+    loop {
+        let result = client
+            .scan()
+            .table_name(migrations_table)
+            .set_exclusive_start_key(start_key)
+            .send()
+            .await?;
+
+        versions.extend(
+            result
+                .items()
+                .iter()
+                .filter_map(|item| item.get("version"))
+                .filter_map(|version| version.as_n().ok())
+                .filter_map(|version| version.parse::<u32>().ok()),
+        );
+
+        match result.last_evaluated_key {
+            Some(key) if !key.is_empty() => start_key = Some(key),
+            _ => break,
+        }
+    }
+
+    Ok(versions)
+}
+
+async fn record_migration(
+    client: &Client,
+    migrations_table: &str,
+    migration: &Migration,
+) -> eyre::Result<()> {
+    let applied_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    client
+        .put_item()
+        .table_name(migrations_table)
+        .item("version", AttributeValue::N(migration.version.to_string()))
+        .item(
+            "description",
+            AttributeValue::S(migration.description.to_string()),
+        )
+        .item("applied_at", AttributeValue::N(applied_at.to_string()))
+        .send()
+        .await?;
+
+    Ok(())
+}
+
+pub async fn describe_table(
+    client: &Client,
+    table_name: &str,
+) -> eyre::Result<Option<TableDescription>> {
+    match client.describe_table().table_name(table_name).send().await {
+        Ok(output) => Ok(output.table),
+        Err(e)
+            if e.as_service_error()
+                .is_some_and(|e| e.is_resource_not_found_exception()) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn wait_until_active(
+    client: &Client,
+    table_name: &str,
+    index_name: Option<&str>,
+) -> eyre::Result<()> {
+    loop {
+        let table = describe_table(client, table_name)
+            .await?
+            .ok_or_else(|| eyre::eyre!("Table {} disappeared", table_name))?;
+
+        let table_active = table.table_status() == Some(&TableStatus::Active);
+        let index_active = match index_name {
+            Some(name) => table
+                .global_secondary_indexes()
+                .iter()
+                .find(|i| i.index_name() == Some(name))
+                .is_some_and(|i| {
+                    i.index_status() == Some(&IndexStatus::Active) && i.backfilling() != Some(true)
+                }),
+            None => true,
+        };
+
+        if table_active && index_active {
+            return Ok(());
+        }
+
+        println!(
+            "Waiting for {} to become active...",
+            index_name.unwrap_or(table_name)
+        );
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+fn key_schema(
+    partition_key: KeyAttribute,
+    sort_key: Option<KeyAttribute>,
+) -> Vec<(KeyAttribute, KeyType)> {
+    let mut keys = vec![(partition_key, KeyType::Hash)];
+    if let Some(sort_key) = sort_key {
+        keys.push((sort_key, KeyType::Range));
+    }
+    keys
+}
+
+fn key_schema_element(key: KeyAttribute, key_type: KeyType) -> eyre::Result<KeySchemaElement> {
+    Ok(KeySchemaElement::builder()
+        .attribute_name(key.name)
+        .key_type(key_type)
+        .build()?)
+}
+
+fn attribute_definition(key: KeyAttribute) -> eyre::Result<AttributeDefinition> {
+    let kind = match key.kind {
+        AttributeKind::S => ScalarAttributeType::S,
+        AttributeKind::N => ScalarAttributeType::N,
+    };
+
+    Ok(AttributeDefinition::builder()
+        .attribute_name(key.name)
+        .attribute_type(kind)
+        .build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigArgs;
+
+    const UID: KeyAttribute = KeyAttribute {
+        name: "uid",
+        kind: AttributeKind::S,
+    };
+
+    fn elements(keys: &[(KeyAttribute, KeyType)]) -> Vec<KeySchemaElement> {
+        keys.iter()
+            .map(|(key, key_type)| key_schema_element(*key, key_type.clone()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn key_schema_must_match_in_name_type_and_order() {
+        let expected = key_schema(UID, Some(TIMESTAMP));
+        assert!(check_key_schema("orders", &elements(&expected), &expected).is_ok());
+
+        // without the sort key
+        let actual = elements(&key_schema(UID, None));
+        assert!(check_key_schema("orders", &actual, &expected).is_err());
+        // keys swapped
+        let actual = elements(&key_schema(TIMESTAMP, Some(UID)));
+        assert!(check_key_schema("orders", &actual, &expected).is_err());
+        // another partition key
+        let owner = KeyAttribute {
+            name: "owner",
+            kind: AttributeKind::S,
+        };
+        let actual = elements(&key_schema(owner, Some(TIMESTAMP)));
+        let error = check_key_schema("OwnerIndex", &actual, &expected).unwrap_err();
+        assert!(error.to_string().starts_with("OwnerIndex has key schema"));
+    }
+
+    #[test]
+    fn migration_versions_strictly_increase() {
+        assert_eq!(MIGRATIONS[0].version, 1);
+        for pair in MIGRATIONS.windows(2) {
+            assert!(
+                pair[0].version < pair[1].version,
+                "migration {} comes after {}",
+                pair[1].version,
+                pair[0].version
+            );
+        }
+    }
+
+    #[test]
+    fn retired_migrations_keep_their_version() {
+        // a retired step still counts as applied where it ran, so it must stay where it was
+        const RETIRED: &[u32] = &[4];
+
+        let retired = MIGRATIONS
+            .iter()
+            .filter(|migration| matches!(migration.step, MigrationStep::Retired))
+            .map(|migration| migration.version)
+            .collect::<Vec<_>>();
+        assert_eq!(retired, RETIRED);
+    }
+
+    // Needs DynamoDB Local, e.g. `docker run -p 8000:8000 amazon/dynamodb-local`, and any AWS
+    // region and credentials: cargo test -- --ignored setup_and_migrate_are_idempotent
+    #[tokio::test]
+    #[ignore]
+    async fn setup_and_migrate_are_idempotent() {
+        let prefix = format!("idempotency_{}", std::process::id());
+        let file = std::env::temp_dir().join(format!("{}.toml", prefix));
+        std::fs::write(
+            &file,
+            format!(
+                "[rpc]\nendpoints = [\"http://localhost:8545\"]\n\
+                 [apis]\nzerox_api_key = \"key\"\n\
+                 [dynamodb]\norders_table = \"{0}_orders\"\n\
+                 migrations_table = \"{0}_migrations\"\n\
+                 raw_events_table = \"{0}_raw_events\"\n\
+                 tokens_table = \"{0}_tokens\"\n",
+                prefix
+            ),
+        )
+        .unwrap();
+        let config = Config::load(&ConfigArgs {
+            config: Some(file),
+            dynamodb_endpoint_url: Some(
+                std::env::var("DYNAMODB_ENDPOINT_URL")
+                    .unwrap_or_else(|_| "http://localhost:8000".into()),
+            ),
+            ..Default::default()
+        })
+        .unwrap();
+
+        for _ in 0..2 {
+            setup(&config, &[]).await.unwrap();
+            migrate(&config, &[]).await.unwrap();
+        }
+
+        let client = new_client(&config).await;
+        for spec in tables(&config) {
+            assert_eq!(
+                ensure_table(&client, &spec).await.unwrap(),
+                TableState::Verified
+            );
+        }
+        let mut applied = applied_migrations(&client, config.migrations_table())
+            .await
+            .unwrap();
+        applied.sort();
+        let expected = MIGRATIONS
+            .iter()
+            .map(|migration| migration.version)
+            .collect::<Vec<_>>();
+        assert_eq!(applied, expected);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::config::Config;
+use crate::metrics;
+use crate::services::{
+    aws_dynamodb::{new_client, paginate, Page, PageRequest},
+    aws_dynamodb_serde::{from_item, to_item},
+    cow_get_order_api::CowTrade,
+    http_client::ApiError,
+};
+use crate::TradeEvent;
+use aws_sdk_dynamodb::{types::AttributeValue, Client};
+use ethers::types::{Address, Bytes, H256, U256};
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RawEventKind {
+    Trade,
+    CowOrder,
+    ZeroxQuote,
+    CowQuote,
+    Univ3Swap,
+    SellTokenPrice,
+    BuyTokenPrice,
+    UsdcPrice,
+}
+
+impl RawEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RawEventKind::Trade => "trade",
+            RawEventKind::CowOrder => "cow_order",
+            RawEventKind::ZeroxQuote => "zerox_quote",
+            RawEventKind::CowQuote => "cow_quote",
+            RawEventKind::Univ3Swap => "univ3_swap",
+            RawEventKind::SellTokenPrice => "sell_token_price",
+            RawEventKind::BuyTokenPrice => "buy_token_price",
+            RawEventKind::UsdcPrice => "usdc_price",
+        }
+    }
+}
+
+// One ingested payload, stored exactly as it was received. Failed requests are kept as well,
+// with `error` set and whatever body came back, so a re-analysis knows the source was down.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RawEvent {
+    pub order_uid: String,
+    // "<kind>#<recorded_at>", so an order's events sort by kind then arrival
+    pub event_key: String,
+    pub kind: RawEventKind,
+    // unix millis
+    pub recorded_at: u64,
+    pub payload: String,
+    pub error: Option<String>,
+}
+
+impl RawEvent {
+    pub fn new(order_uid: &str, kind: RawEventKind, response: &eyre::Result<String>) -> Self {
+        let recorded_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let (payload, error) = match response {
+            Ok(body) => (body.clone(), None),
+            Err(e) => match e.downcast_ref::<ApiError>() {
+                Some(api_error) => (api_error.body.clone(), Some(e.to_string())),
+                None => (String::new(), Some(e.to_string())),
+            },
+        };
+
+        Self {
+            order_uid: order_uid.to_string(),
+            event_key: format!("{}#{:013}", kind.as_str(), recorded_at),
+            kind,
+            recorded_at,
+            payload,
+            error,
+        }
+    }
+}
+
+// The on-chain side of an order, as seen in the settlement's Trade log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedTrade {
+    pub trade: TradeEvent,
+    pub block_number: u64,
+    pub timestamp: u64,
+    pub transaction_hash: H256,
+    pub log_index: U256,
+}
+
+impl RecordedTrade {
+    // for orders that settled while nothing was listening, rebuilt from the CowSwap trades API
+    pub fn from_cow_trade(trade: &CowTrade, timestamp: u64) -> eyre::Result<Self> {
+        let sell_amount = U256::from_dec_str(trade.sell_amount())?;
+        let sell_amount_before_fees = U256::from_dec_str(trade.sell_amount_before_fees())?;
+
+        Ok(Self {
+            trade: TradeEvent {
+                owner: trade.owner().parse::<Address>()?,
+                sell_token: trade.sell_token().parse::<Address>()?,
+                buy_token: trade.buy_token().parse::<Address>()?,
+                sell_amount,
+                buy_amount: U256::from_dec_str(trade.buy_amount())?,
+                fee_amount: sell_amount.saturating_sub(sell_amount_before_fees),
+                order_uid: trade.order_uid().parse::<Bytes>()?,
+            },
+            block_number: *trade.block_number(),
+            timestamp,
+            transaction_hash: trade
+                .tx_hash()
+                .as_deref()
+                .map(str::parse::<H256>)
+                .transpose()?
+                .unwrap_or_default(),
+            log_index: U256::from(*trade.log_index()),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct RawEventStore {
+    client: Client,
+    table_name: String,
+    // records nothing, for analyses that only print their result
+    dry_run: bool,
+}
+
+impl RawEventStore {
+    pub async fn new(config: &Config) -> eyre::Result<Self> {
+        Ok(Self {
+            client: new_client(config).await,
+            table_name: config.raw_events_table().to_string(),
+            dry_run: false,
+        })
+    }
+
+    pub async fn dry_run(config: &Config) -> eyre::Result<Self> {
+        Ok(Self {
+            dry_run: true,
+            ..Self::new(config).await?
+        })
+    }
+
+    // append only: an event that is already stored is never replaced
+    pub async fn append(&self, event: &RawEvent) -> eyre::Result<()> {
+        let timer = metrics::DYNAMODB_DURATION
+            .with_label_values(&["append_raw_event"])
+            .start_timer();
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(to_item(event)?))
+            .condition_expression("attribute_not_exists(event_key)")
+            .send()
+            .await;
+        timer.observe_duration();
+
+        if result.is_err() {
+            metrics::DYNAMODB_ERRORS
+                .with_label_values(&["append_raw_event"])
+                .inc();
+        }
+        result?;
+        Ok(())
+    }
+
+    // Recording must never stop an analysis, so failures are only logged
+    pub async fn record(
+        &self,
+        order_uid: &str,
+        kind: RawEventKind,
+        response: &eyre::Result<String>,
+    ) {
+        if self.dry_run {
+            return;
+        }
+
+        let event = RawEvent::new(order_uid, kind, response);
+        if let Err(e) = self.append(&event).await {
+            eprintln!(
+                "Failed to record raw {} for {}: {}",
+                kind.as_str(),
+                order_uid,
+                e
+            );
+        }
+    }
+
+    pub async fn record_trade(&self, trade: &RecordedTrade) {
+        let order_uid = trade.trade.order_uid.to_string();
+        let payload = serde_json::to_string(trade).map_err(eyre::Report::from);
+        self.record(&order_uid, RawEventKind::Trade, &payload).await;
+    }
+
+    pub async fn events_for(&self, order_uid: &str) -> eyre::Result<Vec<RawEvent>> {
+        let request = self
+            .client
+            .query()
+            .table_name(&self.table_name)
+            .key_condition_expression("order_uid = :uid")
+            .expression_attribute_values(":uid", AttributeValue::S(order_uid.to_string()));
+
+        let mut events = Vec::new();
+        let mut page = PageRequest::default();
+        loop {
+            let items = paginate(&page, &["order_uid", "event_key"], |start_key, limit| {
+                let request = request
+                    .clone()
+                    .set_exclusive_start_key(start_key)
+                    .limit(limit);
+                async move {
+                    let result = request.send().await?;
+                    Ok((result.items.unwrap_or_default(), result.last_evaluated_key))
+                }
+            })
+            .await?;
+            events.extend(items.items.iter().map(from_item));
+
+            match items.next_cursor {
+                Some(cursor) => page.cursor = Some(cursor),
+                None => return events.into_iter().collect(),
+            }
+        }
+    }
+
+    // uids of every recorded trade, i.e. every order that can be re-analysed
+    pub async fn trade_uids(&self, page: &PageRequest) -> eyre::Result<Page<String>> {
+        let request = self
+            .client
+            .scan()
+            .table_name(&self.table_name)
+            .filter_expression("begins_with(event_key, :kind)")
+            .expression_attribute_values(
+                ":kind",
+                AttributeValue::S(format!("{}#", RawEventKind::Trade.as_str())),
+            )
+            // with the sort key, so a page can be cut after any item
+            .projection_expression("order_uid, event_key");
+
+        let items = paginate(page, &["order_uid", "event_key"], |start_key, limit| {
+            let request = request
+                .clone()
+                .set_exclusive_start_key(start_key)
+                .limit(limit);
+            async move {
+                let result = request.send().await?;
+                Ok((result.items.unwrap_or_default(), result.last_evaluated_key))
+            }
+        })
+        .await?;
+
+        Ok(items.map(|item| {
+            item.get("order_uid")
+                .and_then(|uid| uid.as_s().ok())
+                .cloned()
+                .unwrap_or_default()
+        }))
+    }
+}
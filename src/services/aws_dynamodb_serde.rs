@@ -0,0 +1,284 @@
+use crate::services::aws_dynamodb::Item;
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Number, Value};
+use std::str::FromStr;
+
+// Serde bridge between Rust types and DynamoDB items, going through serde_json::Value:
+// numbers become N (keeping their exact textual form), strings S, bools BOOL,
+// sequences L and structs/maps M. Top level `None`s are left out of the item entirely,
+// so they read back as missing fields. Non-finite floats have no N form, and serde_json turns
+// them into nulls that read back as defaults, so types holding floats check them before this,
+// see `Order::check_finite`.
+
+pub fn to_item<T: Serialize>(value: &T) -> eyre::Result<Item> {
+    match serde_json::to_value(value)? {
+        Value::Object(fields) => Ok(fields
+            .into_iter()
+            .filter(|(_, value)| !value.is_null())
+            .map(|(name, value)| (name, to_attribute_value(value)))
+            .collect()),
+        other => Err(eyre::eyre!(
+            "Only structs and maps can become items, got {}",
+            other
+        )),
+    }
+}
+
+pub fn from_item<T: DeserializeOwned>(item: &Item) -> eyre::Result<T> {
+    let mut fields = Map::new();
+    for (name, value) in item {
+        let value = from_attribute_value(value)
+            .map_err(|e| eyre::eyre!("Failed to read attribute {}: {}", name, e))?;
+        fields.insert(name.clone(), value);
+    }
+
+    Ok(serde_json::from_value(Value::Object(fields))?)
+}
+
+pub fn to_attribute_value(value: Value) -> AttributeValue {
+    match value {
+        Value::Null => AttributeValue::Null(true),
+        Value::Bool(b) => AttributeValue::Bool(b),
+        Value::Number(n) => AttributeValue::N(n.to_string()),
+        Value::String(s) => AttributeValue::S(s),
+        Value::Array(values) => {
+            AttributeValue::L(values.into_iter().map(to_attribute_value).collect())
+        }
+        Value::Object(fields) => AttributeValue::M(
+            fields
+                .into_iter()
+                .map(|(name, value)| (name, to_attribute_value(value)))
+                .collect(),
+        ),
+    }
+}
+
+pub fn from_attribute_value(value: &AttributeValue) -> eyre::Result<Value> {
+    Ok(match value {
+        AttributeValue::Null(_) => Value::Null,
+        AttributeValue::Bool(b) => Value::Bool(*b),
+        AttributeValue::N(n) => Value::Number(parse_number(n)?),
+        AttributeValue::S(s) => Value::String(s.clone()),
+        AttributeValue::Ss(values) => {
+            Value::Array(values.iter().cloned().map(Value::String).collect())
+        }
+        AttributeValue::Ns(values) => Value::Array(
+            values
+                .iter()
+                .map(|n| parse_number(n).map(Value::Number))
+                .collect::<eyre::Result<_>>()?,
+        ),
+        AttributeValue::L(values) => Value::Array(
+            values
+                .iter()
+                .map(from_attribute_value)
+                .collect::<eyre::Result<_>>()?,
+        ),
+        AttributeValue::M(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), from_attribute_value(value)?)))
+                .collect::<eyre::Result<_>>()?,
+        ),
+        other => return Err(eyre::eyre!("Unsupported attribute value {:?}", other)),
+    })
+}
+
+fn parse_number(n: &str) -> eyre::Result<Number> {
+    Number::from_str(n).map_err(|e| eyre::eyre!("Invalid number {}: {}", n, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::Order;
+    use proptest::prelude::*;
+    use serde::Deserialize;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Nested {
+        label: String,
+        amounts: Vec<u64>,
+        prices: HashMap<String, f64>,
+        flag: bool,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Sample {
+        small: u8,
+        large: u64,
+        signed: i64,
+        float: f64,
+        text: String,
+        maybe_text: Option<String>,
+        maybe_float: Option<f64>,
+        nested: Nested,
+        maybe_nested: Option<Nested>,
+    }
+
+    fn finite_f64() -> impl Strategy<Value = f64> {
+        prop::num::f64::NORMAL | prop::num::f64::SUBNORMAL | prop::num::f64::ZERO
+    }
+
+    fn non_finite_f64() -> impl Strategy<Value = f64> {
+        prop_oneof![Just(f64::NAN), Just(f64::INFINITY), Just(f64::NEG_INFINITY)]
+    }
+
+    fn nested() -> impl Strategy<Value = Nested> {
+        (
+            ".*",
+            prop::collection::vec(any::<u64>(), 0..4),
+            prop::collection::hash_map("[a-z]{1,8}", finite_f64(), 0..4),
+            any::<bool>(),
+        )
+            .prop_map(|(label, amounts, prices, flag)| Nested {
+                label,
+                amounts,
+                prices,
+                flag,
+            })
+    }
+
+    fn sample() -> impl Strategy<Value = Sample> {
+        (
+            (any::<u8>(), any::<u64>(), any::<i64>(), finite_f64()),
+            (".*", prop::option::of(".*"), prop::option::of(finite_f64())),
+            (nested(), prop::option::of(nested())),
+        )
+            .prop_map(
+                |(
+                    (small, large, signed, float),
+                    (text, maybe_text, maybe_float),
+                    (nested, maybe_nested),
+                )| {
+                    Sample {
+                        small,
+                        large,
+                        signed,
+                        float,
+                        text,
+                        maybe_text,
+                        maybe_float,
+                        nested,
+                        maybe_nested,
+                    }
+                },
+            )
+    }
+
+    // every field of an Order, order_fields_are_all_generated keeps it that way
+    fn order_json() -> impl Strategy<Value = Value> {
+        (
+            (
+                "0x[0-9a-f]{112}",
+                "0x[0-9a-f]{40}",
+                "0x[0-9a-f]{40}",
+                "0x[0-9a-f]{40}",
+            ),
+            (
+                any::<u8>(),
+                any::<u8>(),
+                any::<u64>(),
+                any::<u64>(),
+                any::<u32>(),
+            ),
+            prop::collection::vec(finite_f64(), 19),
+            prop::collection::vec("[0-9]{1,78}", 7),
+        )
+            .prop_map(
+                |(
+                    (uid, owner, buy_token, sell_token),
+                    (buy_decimals, sell_decimals, block_number, timestamp, analysis_version),
+                    f,
+                    raw,
+                )| {
+                    json!({
+                        "uid": uid,
+                        "owner": owner,
+                        "buy_token": buy_token,
+                        "sell_token": sell_token,
+                        "buy_decimals": buy_decimals,
+                        "sell_decimals": sell_decimals,
+                        "min_buy": f[0],
+                        "sell": f[1],
+                        "executed_buy": f[2],
+                        "executed_sell": f[3],
+                        "min_buy_raw": raw[0],
+                        "sell_raw": raw[1],
+                        "executed_buy_raw": raw[2],
+                        "executed_sell_raw": raw[3],
+                        "net_surplus": f[4],
+                        "surplus_percentage": f[5],
+                        "zerox_quote_buy": f[6],
+                        "zerox_quote_buy_raw": raw[4],
+                        "compared_executed_with_zerox_quote": f[7],
+                        "compared_with_zerox_percentage": f[8],
+                        "cows_own_quote_buy": f[9],
+                        "cows_own_quote_buy_raw": raw[5],
+                        "compared_executed_with_cows_own_quote": f[10],
+                        "compared_with_cows_own_quote_percentage": f[11],
+                        "univ3_swap_buy": f[12],
+                        "univ3_swap_buy_raw": raw[6],
+                        "compared_executed_with_univ3_swap": f[13],
+                        "compared_with_univ3_swap_percentage": f[14],
+                        "sell_token_usd_price": f[15],
+                        "buy_token_usd_price": f[16],
+                        "sell_usd": f[17],
+                        "net_surplus_usd": f[18],
+                        "block_number": block_number,
+                        "timestamp": timestamp,
+                        "analysis_version": analysis_version,
+                    })
+                },
+            )
+    }
+
+    fn order() -> impl Strategy<Value = Order> {
+        order_json().prop_map(|json| serde_json::from_value(json).unwrap())
+    }
+
+    proptest! {
+        #[test]
+        fn sample_round_trips(sample in sample()) {
+            let item = to_item(&sample).unwrap();
+            prop_assert_eq!(from_item::<Sample>(&item).unwrap(), sample);
+        }
+
+        // Order reads missing fields as defaults, so one the strategy leaves out would round
+        // trip whether or not it is stored
+        #[test]
+        fn order_fields_are_all_generated(json in order_json()) {
+            let Value::Object(fields) = serde_json::to_value(Order::default()).unwrap() else {
+                unreachable!("an Order serializes to an object")
+            };
+            let generated = json.as_object().unwrap();
+            for name in fields.keys() {
+                prop_assert!(generated.contains_key(name), "{} is never generated", name);
+            }
+        }
+
+        #[test]
+        fn order_round_trips(order in order()) {
+            let item = to_item(&order).unwrap();
+            prop_assert_eq!(from_item::<Order>(&item).unwrap(), order);
+        }
+
+        #[test]
+        fn orders_with_finite_floats_pass_the_check(order in order()) {
+            prop_assert!(order.check_finite().is_ok());
+        }
+
+        #[test]
+        fn orders_with_non_finite_floats_are_rejected(
+            mut order in order(),
+            value in non_finite_f64(),
+        ) {
+            order.update_usd_valuation(value, 1.0, 1.0);
+            let error = order.check_finite().unwrap_err().to_string();
+            prop_assert!(error.contains("usd"), "{}", error);
+        }
+    }
+}
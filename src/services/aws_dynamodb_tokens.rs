@@ -0,0 +1,59 @@
+use crate::config::Config;
+use crate::metrics;
+use crate::services::{
+    aws_dynamodb::new_client,
+    aws_dynamodb_serde::{from_item, to_item},
+};
+use crate::tokens::TokenInfo;
+use aws_sdk_dynamodb::{types::AttributeValue, Client};
+
+// Token metadata keyed by lowercase address, so a token is only read from chain once
+#[derive(Clone)]
+pub struct TokenStore {
+    client: Client,
+    table_name: String,
+}
+
+impl TokenStore {
+    pub async fn new(config: &Config) -> eyre::Result<Self> {
+        Ok(Self {
+            client: new_client(config).await,
+            table_name: config.tokens_table().to_string(),
+        })
+    }
+
+    pub async fn get(&self, address: &str) -> eyre::Result<Option<TokenInfo>> {
+        let result = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .key("address", AttributeValue::S(address.to_string()))
+            .send()
+            .await?;
+
+        result.item.as_ref().map(from_item).transpose()
+    }
+
+    // replaces whatever is stored for the token
+    pub async fn put(&self, token: &TokenInfo) -> eyre::Result<()> {
+        let timer = metrics::DYNAMODB_DURATION
+            .with_label_values(&["put_token"])
+            .start_timer();
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(to_item(token)?))
+            .send()
+            .await;
+        timer.observe_duration();
+
+        if result.is_err() {
+            metrics::DYNAMODB_ERRORS
+                .with_label_values(&["put_token"])
+                .inc();
+        }
+        result?;
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use crate::config::Config;
+use aws_sdk_s3::{primitives::ByteStream, Client};
+
+// The bucket orders are archived to, any S3-compatible store works
+#[derive(Clone)]
+pub struct ArchiveBucket {
+    client: Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl ArchiveBucket {
+    pub async fn new(config: &Config) -> eyre::Result<Self> {
+        let bucket = config
+            .archive_bucket()
+            .clone()
+            .ok_or_else(|| eyre::eyre!("ARCHIVE_BUCKET must be set to archive orders"))?;
+        let sdk_config = aws_config::defaults(aws_config::BehaviorVersion::latest())
+            .load()
+            .await;
+
+        let client = match config.s3_endpoint_url() {
+            // MinIO and most other S3-compatible stores don't do virtual-hosted buckets
+            Some(endpoint_url) => Client::from_conf(
+                aws_sdk_s3::config::Builder::from(&sdk_config)
+                    .endpoint_url(endpoint_url)
+                    .force_path_style(true)
+                    .build(),
+            ),
+            None => Client::new(&sdk_config),
+        };
+
+        Ok(Self {
+            client,
+            bucket,
+            prefix: config.archive_prefix().clone(),
+        })
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    // `name` is relative to the archive prefix, the full key is returned
+    pub async fn put(&self, name: &str, body: Vec<u8>) -> eyre::Result<String> {
+        let key = format!("{}{}", self.prefix, name);
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .body(ByteStream::from(body))
+            .send()
+            .await?;
+
+        Ok(key)
+    }
+
+    pub async fn get(&self, key: &str) -> eyre::Result<Vec<u8>> {
+        let result = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await?;
+
+        Ok(result.body.collect().await?.into_bytes().to_vec())
+    }
+
+    // every key under the archive prefix, in key order
+    pub async fn keys(&self) -> eyre::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut token = None;
+
+        loop {
+            let result = self
+                .client
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(&self.prefix)
+                .set_continuation_token(token)
+                .send()
+                .await?;
+
+            keys.extend(
+                result
+                    .contents()
+                    .iter()
+                    .filter_map(|object| object.key().map(str::to_string)),
+            );
+
+            match result.next_continuation_token {
+                Some(next) => token = Some(next),
+                None => return Ok(keys),
+            }
+        }
+    }
+}
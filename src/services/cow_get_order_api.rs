@@ -1,5 +1,6 @@
+use crate::config::Config;
+use crate::services::http_client::ApiClient;
 use getset::Getters;
-use reqwest;
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize, Getters)]
@@ -26,18 +27,18 @@ impl CowGetResponse {
     }
 }
 
+// returns the raw response body, see parse_cow_order
 pub async fn cowswap_get_order(
-    client: &reqwest::Client,
+    config: &Config,
+    client: &ApiClient,
     order_uid: &str,
-) -> eyre::Result<(CowGetResponse, bool)> {
-    let url: String = format!("https://api.cow.fi/mainnet/api/v1/orders/{}", order_uid);
-    let response = client
-        .get(&url)
-        .send()
-        .await
-        .map_err(|e| eyre::eyre!("Failed to send request: {}", e))?
-        .json::<CowGetResponse>()
-        .await
+) -> eyre::Result<String> {
+    let url = format!("{}/api/v1/orders/{}", config.cow_api_url(), order_uid);
+    client.send(client.get(&url)).await
+}
+
+pub fn parse_cow_order(body: &str) -> eyre::Result<(CowGetResponse, bool)> {
+    let response = serde_json::from_str::<CowGetResponse>(body)
         .map_err(|e| eyre::eyre!("Failed to parse response into json: {}", e))?;
 
     // 1. 0x has only sell orders
@@ -45,3 +46,39 @@ pub async fn cowswap_get_order(
     let should_proceed = response.is_sell() && response.sell() == response.executed_sell();
     Ok((response, should_proceed))
 }
+
+// one on-chain settlement of an order, as listed by the CowSwap trades API
+#[derive(Debug, Deserialize, Getters)]
+#[serde(rename_all = "camelCase")]
+#[getset(get = "pub")]
+pub struct CowTrade {
+    block_number: u64,
+    log_index: u64,
+    order_uid: String,
+    owner: String,
+    sell_token: String,
+    buy_token: String,
+    sell_amount: String,
+    sell_amount_before_fees: String,
+    buy_amount: String,
+    tx_hash: Option<String>,
+}
+
+// returns the raw response body, see parse_cow_trades
+pub async fn cowswap_get_trades(
+    config: &Config,
+    client: &ApiClient,
+    order_uid: &str,
+) -> eyre::Result<String> {
+    let url = format!(
+        "{}/api/v1/trades?orderUid={}",
+        config.cow_api_url(),
+        order_uid
+    );
+    client.send(client.get(&url)).await
+}
+
+pub fn parse_cow_trades(body: &str) -> eyre::Result<Vec<CowTrade>> {
+    serde_json::from_str::<Vec<CowTrade>>(body)
+        .map_err(|e| eyre::eyre!("Failed to parse response into json: {}", e))
+}
@@ -0,0 +1,34 @@
+use crate::config::Config;
+use crate::services::http_client::ApiClient;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+struct NativePriceResponse {
+    // wei per smallest unit of the token
+    price: f64,
+}
+
+// returns the raw response body, see parse_native_price
+pub async fn cowswap_native_price(
+    config: &Config,
+    client: &ApiClient,
+    token: &str,
+) -> eyre::Result<String> {
+    let url = format!(
+        "{}/api/v1/token/{}/native_price",
+        config.cow_api_url(),
+        token
+    );
+    client.send(client.get(&url)).await
+}
+
+pub fn parse_native_price(body: &str) -> eyre::Result<f64> {
+    let response: NativePriceResponse = serde_json::from_str(body)
+        .map_err(|e| eyre::eyre!("Failed to parse response into json: {}", e))?;
+
+    if response.price > 0.0 && response.price.is_finite() {
+        Ok(response.price)
+    } else {
+        Err(eyre::eyre!("Native price unavailable"))
+    }
+}
@@ -1,5 +1,6 @@
+use crate::config::Config;
+use crate::services::http_client::ApiClient;
 use ethers::types::U256;
-use reqwest;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize)]
@@ -39,14 +40,16 @@ struct Quote {
     _others: (),
 }
 
+// returns the raw response body, see parse_cowswap_quote
 pub async fn cowswap_quote_buy(
-    client: &reqwest::Client,
+    config: &Config,
+    client: &ApiClient,
     owner: &str,
     sell_token: &str,
     buy_token: &str,
     sell: &str,
 ) -> eyre::Result<String> {
-    let url = "https://api.cow.fi/mainnet/api/v1/quote";
+    let url = format!("{}/api/v1/quote", config.cow_api_url());
 
     let quote_param = QuoteParam {
         sell_token: sell_token.to_string(),
@@ -65,14 +68,12 @@ pub async fn cowswap_quote_buy(
         sell_amount_before_fee: sell.to_string(),
     };
 
-    let response: CowPostResponse = client
-        .post(url)
-        .json(&quote_param)
-        .send()
-        .await
-        .map_err(|e| eyre::eyre!("Failed to send request: {}", e))?
-        .json()
-        .await
+    let request = client.post(url).json(&quote_param);
+    client.send(request).await
+}
+
+pub fn parse_cowswap_quote(body: &str, sell: &str) -> eyre::Result<String> {
+    let response: CowPostResponse = serde_json::from_str(body)
         .map_err(|e| eyre::eyre!("Failed to parse response into json: {}", e))?;
 
     // response.quote.sell & sell aren't always the same because of fees
@@ -0,0 +1,361 @@
+use crate::config::Config;
+use crate::metrics;
+use chrono::DateTime;
+use rand::Rng;
+use reqwest::{header, RequestBuilder, StatusCode};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// One connection pool and one rate limit per host for the whole process, however many
+// ApiClients there are
+static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(reqwest::Client::new);
+static HOSTS: LazyLock<Mutex<HashMap<String, HostState>>> = LazyLock::new(Default::default);
+
+// A token bucket holding up to a second worth of requests, and the pause a host asked for
+struct HostState {
+    tokens: f64,
+    updated: Instant,
+    paused_until: Option<Instant>,
+}
+
+// A non-success answer, with the body kept as it came and the error message in it, if any
+#[derive(Debug)]
+pub struct ApiError {
+    pub host: String,
+    pub status: StatusCode,
+    pub body: String,
+    pub message: Option<String>,
+}
+
+impl ApiError {
+    fn new(host: &str, status: StatusCode, body: String) -> Self {
+        Self {
+            host: host.to_string(),
+            status,
+            message: error_message(&body),
+            body,
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{} answered {}: {}", self.host, self.status, message),
+            None => write!(f, "{} answered {}", self.host, self.status),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+// CowSwap answers {"errorType", "description"} and 0x {"name", "message"}
+fn error_message(body: &str) -> Option<String> {
+    let json = serde_json::from_str::<Value>(body).ok()?;
+    let field = |keys: &[&str]| {
+        keys.iter()
+            .find_map(|key| json.get(key).and_then(Value::as_str))
+            .map(str::to_string)
+    };
+
+    match (
+        field(&["errorType", "name", "code"]),
+        field(&["description", "message", "reason", "error"]),
+    ) {
+        (Some(kind), Some(message)) => Some(format!("{}: {}", kind, message)),
+        (kind, message) => kind.or(message),
+    }
+}
+
+// The way out to the CowSwap and 0x APIs. Requests are held back to each host's rate limit,
+// and retried with exponential backoff and jitter when they time out, can't connect, or are
+// answered 429 or 5xx, waiting as long as Retry-After asks when it is given.
+#[derive(Clone)]
+pub struct ApiClient {
+    timeout: Duration,
+    max_retries: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    // requests per second, by host
+    rate_limits: Arc<HashMap<String, f64>>,
+}
+
+impl ApiClient {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            timeout: config.http_timeout(),
+            max_retries: config.http_max_retries(),
+            base_delay: config.http_retry_base_delay(),
+            max_delay: config.http_retry_max_delay(),
+            rate_limits: Arc::new(config.http_rate_limits().clone()),
+        }
+    }
+
+    pub fn get(&self, url: impl reqwest::IntoUrl) -> RequestBuilder {
+        CLIENT.get(url)
+    }
+
+    pub fn post(&self, url: impl reqwest::IntoUrl) -> RequestBuilder {
+        CLIENT.post(url)
+    }
+
+    // returns the body of the first success, or the last error, an ApiError when there was an
+    // answer
+    pub async fn send(&self, request: RequestBuilder) -> eyre::Result<String> {
+        let request = request
+            .timeout(self.timeout)
+            .build()
+            .map_err(|e| eyre::eyre!("Failed to build request: {}", e))?;
+        let host = request.url().host_str().unwrap_or_default().to_string();
+
+        for attempt in 0.. {
+            self.acquire(&host).await;
+            let attempt_request = request
+                .try_clone()
+                .ok_or_else(|| eyre::eyre!("Request to {} can't be retried", host))?;
+
+            let (error, reason, retry_after) = match CLIENT.execute(attempt_request).await {
+                Ok(response) if response.status().is_success() => {
+                    return response
+                        .text()
+                        .await
+                        .map_err(|e| eyre::eyre!("Failed to read response body: {}", e));
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let retry_after = retry_after(response.headers());
+                    let body = response.text().await.unwrap_or_default();
+                    let error = ApiError::new(&host, status, body);
+                    if !is_retryable(status) {
+                        return Err(error.into());
+                    }
+                    (
+                        eyre::Report::from(error),
+                        status.as_str().to_string(),
+                        retry_after,
+                    )
+                }
+                Err(e) if e.is_timeout() || e.is_connect() => {
+                    let reason = if e.is_timeout() { "timeout" } else { "connect" };
+                    (
+                        eyre::eyre!("Failed to send request: {}", e),
+                        reason.to_string(),
+                        None,
+                    )
+                }
+                Err(e) => return Err(eyre::eyre!("Failed to send request: {}", e)),
+            };
+
+            // a host asking for a longer wait than that is better given up on for this order
+            let delay = retry_after.unwrap_or_else(|| self.backoff(attempt));
+            if attempt >= self.max_retries || delay > self.max_delay {
+                return Err(error);
+            }
+            if let Some(retry_after) = retry_after {
+                pause(&host, retry_after);
+            }
+
+            metrics::HTTP_CLIENT_RETRIES
+                .with_label_values(&[&host, &reason])
+                .inc();
+            eprintln!(
+                "{}, retrying in {} ms ({} of {})",
+                error,
+                delay.as_millis(),
+                attempt + 1,
+                self.max_retries
+            );
+            tokio::time::sleep(delay).await;
+        }
+        unreachable!("the retry loop returns")
+    }
+
+    // doubles from the base delay up to the max, then drawn from its upper half so that
+    // concurrent retries spread out
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    // waits for a token of the host's bucket, hosts without a limit go right away
+    async fn acquire(&self, host: &str) {
+        let rate = self.rate_limits.get(host).copied();
+        loop {
+            let wait = {
+                let mut hosts = HOSTS.lock().unwrap();
+                let now = Instant::now();
+                let state = hosts.entry(host.to_string()).or_insert(HostState {
+                    tokens: rate.unwrap_or_default().max(1.0),
+                    updated: now,
+                    paused_until: None,
+                });
+
+                match (state.paused_until, rate) {
+                    (Some(until), _) if until > now => until - now,
+                    (_, None) => return,
+                    (_, Some(rate)) => {
+                        let capacity = rate.max(1.0);
+                        let elapsed = now.duration_since(state.updated).as_secs_f64();
+                        state.tokens = (state.tokens + elapsed * rate).min(capacity);
+                        state.updated = now;
+                        if state.tokens >= 1.0 {
+                            state.tokens -= 1.0;
+                            return;
+                        }
+                        Duration::from_secs_f64((1.0 - state.tokens) / rate)
+                    }
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+// holds back every request to the host, not only the one that was told to wait
+fn pause(host: &str, delay: Duration) {
+    let mut hosts = HOSTS.lock().unwrap();
+    let until = Instant::now() + delay;
+    if let Some(state) = hosts.get_mut(host) {
+        state.paused_until = Some(state.paused_until.map_or(until, |paused| paused.max(until)));
+    }
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+// either a number of seconds or an HTTP date
+fn retry_after(headers: &header::HeaderMap) -> Option<Duration> {
+    let value = headers.get(header::RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = DateTime::parse_from_rfc2822(value).ok()?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+    Some(Duration::from_secs((at.timestamp() - now).max(0) as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn headers(retry_after: &str) -> header::HeaderMap {
+        let mut headers = header::HeaderMap::new();
+        headers.insert(header::RETRY_AFTER, retry_after.parse().unwrap());
+        headers
+    }
+
+    fn client(base_delay: Duration, max_delay: Duration) -> ApiClient {
+        ApiClient {
+            timeout: Duration::from_secs(10),
+            max_retries: 3,
+            base_delay,
+            max_delay,
+            rate_limits: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn retry_after_in_seconds() {
+        assert_eq!(retry_after(&headers("120")), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(&headers(" 0 ")), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_as_http_date() {
+        let at = (Utc::now() + chrono::Duration::seconds(90))
+            .format("%a, %d %b %Y %H:%M:%S GMT")
+            .to_string();
+        let delay = retry_after(&headers(&at)).unwrap();
+        assert!(
+            (Duration::from_secs(88)..=Duration::from_secs(90)).contains(&delay),
+            "{:?}",
+            delay
+        );
+
+        // a date that has passed means right away
+        assert_eq!(
+            retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn retry_after_that_is_neither_is_ignored() {
+        assert_eq!(retry_after(&headers("soon")), None);
+        assert_eq!(retry_after(&headers("-5")), None);
+        assert_eq!(retry_after(&header::HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn error_message_of_cowswap() {
+        let body = r#"{"errorType":"NoLiquidity","description":"no route found"}"#;
+        assert_eq!(
+            error_message(body).as_deref(),
+            Some("NoLiquidity: no route found")
+        );
+    }
+
+    #[test]
+    fn error_message_of_0x() {
+        let body = r#"{"name":"INPUT_INVALID","message":"sellAmount is too small"}"#;
+        assert_eq!(
+            error_message(body).as_deref(),
+            Some("INPUT_INVALID: sellAmount is too small")
+        );
+        // the older API, with a numeric code
+        let body = r#"{"code":100,"reason":"Validation Failed"}"#;
+        assert_eq!(error_message(body).as_deref(), Some("Validation Failed"));
+    }
+
+    #[test]
+    fn error_message_of_anything_else() {
+        assert_eq!(
+            error_message(r#"{"error":"Unauthorized"}"#).as_deref(),
+            Some("Unauthorized")
+        );
+        assert_eq!(error_message(r#"{"status":500}"#), None);
+        assert_eq!(error_message("<html>Bad Gateway</html>"), None);
+        assert_eq!(error_message(""), None);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_max_with_jitter() {
+        let client = client(Duration::from_millis(100), Duration::from_secs(1));
+        for (attempt, full) in [(0, 100), (1, 200), (3, 800), (4, 1000), (10, 1000)] {
+            for _ in 0..20 {
+                let delay = client.backoff(attempt).as_millis();
+                assert!(
+                    (full / 2..=full).contains(&delay),
+                    "attempt {} waited {} ms",
+                    attempt,
+                    delay
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn backoff_does_not_overflow() {
+        let client = client(Duration::from_secs(1), Duration::from_secs(30));
+        for attempt in [31, 32, 64, u32::MAX] {
+            assert!(client.backoff(attempt) <= Duration::from_secs(30));
+        }
+    }
+
+    #[test]
+    fn only_throttling_and_server_errors_are_retried() {
+        assert!(is_retryable(StatusCode::TOO_MANY_REQUESTS));
+        assert!(is_retryable(StatusCode::BAD_GATEWAY));
+        assert!(!is_retryable(StatusCode::BAD_REQUEST));
+        assert!(!is_retryable(StatusCode::NOT_FOUND));
+    }
+}
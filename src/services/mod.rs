@@ -1,6 +1,13 @@
 pub mod aws_dynamodb;
+pub mod aws_dynamodb_admin;
+pub mod aws_dynamodb_raw_events;
+pub mod aws_dynamodb_serde;
+pub mod aws_dynamodb_tokens;
 pub mod aws_ec2;
+pub mod aws_s3;
 pub mod cow_get_order_api;
+pub mod cow_native_price_api;
 pub mod cow_post_quote_api;
+pub mod http_client;
 pub mod uni_fork_swap;
 pub mod zerox_get_quote_api;
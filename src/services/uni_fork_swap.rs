@@ -1,21 +1,21 @@
+use crate::config::Config;
 use crate::constant;
 use crate::contract::{
     ierc20::IERC20,
     ifactory::IFactory,
     swap_router::{ExactInputSingleParams, SwapRouter},
 };
-use crate::helper::EnvConfig;
+use crate::metrics;
 use ethers::{
     core::utils::{parse_ether, Anvil},
     providers::{Http, Provider},
     types::{Address, U256},
 };
 use std::sync::Arc;
-use std::time::Duration;
 use tokio::time::timeout;
 
 pub async fn uni_swap_buy(
-    config: &EnvConfig,
+    config: &Config,
     block_number: u64,
     owner: &str,
     sell_token: &str,
@@ -23,11 +23,13 @@ pub async fn uni_swap_buy(
     sell_amount: &str,
 ) -> eyre::Result<String> {
     let forked_block_number = block_number - 1;
+    let timer = metrics::ANVIL_SPAWN_DURATION.start_timer();
     let anvil = Anvil::new()
-        .fork(config.get_alchemy_http_url())
+        .fork(config.archive_rpc_endpoint().url())
         .chain_id(1_u64)
         .fork_block_number(forked_block_number)
         .spawn();
+    timer.observe_duration();
 
     let http_provider =
         Provider::<Http>::try_from(anvil.endpoint()).expect("Failed to create HTTP provider");
@@ -78,9 +80,7 @@ pub async fn uni_swap_buy(
     );
 
     let mut max_amount_out = U256::default();
-    let fee_tiers = [100, 500, 3000, 10000];
-
-    for fee in fee_tiers {
+    for &fee in config.univ3_fee_tiers() {
         match factory.get_pool(sell_token, buy_token, fee).call().await {
             Ok(pool_address) if pool_address != Address::zero() => (),
             _ => continue,
@@ -99,7 +99,7 @@ pub async fn uni_swap_buy(
             sqrt_price_limit_x96: U256::from(0),
         });
 
-        let time_out = Duration::from_secs(8);
+        let time_out = config.univ3_tx_timeout();
         // Try to execute the swap
         let pending_tx = match timeout(time_out, tx.send()).await {
             Ok(Ok(tx)) => tx,
@@ -1,16 +1,20 @@
-use crate::EnvConfig;
+use crate::services::http_client::ApiClient;
+use crate::Config;
 use reqwest::header::{HeaderMap, HeaderValue};
 use serde::Deserialize;
 use std::collections::HashMap;
 
+// the fee and route are only required to be there, nothing reads them yet
 #[derive(Debug, Deserialize)]
 struct ZeroXGetResponse {
     #[serde(rename = "buyAmount")]
     buy: String,
     #[serde(rename = "totalNetworkFee")]
+    #[allow(dead_code)]
     total_network_fee: String,
     #[serde(rename = "liquidityAvailable")]
     liquidity_available: bool,
+    #[allow(dead_code)]
     route: Route,
     #[serde(skip)]
     _others: (),
@@ -24,6 +28,7 @@ impl ZeroXGetResponse {
 
 #[derive(Debug, Deserialize)]
 struct Route {
+    #[allow(dead_code)]
     fills: Vec<Fill>,
     #[serde(skip)]
     _others: (),
@@ -31,14 +36,16 @@ struct Route {
 
 #[derive(Debug, Deserialize)]
 struct Fill {
+    #[allow(dead_code)]
     source: String,
     #[serde(skip)]
     _others: (),
 }
 
+// returns the raw response body, see parse_zerox_quote
 pub async fn zerox_quote_buy(
-    config: &EnvConfig,
-    client: &reqwest::Client,
+    config: &Config,
+    client: &ApiClient,
     chain_id: &str,
     taker_address: &str,
     sell_token: &str,
@@ -61,15 +68,15 @@ pub async fn zerox_quote_buy(
     );
     headers.insert("0x-version", HeaderValue::from_static("v2"));
 
-    let zerox_response: ZeroXGetResponse = client
-        .get("https://api.0x.org/swap/permit2/price?")
+    let request = client
+        .get(format!("{}/swap/permit2/price", config.zerox_api_url()))
         .headers(headers)
-        .query(&params)
-        .send()
-        .await
-        .map_err(|e| eyre::eyre!("Failed to send request: {}", e))?
-        .json()
-        .await
+        .query(&params);
+    client.send(request).await
+}
+
+pub fn parse_zerox_quote(body: &str) -> eyre::Result<String> {
+    let zerox_response: ZeroXGetResponse = serde_json::from_str(body)
         .map_err(|e| eyre::eyre!("Failed to parse response into json: {}", e))?;
 
     if zerox_response.is_invalid() {
@@ -0,0 +1,237 @@
+use crate::config::Config;
+use crate::constant;
+use crate::contract::ierc20::{get_token_decimals, get_token_name, get_token_symbol};
+use crate::order::Order;
+use crate::rpc::{Rpc, RpcProvider};
+use crate::services::{aws_dynamodb::Page, aws_dynamodb_tokens::TokenStore};
+use ethers::types::Address;
+use futures::future;
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Getters)]
+#[getset(get = "pub")]
+pub struct TokenInfo {
+    // lowercase
+    address: String,
+    // empty when the token doesn't implement it
+    symbol: String,
+    name: String,
+    decimals: u8,
+    // only known for tokens seeded from a token list
+    logo_uri: Option<String>,
+}
+
+impl TokenInfo {
+    // the sentinel CowSwap uses for native ETH, which has no contract to ask
+    fn native_eth() -> Self {
+        Self {
+            address: constant::WETH.to_string(),
+            symbol: "ETH".to_string(),
+            name: "Ether".to_string(),
+            decimals: 18,
+            logo_uri: None,
+        }
+    }
+}
+
+// A token list as published by Uniswap and others, see https://tokenlists.org
+#[derive(Debug, Deserialize)]
+struct TokenList {
+    tokens: Vec<TokenListEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TokenListEntry {
+    chain_id: u64,
+    address: String,
+    name: String,
+    symbol: String,
+    decimals: u8,
+    #[serde(rename = "logoURI")]
+    logo_uri: Option<String>,
+}
+
+// An order with the metadata of both its tokens, as the API returns it. A token that can't be
+// resolved is left out rather than failing the response.
+#[derive(Debug, Serialize, Getters)]
+#[getset(get = "pub")]
+pub struct OrderWithTokens {
+    #[serde(flatten)]
+    order: Order,
+    sell_token_info: Option<TokenInfo>,
+    buy_token_info: Option<TokenInfo>,
+}
+
+struct Inner {
+    cache: RwLock<HashMap<String, TokenInfo>>,
+    // None to leave the tokens table alone, and read every token from chain
+    store: Option<TokenStore>,
+    // token metadata is kept for good, so the quorum has to agree on it
+    provider: Arc<RpcProvider>,
+}
+
+// Resolves token metadata from memory, then the tokens table, then the chain, keeping what it
+// finds in the layers above
+#[derive(Clone)]
+pub struct TokenRegistry(Arc<Inner>);
+
+impl TokenRegistry {
+    pub async fn new(config: &Config) -> eyre::Result<Self> {
+        Ok(Self(Arc::new(Inner {
+            cache: RwLock::new(HashMap::new()),
+            store: Some(TokenStore::new(config).await?),
+            provider: Arc::new(Rpc::new(config).quorum_provider()),
+        })))
+    }
+
+    // for the command line analyses, which don't touch DynamoDB
+    pub fn chain_only(config: &Config) -> eyre::Result<Self> {
+        Ok(Self(Arc::new(Inner {
+            cache: RwLock::new(HashMap::new()),
+            store: None,
+            provider: Arc::new(Rpc::new(config).quorum_provider()),
+        })))
+    }
+
+    pub async fn resolve(&self, address: &str) -> eyre::Result<TokenInfo> {
+        let address = address.to_lowercase();
+        if let Some(token) = self.0.cache.read().unwrap().get(&address) {
+            return Ok(token.clone());
+        }
+
+        let token = if address == constant::WETH {
+            TokenInfo::native_eth()
+        } else if let Some(token) = self.stored(&address).await? {
+            token
+        } else {
+            let (token, complete) = self.fetch(&address).await?;
+            if !complete {
+                // asked again next time, rather than kept without its symbol or name for good
+                return Ok(token);
+            }
+            // the chain is asked again next time, nothing is lost
+            if let Some(store) = &self.0.store {
+                if let Err(e) = store.put(&token).await {
+                    eprintln!("Failed to store token {}: {}", address, e);
+                }
+            }
+            token
+        };
+
+        self.0.cache.write().unwrap().insert(address, token.clone());
+        Ok(token)
+    }
+
+    pub async fn with_tokens(&self, order: Order) -> OrderWithTokens {
+        let (sell_token_info, buy_token_info) = tokio::join!(
+            self.lookup(order.sell_token()),
+            self.lookup(order.buy_token())
+        );
+
+        OrderWithTokens {
+            order,
+            sell_token_info,
+            buy_token_info,
+        }
+    }
+
+    pub async fn page_with_tokens(&self, page: Page<Order>) -> Page<OrderWithTokens> {
+        Page {
+            items: future::join_all(page.items.into_iter().map(|order| self.with_tokens(order)))
+                .await,
+            next_cursor: page.next_cursor,
+        }
+    }
+
+    async fn lookup(&self, address: &str) -> Option<TokenInfo> {
+        match self.resolve(address).await {
+            Ok(token) => Some(token),
+            Err(e) => {
+                eprintln!("Failed to resolve token {}: {}", address, e);
+                None
+            }
+        }
+    }
+
+    async fn stored(&self, address: &str) -> eyre::Result<Option<TokenInfo>> {
+        match &self.0.store {
+            Some(store) => store.get(address).await,
+            None => Ok(None),
+        }
+    }
+
+    // the bool is false when the symbol or name couldn't be read this time
+    async fn fetch(&self, address: &str) -> eyre::Result<(TokenInfo, bool)> {
+        let provider = &self.0.provider;
+        let token = address.parse::<Address>()?;
+        let (decimals, symbol, name) = tokio::join!(
+            get_token_decimals(Arc::clone(provider), token, false),
+            get_token_symbol(Arc::clone(provider), token),
+            get_token_name(Arc::clone(provider), token),
+        );
+
+        // decimals are needed to read any amount, symbol and name are optional in ERC20
+        let decimals = decimals?;
+        let complete = symbol.is_ok() && name.is_ok();
+        let token = TokenInfo {
+            address: address.to_string(),
+            symbol: symbol.ok().flatten().unwrap_or_default(),
+            name: name.ok().flatten().unwrap_or_default(),
+            decimals,
+            logo_uri: None,
+        };
+        Ok((token, complete))
+    }
+}
+
+// Stores every mainnet token of a token list file, replacing what is stored for them. Amounts
+// are read with the stored decimals, so entries whose decimals the chain doesn't confirm are
+// skipped. Returns how many tokens were stored.
+pub async fn seed_tokens(config: &Config, path: &Path) -> eyre::Result<usize> {
+    let list: TokenList = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let store = TokenStore::new(config).await?;
+    let provider = Arc::new(Rpc::new(config).quorum_provider());
+
+    let mut seeded = 0;
+    for entry in list.tokens {
+        if entry.chain_id != 1 {
+            continue;
+        }
+
+        let address = entry.address.parse::<Address>()?;
+        match get_token_decimals(Arc::clone(&provider), address, false).await {
+            Ok(decimals) if decimals == entry.decimals => {}
+            Ok(decimals) => {
+                eprintln!(
+                    "Skipping {} ({}): the list says {} decimals, the chain {}",
+                    entry.symbol, entry.address, entry.decimals, decimals
+                );
+                continue;
+            }
+            Err(e) => {
+                eprintln!(
+                    "Skipping {} ({}): failed to read its decimals: {}",
+                    entry.symbol, entry.address, e
+                );
+                continue;
+            }
+        }
+
+        let token = TokenInfo {
+            address: entry.address.to_lowercase(),
+            symbol: entry.symbol,
+            name: entry.name,
+            decimals: entry.decimals,
+            logo_uri: entry.logo_uri,
+        };
+        store.put(&token).await?;
+        seeded += 1;
+    }
+
+    Ok(seeded)
+}